@@ -0,0 +1,121 @@
+// Compare the control algorithms on identical traffic.
+//
+//     cargo run --release --bin bench -- [building.conf] [traffic.conf] [faults.conf]
+//
+// Every algorithm is run against the same seeded stream of passengers on the
+// in-process simulator and the results are printed side by side.  With a
+// faults file, each run suffers the same hardware faults.
+
+use elevator::building::BuildingConfig;
+use elevator::faults::FaultPlan;
+use elevator::fcfs::FirstCome;
+use elevator::logic::{Algorithm, Elevator};
+use elevator::sim::{self, Stats};
+use elevator::states::{ElevatorAfterEvent, IdleElevator};
+use elevator::traffic::TrafficProfile;
+use std::process::exit;
+
+fn algorithms(building: &BuildingConfig) -> Vec<(&'static str, Box<dyn Algorithm>)> {
+    vec![
+        (
+            "logic",
+            Box::new(Elevator::with_settings(building.settings())),
+        ),
+        (
+            "states",
+            Box::new(ElevatorAfterEvent::Idle(IdleElevator::with_settings(
+                building.settings(),
+            ))),
+        ),
+        (
+            "first-come",
+            Box::new(FirstCome::with_settings(building.settings())),
+        ),
+    ]
+}
+
+fn print_row(name: &str, stats: &Stats, building: &BuildingConfig) {
+    let row = format!(
+        "{:<10} {:>9.1} {:>9.1} {:>12.1} {:>12.1} {:>6} {:>9} {:>10} {:>6}/{:<6} {}",
+        name,
+        building.seconds(stats.mean_wait()),
+        building.seconds(stats.max_wait as f64),
+        building.seconds(stats.mean_journey()),
+        building.seconds(stats.max_journey as f64),
+        stats.stops,
+        stats.distance,
+        stats.reversals,
+        stats.delivered,
+        stats.passengers,
+        problem(stats),
+    );
+    println!("{}", row.trim_end());
+}
+
+// The worst thing that went wrong, if anything did
+fn problem(stats: &Stats) -> String {
+    if let Some(violation) = stats.violations.first() {
+        format!("UNSAFE: {violation}")
+    } else if let Some(crash) = &stats.crash {
+        format!("CRASH: {crash}")
+    } else if let Some(failure) = stats.failure {
+        format!("OUT OF SERVICE: {failure:?} timed out")
+    } else {
+        String::new()
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let building = match args.get(1) {
+        Some(path) => BuildingConfig::load(path),
+        None => Ok(BuildingConfig::new()),
+    };
+    let traffic = match args.get(2) {
+        Some(path) => TrafficProfile::load(path),
+        None => Ok(TrafficProfile::new()),
+    };
+    let faults = match args.get(3) {
+        Some(path) => FaultPlan::load(path),
+        None => Ok(FaultPlan::new()),
+    };
+    let (building, traffic, faults) = match (building, traffic, faults) {
+        (Ok(building), Ok(traffic), Ok(faults)) => (building, traffic, faults),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            eprintln!("bench: {e}");
+            exit(1);
+        }
+    };
+
+    let passengers = traffic.generate(&building);
+    let limit = traffic.timeout * 1000 / building.tick_interval;
+
+    println!(
+        "{} passengers, {:?} traffic, seed {}\n",
+        passengers.len(),
+        traffic.pattern,
+        traffic.seed
+    );
+    println!(
+        "{:<10} {:>9} {:>9} {:>12} {:>12} {:>6} {:>9} {:>10} {:>13}",
+        "algorithm",
+        "wait(s)",
+        "max",
+        "journey(s)",
+        "max",
+        "stops",
+        "distance",
+        "reversals",
+        "delivered"
+    );
+    for (name, mut algorithm) in algorithms(&building) {
+        let stats = sim::run_with_faults(
+            algorithm.as_mut(),
+            &building,
+            &passengers,
+            limit,
+            faults.clone(),
+        );
+        print_row(name, &stats, &building);
+    }
+}
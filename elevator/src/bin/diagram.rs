@@ -0,0 +1,44 @@
+// Print the state machine's transitions, as found by exploring it (see
+// diagram.rs).
+//
+//     cargo run --release --bin diagram -- dot [building.conf] | dot -Tsvg > elevator.svg
+//     cargo run --release --bin diagram -- mermaid [building.conf]
+//     cargo run --release --bin diagram -- table [building.conf]
+//
+// The building's settings (express zones, rear doors, parking) decide which
+// transitions there are.
+
+use elevator::building::BuildingConfig;
+use elevator::diagram::{self, DEFAULT_DEPTH};
+use elevator::logic::Elevator;
+use std::process::exit;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (format, building) = match &args[..] {
+        [format, building @ ..] if building.len() <= 1 => (format, building.first()),
+        _ => usage(),
+    };
+    let building = match building {
+        Some(path) => BuildingConfig::load(path),
+        None => Ok(BuildingConfig::new()),
+    };
+    let building = building.unwrap_or_else(|e| {
+        eprintln!("diagram: {e}");
+        exit(1)
+    });
+
+    let edges = diagram::explore::<Elevator>(building.settings(), DEFAULT_DEPTH);
+    let output = match format.as_str() {
+        "dot" => diagram::dot(&edges),
+        "mermaid" => diagram::mermaid(&edges),
+        "table" => diagram::table(&edges),
+        _ => usage(),
+    };
+    print!("{output}");
+}
+
+fn usage() -> ! {
+    eprintln!("usage: diagram dot|mermaid|table [building.conf]");
+    exit(2)
+}
@@ -0,0 +1,93 @@
+// Reproduce a run of the controller from its journal, or from a capture of
+// its traffic with Lifty.
+//
+//     cargo run --bin replay -- elevator.journal
+//     cargo run --bin replay -- --capture lifty.capture [scale]
+//
+// The journalled events are fed back through the state machine, and the
+// commands it sends and the states it goes through are checked against the
+// journal.  A capture is played back to a controller driving the state
+// machine, as if it came from Lifty, with the timing scaled if asked (0.5
+// is twice as fast).  The controller starts up the way the real one does,
+// but without a snapshot to restore calls from.  Either way the first
+// difference is reported.  Like the controller, it runs the building in
+// config/building.conf if there is one.
+
+use elevator::building::BuildingConfig;
+use elevator::capture::{self, Playback};
+use elevator::controller::LiftyController;
+use elevator::journal;
+use elevator::logic::Algorithm;
+use elevator::states::{ElevatorAfterEvent, IdleElevator};
+use std::path::Path;
+use std::process::exit;
+
+const BUILDING: &str = "config/building.conf";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let building = if Path::new(BUILDING).exists() {
+        BuildingConfig::load(BUILDING).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        })
+    } else {
+        BuildingConfig::new()
+    };
+    let result = match &args[..] {
+        [flag, path, scale @ ..] if flag == "--capture" && scale.len() <= 1 => {
+            let scale = scale.first().map_or(Ok(1.0), |scale| {
+                scale.parse().map_err(|_| format!("bad scale ({scale})"))
+            });
+            scale.and_then(|scale| play_back(path, scale, &building))
+        }
+        [path] => journal::read(path).and_then(|entries| {
+            journal::replay(&entries, building.settings()).map_err(|e| format!("{path}: {e}"))
+        }),
+        _ => {
+            eprintln!("usage: replay <journal> | replay --capture <capture> [scale]");
+            exit(2)
+        }
+    };
+    match result {
+        Ok(events) => println!("{events} events replayed, no differences"),
+        Err(difference) => {
+            eprintln!("{difference}");
+            exit(1)
+        }
+    }
+}
+
+fn play_back(path: &str, scale: f64, building: &BuildingConfig) -> Result<usize, String> {
+    let datagrams = capture::read(path)?;
+    let (mut controller, mut hardware) =
+        LiftyController::take_over_with(Playback::scaled(datagrams, scale), building);
+    let resumed = hardware.as_ref().and_then(|hardware| {
+        ElevatorAfterEvent::resume(building.settings(), hardware)
+            .inspect_err(|e| eprintln!("Can't carry on with {e}, starting afresh"))
+            .ok()
+    });
+    if resumed.is_none() && hardware.take().is_some() {
+        controller.start_afresh();
+    }
+    let (command, mut elevator) = resumed.unwrap_or_else(|| {
+        let elevator = IdleElevator::with_settings(building.settings());
+        (None, ElevatorAfterEvent::Idle(elevator))
+    });
+    if let Some(command) = command {
+        controller.send(command);
+    }
+    let mut events = 0;
+    while let Some(event) = controller.receive() {
+        if let Some(command) = elevator.handle_at(event, controller.now()) {
+            controller.send(command);
+        }
+        events += 1;
+    }
+    let playback = controller.transport();
+    match playback.difference() {
+        Some(difference) => Err(format!("{path}: {difference}")),
+        None if !playback.finished() => Err(format!("{path}: stopped before the end")),
+        None => Ok(events),
+    }
+}
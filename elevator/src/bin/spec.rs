@@ -0,0 +1,40 @@
+// Print the elevator algorithm as a TLA+ spec, or a TLC config to check it
+// with (see tla.rs).
+//
+//     cargo run --bin spec -- tla [building.conf] > Elevator.tla
+//     cargo run --bin spec -- cfg > Elevator.cfg
+//     java -cp tla2tools.jar tlc2.TLC Elevator
+//
+// The floors served and the idle policy come from the building's settings.
+
+use elevator::building::BuildingConfig;
+use elevator::tla;
+use std::process::exit;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (format, building) = match &args[..] {
+        [format, building @ ..] if building.len() <= 1 => (format, building.first()),
+        _ => usage(),
+    };
+    let building = match building {
+        Some(path) => BuildingConfig::load(path),
+        None => Ok(BuildingConfig::new()),
+    };
+    let building = building.unwrap_or_else(|e| {
+        eprintln!("spec: {e}");
+        exit(1)
+    });
+
+    let output = match format.as_str() {
+        "tla" => tla::module(&building.settings()),
+        "cfg" => tla::config(),
+        _ => usage(),
+    };
+    print!("{output}");
+}
+
+fn usage() -> ! {
+    eprintln!("usage: spec tla|cfg [building.conf]");
+    exit(2)
+}
@@ -0,0 +1,187 @@
+use crate::config;
+use crate::logic::{
+    IdlePolicy, ServedFloors, Settings, DEFAULT_BYPASS_LOAD, DEFAULT_IDLE_DELAY, HIGHEST_FLOOR,
+    LOWEST_FLOOR, NUM_FLOORS,
+};
+use crate::motion::MotionProfile;
+use std::time::Duration;
+
+// Physical description of the building and its hardware.  The defaults match
+// the timing hard-wired into lifty/lifty.rs.
+#[derive(Debug, Clone)]
+pub struct BuildingConfig {
+    pub served: ServedFloors, // doors on each floor
+    pub physics: bool,        // use `motion` rather than ticks_per_floor
+    pub motion: MotionProfile,
+    pub ticks_per_floor: usize,
+    pub ticks_for_door: usize,
+    pub approach_ticks: usize,
+    pub tick_interval: u64,  // milliseconds
+    pub door_dwell: u64,     // milliseconds the doors stay open
+    pub double_press: u64,   // milliseconds between presses that cancel a car call
+    pub door_timeout: u64,   // milliseconds for the doors to open or close
+    pub travel_timeout: u64, // milliseconds for the car to reach the next floor
+    pub rated_load: u32,     // kilograms
+    pub bypass_load: u8,     // percent of rated load
+    pub idle_policy: IdlePolicy,
+    pub idle_delay: u64, // milliseconds idle before parking
+    pub anti_nuisance: bool,
+}
+
+impl BuildingConfig {
+    pub fn new() -> Self {
+        BuildingConfig {
+            served: ServedFloors::all(),
+            physics: false,
+            motion: MotionProfile::new(),
+            ticks_per_floor: 40,
+            ticks_for_door: 20,
+            approach_ticks: 10,
+            tick_interval: 100,
+            door_dwell: 3000,
+            double_press: 1000,
+            door_timeout: 10_000,
+            travel_timeout: 30_000,
+            rated_load: 630,
+            bypass_load: DEFAULT_BYPASS_LOAD,
+            idle_policy: IdlePolicy::StayPut,
+            idle_delay: DEFAULT_IDLE_DELAY.as_millis() as u64,
+            anti_nuisance: false,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut building = BuildingConfig::new();
+        let mut front: Vec<u8> = (LOWEST_FLOOR..=HIGHEST_FLOOR).collect();
+        let mut rear = Vec::new();
+        for (key, value) in config::read(path)? {
+            match key.as_str() {
+                "front" => front = floor_list(&key, &value)?,
+                "rear" => rear = floor_list(&key, &value)?,
+                "physics" => building.physics = config::value(&key, &value)?,
+                "rated_speed" => building.motion.rated_speed = positive(&key, &value)?,
+                "acceleration" => building.motion.acceleration = positive(&key, &value)?,
+                "jerk" => building.motion.jerk = positive(&key, &value)?,
+                "floor_heights" => {
+                    let heights = value
+                        .split(',')
+                        .map(|height| positive(&key, height.trim()))
+                        .collect::<Result<Vec<f64>, String>>()?;
+                    building.motion.floor_heights = heights.try_into().map_err(|_| {
+                        format!(
+                            "bad value for floor_heights ({value}): need {} heights",
+                            NUM_FLOORS - 1
+                        )
+                    })?;
+                }
+                "ticks_per_floor" => building.ticks_per_floor = config::value(&key, &value)?,
+                "ticks_for_door" => building.ticks_for_door = config::value(&key, &value)?,
+                "approach_ticks" => building.approach_ticks = config::value(&key, &value)?,
+                "tick_interval" => building.tick_interval = config::value(&key, &value)?,
+                "door_dwell" => building.door_dwell = config::value(&key, &value)?,
+                "double_press" => building.double_press = config::value(&key, &value)?,
+                "door_timeout" => building.door_timeout = config::value(&key, &value)?,
+                "travel_timeout" => building.travel_timeout = config::value(&key, &value)?,
+                "rated_load" => building.rated_load = config::value(&key, &value)?,
+                "bypass_load" => building.bypass_load = config::value(&key, &value)?,
+                "anti_nuisance" => building.anti_nuisance = config::value(&key, &value)?,
+                "idle_delay" => building.idle_delay = config::value(&key, &value)?,
+                "idle_policy" => {
+                    building.idle_policy = match value.as_str() {
+                        "stay" => IdlePolicy::StayPut,
+                        "lobby" => IdlePolicy::ReturnToLobby,
+                        "busiest" => IdlePolicy::ParkAtBusiestFloor,
+                        _ => return Err(format!("{path}: unknown idle policy {value}")),
+                    }
+                }
+                _ => return Err(format!("{path}: unknown setting {key}")),
+            }
+        }
+        building.served = ServedFloors::with_doors(&front, &rear);
+        if building.tick_interval == 0 {
+            return Err(format!("{path}: tick_interval must be more than 0"));
+        }
+        if building.rated_load == 0 {
+            return Err(format!("{path}: rated_load must be more than 0"));
+        }
+        if building.bypass_load > 100 {
+            return Err(format!("{path}: bypass_load is a percentage, 100 at most"));
+        }
+        if !building.physics && building.approach_ticks >= building.ticks_per_floor {
+            return Err(format!(
+                "{path}: approach_ticks must be less than ticks_per_floor"
+            ));
+        }
+        if !building.served.serves(LOWEST_FLOOR) || building.served.floors().count() < 2 {
+            return Err(format!(
+                "{path}: served floors must include the lobby and at least one other floor"
+            ));
+        }
+        Ok(building)
+    }
+
+    // Parameters for the elevator algorithm
+    pub fn settings(&self) -> Settings {
+        Settings {
+            served: self.served,
+            bypass_load: self.bypass_load,
+            idle_policy: self.idle_policy,
+            idle_delay: Duration::from_millis(self.idle_delay),
+            anti_nuisance: self.anti_nuisance,
+        }
+    }
+
+    // Convert a number of simulator ticks into seconds
+    pub fn seconds(&self, ticks: f64) -> f64 {
+        ticks * self.tick_interval as f64 / 1000.0
+    }
+}
+
+fn positive(key: &str, value: &str) -> Result<f64, String> {
+    match config::value(key, value)? {
+        number if number > 0.0 => Ok(number),
+        _ => Err(format!("bad value for {key} ({value}): must be positive")),
+    }
+}
+
+// Comma separated list of floors, e.g. "1, 2, 5".  May be empty.
+fn floor_list(key: &str, value: &str) -> Result<Vec<u8>, String> {
+    let mut floors = Vec::new();
+    for floor in value.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        let floor: u8 = config::value(key, floor)?;
+        if !(LOWEST_FLOOR..=HIGHEST_FLOOR).contains(&floor) {
+            return Err(format!("bad value for {key} ({value}): no floor {floor}"));
+        }
+        floors.push(floor);
+    }
+    Ok(floors)
+}
+
+impl Default for BuildingConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    fn load(text: &str) -> Result<BuildingConfig, String> {
+        let path = std::env::temp_dir().join(format!("building-{}.conf", std::process::id()));
+        fs::write(&path, text).unwrap();
+        let building = BuildingConfig::load(path.to_str().unwrap());
+        fs::remove_file(path).unwrap();
+        building
+    }
+
+    #[test]
+    fn rejects_impossible_settings() {
+        assert!(load("tick_interval = 50\n").is_ok());
+        assert!(load("tick_interval = 0\n").is_err());
+        assert!(load("rated_load = 0\n").is_err());
+        assert!(load("bypass_load = 100\n").is_ok());
+        assert!(load("bypass_load = 101\n").is_err());
+    }
+}
@@ -0,0 +1,274 @@
+// Captures of the raw traffic between the controller and Lifty, and a fake
+// Lifty that plays one back.  `Recording` wraps a transport and writes every
+// message both ways to a file, stamped with the transport's clock in
+// milliseconds:
+//
+//     0 > R
+//     1520 < P3
+//     1520 > MU
+//     4010 < A2
+//
+// `>` is a command sent to Lifty and `<` an event from it.  `Playback` hands
+// the events to a controller at the times they were recorded (or scaled),
+// and checks that the controller sends the same commands in the same order.
+// Its clock only moves while the controller waits, so a long session plays
+// back in no time.
+
+use crate::controller::Transport;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Datagram {
+    pub at: Duration,
+    pub sent: bool, // Sent to Lifty, rather than received from it
+    pub message: String,
+}
+
+impl Datagram {
+    pub fn parse(line: &str) -> Result<Datagram, String> {
+        let bad = || format!("bad datagram ({line})");
+        let mut fields = line.splitn(3, ' ');
+        let at = fields
+            .next()
+            .and_then(|at| at.parse().ok())
+            .ok_or_else(bad)?;
+        let sent = match fields.next() {
+            Some(">") => true,
+            Some("<") => false,
+            _ => return Err(bad()),
+        };
+        let message = fields.next().filter(|m| !m.is_empty()).ok_or_else(bad)?;
+        Ok(Datagram {
+            at: Duration::from_millis(at),
+            sent,
+            message: message.to_string(),
+        })
+    }
+
+    pub fn text(&self) -> String {
+        let direction = if self.sent { ">" } else { "<" };
+        format!("{} {direction} {}", self.at.as_millis(), self.message)
+    }
+}
+
+pub fn read(path: &str) -> Result<Vec<Datagram>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    text.lines()
+        .enumerate()
+        .map(|(n, line)| Datagram::parse(line).map_err(|e| format!("{path}: line {}: {e}", n + 1)))
+        .collect()
+}
+
+pub struct Recording<T: Transport> {
+    transport: T,
+    file: File,
+}
+
+impl<T: Transport> Recording<T> {
+    // Starts a new capture, replacing any earlier one at `path`
+    pub fn create(transport: T, path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{path}: {e}"))?;
+        Ok(Recording { transport, file })
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    fn record(&mut self, sent: bool, message: &str) {
+        let datagram = Datagram {
+            at: self.transport.now(),
+            sent,
+            message: message.to_string(),
+        };
+        // Losing the capture mustn't stop the car
+        if let Err(e) = writeln!(self.file, "{}", datagram.text()) {
+            eprintln!("capture: {e}");
+        }
+    }
+}
+
+impl<T: Transport> Transport for Recording<T> {
+    fn send(&mut self, message: &str) {
+        self.record(true, message);
+        self.transport.send(message);
+    }
+
+    fn receive(&mut self, timeout: Option<Duration>) -> Option<String> {
+        let message = self.transport.receive(timeout)?;
+        self.record(false, &message);
+        Some(message)
+    }
+
+    fn now(&self) -> Duration {
+        self.transport.now()
+    }
+}
+
+pub struct Playback {
+    datagrams: VecDeque<Datagram>,
+    scale: f64, // Recorded times are multiplied by this
+    clock: Duration,
+    difference: Option<String>, // The first command that wasn't as recorded
+}
+
+impl Playback {
+    pub fn new(datagrams: Vec<Datagram>) -> Self {
+        Playback::scaled(datagrams, 1.0)
+    }
+
+    // A scale of 0.5 plays the events back twice as fast
+    pub fn scaled(datagrams: Vec<Datagram>, scale: f64) -> Self {
+        Playback {
+            datagrams: datagrams.into(),
+            scale,
+            clock: Duration::ZERO,
+            difference: None,
+        }
+    }
+
+    pub fn difference(&self) -> Option<&str> {
+        self.difference.as_deref()
+    }
+
+    // Whether everything in the capture has been played back
+    pub fn finished(&self) -> bool {
+        self.datagrams.is_empty()
+    }
+
+    fn differ(&mut self, difference: String) {
+        self.difference.get_or_insert(difference);
+        self.datagrams.clear();
+    }
+}
+
+impl Transport for Playback {
+    fn send(&mut self, message: &str) {
+        let at = self.clock.as_millis();
+        match self.datagrams.front() {
+            Some(next) if next.sent && next.message == message => {
+                self.datagrams.pop_front();
+            }
+            Some(next) if next.sent => {
+                let expected = next.message.clone();
+                self.differ(format!("{at}: sent {message}, expected {expected}"));
+            }
+            Some(next) => {
+                let pending = next.message.clone();
+                self.differ(format!("{at}: sent {message} before receiving {pending}"));
+            }
+            // Past the end of the capture
+            None if self.difference.is_none() => self.differ(format!("{at}: sent {message}")),
+            None => {}
+        }
+    }
+
+    fn receive(&mut self, timeout: Option<Duration>) -> Option<String> {
+        let deadline = timeout.map(|timeout| self.clock + timeout);
+        match self.datagrams.front() {
+            Some(next) if !next.sent => {
+                let at = next.at.mul_f64(self.scale).max(self.clock);
+                if deadline.is_some_and(|deadline| at > deadline) {
+                    self.clock = deadline.unwrap();
+                    return None;
+                }
+                self.clock = at;
+                self.datagrams.pop_front().map(|datagram| datagram.message)
+            }
+            // Waiting for the controller to send something, which it will
+            // only do once its timeout is up
+            Some(_) => {
+                if let Some(deadline) = deadline {
+                    self.clock = deadline;
+                }
+                None
+            }
+            // The session is over
+            None => None,
+        }
+    }
+
+    fn now(&self) -> Duration {
+        self.clock
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::building::BuildingConfig;
+    use crate::controller::LiftyController;
+    use crate::logic::{Algorithm, Elevator};
+    use crate::sim::Simulation;
+    use crate::traffic::TrafficProfile;
+
+    fn run(controller: &mut LiftyController<impl Transport>) {
+        let mut elevator = Elevator::new();
+        while let Some(event) = controller.receive() {
+            if let Some(command) = elevator.handle_at(event, controller.now()) {
+                controller.send(command);
+            }
+        }
+    }
+
+    #[test]
+    fn datagrams() {
+        for line in ["0 > R", "1520 < P3", "20 < Q floor=1 motor=off"] {
+            assert_eq!(Datagram::parse(line).map(|d| d.text()), Ok(line.into()));
+        }
+        assert!(Datagram::parse("10 = MU").is_err());
+        assert!(Datagram::parse("10 >").is_err());
+        assert!(Datagram::parse("soon > MU").is_err());
+    }
+
+    #[test]
+    fn play_back_a_recorded_session() {
+        let path = std::env::temp_dir().join(format!("lifty-{}.capture", std::process::id()));
+        let path = path.to_str().unwrap();
+        let config = BuildingConfig::new();
+        let profile = TrafficProfile {
+            passengers: 20,
+            ..TrafficProfile::new()
+        };
+        let passengers = profile.generate(&config);
+        let simulation = Simulation::new(&config, &passengers, 1_000_000);
+        let mut controller =
+            LiftyController::with_transport(Recording::create(simulation, path).unwrap());
+        run(&mut controller);
+        assert_eq!(controller.transport().transport().stats().delivered, 20);
+        let datagrams = read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let mut controller = LiftyController::with_transport(Playback::new(datagrams.clone()));
+        run(&mut controller);
+        assert_eq!(controller.transport().difference(), None);
+        assert!(controller.transport().finished());
+
+        // A controller that does something else is caught out
+        let mut datagrams = datagrams;
+        let n = datagrams.iter().position(|d| d.message == "MU").unwrap();
+        datagrams[n].message = "MD".to_string();
+        let mut controller = LiftyController::with_transport(Playback::new(datagrams));
+        run(&mut controller);
+        let difference = controller.transport().difference().unwrap();
+        assert!(difference.ends_with("sent MU, expected MD"));
+    }
+
+    #[test]
+    fn scaled_timing() {
+        let datagrams = vec![
+            Datagram::parse("0 > R").unwrap(),
+            Datagram::parse("1000 < P3").unwrap(),
+        ];
+        let mut playback = Playback::scaled(datagrams, 0.5);
+        playback.send("R");
+        assert_eq!(playback.receive(Some(Duration::from_millis(400))), None);
+        assert_eq!(playback.now(), Duration::from_millis(400));
+        assert_eq!(playback.receive(None), Some("P3".to_string()));
+        assert_eq!(playback.now(), Duration::from_millis(500));
+        assert!(playback.finished());
+    }
+}
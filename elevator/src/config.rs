@@ -0,0 +1,39 @@
+// Reader for the small `key = value` files used to configure the elevator
+// tools.  Blank lines are skipped and anything after a '#' is a comment.
+//
+//     # building.conf
+//     ticks_per_floor = 40
+//     ticks_for_door  = 20
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+pub fn parse(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) => pairs.push((key.trim().to_string(), value.trim().to_string())),
+            None => return Err(format!("line {}: expected `key = value`", n + 1)),
+        }
+    }
+    Ok(pairs)
+}
+
+pub fn read(path: &str) -> Result<Vec<(String, String)>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    parse(&text).map_err(|e| format!("{path}: {e}"))
+}
+
+pub fn value<T>(key: &str, value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("bad value for {key} ({value}): {e}"))
+}
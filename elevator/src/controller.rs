@@ -1,5 +1,13 @@
-use crate::logic::{Command, Event, Indicator};
+use crate::building::BuildingConfig;
+use crate::logic::{
+    Activity, Command, Doors, Event, Failure, HardwareState, Indicator, Side, HIGHEST_FLOOR,
+    LOWEST_FLOOR, NUM_FLOORS,
+};
+use crate::metrics::Metrics;
+use std::collections::{BTreeSet, VecDeque};
+use std::io::ErrorKind;
 use std::net::UdpSocket;
+use std::time::{Duration, Instant};
 
 // Address at which the simulator receives commands
 const SIM_ADDRESS: &str = "127.0.0.1:10000";
@@ -7,137 +15,1031 @@ const SIM_ADDRESS: &str = "127.0.0.1:10000";
 // Address where the simulator sends events (that's me)
 const CONTROL_ADDRESS: &str = "127.0.0.1:11000";
 
+// How long the doors stay open before the controller closes them
+const DOOR_DWELL: Duration = Duration::from_secs(3);
+
+// A panel button pressed again within this window cancels the car call
+const DOUBLE_PRESS: Duration = Duration::from_secs(1);
+
+// A stop has to reach Lifty within this long of an approach event.  It only
+// honours S up to the end of the clock tick on which it sent An; after that
+// the car carries on to the next floor.  The window is timed from when An
+// came in, so the time both messages spend on the network comes off it.
+const STOP_WINDOW: Duration = Duration::from_millis(100);
+const STOP_LATENCY: Duration = Duration::from_millis(20);
+
+// How long the hardware gets to do what it was told before the controller
+// gives up on it: the doors to finish opening or closing, and the car to
+// reach the next floor (or to stop there).  A run between floors may cross
+// an express zone, so it gets longer.
+const DOOR_TIMEOUT: Duration = Duration::from_secs(10);
+const TRAVEL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How long Lifty gets to answer a status query
+const STATUS_TIMEOUT: Duration = Duration::from_secs(1);
+
+// How many times in a row the controller resets Lifty for the same failure
+// before giving up on it
+const RESET_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Motor {
+    Up,
+    Down,
+    Off,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Door {
+    Opening,
+    Open,
+    Closing,
+    Closed,
+}
+
+// What Lifty says about itself when asked (Q).  The answer is one line of
+// settings, e.g.
+//
+//     Q floor=3 motor=off door=open doors=front stopping=no indicator=up@3 lit=P5,RU3 crashed=no
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub floor: u8,
+    pub motor: Motor,
+    pub door: Door,
+    pub doors: Doors, // Sides last opened
+    pub stopping: bool,
+    pub indicator: Indicator,
+    pub indicator_floor: u8, // Where the indicator was last set
+    pub lit: Vec<String>,    // Lit buttons, named the way they're pressed ("P5", "RU3")
+    pub crashed: bool,
+}
+
+impl Status {
+    pub fn parse(reply: &str) -> Result<Status, String> {
+        let bad = || format!("bad status ({reply})");
+        let mut words = reply.split_whitespace();
+        if words.next() != Some("Q") {
+            return Err(bad());
+        }
+        let floor_number = |value: &str| match value.parse() {
+            Ok(floor) if (LOWEST_FLOOR..=HIGHEST_FLOOR).contains(&floor) => Ok(floor),
+            _ => Err(bad()),
+        };
+        let yes_no = |value: &str| match value {
+            "yes" => Ok(true),
+            "no" => Ok(false),
+            _ => Err(bad()),
+        };
+        let (mut floor, mut motor, mut door, mut doors) = (None, None, None, None);
+        let mut stopping = None;
+        let (mut indicator, mut lit, mut crashed) = (None, None, None);
+        for word in words {
+            let (key, value) = word.split_once('=').ok_or_else(bad)?;
+            match key {
+                "floor" => floor = Some(floor_number(value)?),
+                "motor" => {
+                    motor = Some(match value {
+                        "up" => Motor::Up,
+                        "down" => Motor::Down,
+                        "off" => Motor::Off,
+                        _ => return Err(bad()),
+                    })
+                }
+                "door" => {
+                    door = Some(match value {
+                        "opening" => Door::Opening,
+                        "open" => Door::Open,
+                        "closing" => Door::Closing,
+                        "closed" => Door::Closed,
+                        _ => return Err(bad()),
+                    })
+                }
+                "doors" => {
+                    doors = Some(match value {
+                        "front" => Doors::FRONT,
+                        "rear" => Doors::REAR,
+                        "both" => Doors::BOTH,
+                        _ => return Err(bad()),
+                    })
+                }
+                "stopping" => stopping = Some(yes_no(value)?),
+                "indicator" => {
+                    let (direction, floor) = value.split_once('@').ok_or_else(bad)?;
+                    let direction = match direction {
+                        "up" => Indicator::Up,
+                        "down" => Indicator::Down,
+                        "off" => Indicator::Off,
+                        _ => return Err(bad()),
+                    };
+                    indicator = Some((direction, floor_number(floor)?));
+                }
+                "lit" => {
+                    lit = Some(
+                        value
+                            .split(',')
+                            .filter(|button| !button.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    )
+                }
+                "crashed" => crashed = Some(yes_no(value)?),
+                _ => return Err(bad()),
+            }
+        }
+        let (indicator, indicator_floor) = indicator.ok_or_else(bad)?;
+        Ok(Status {
+            floor: floor.ok_or_else(bad)?,
+            motor: motor.ok_or_else(bad)?,
+            door: door.ok_or_else(bad)?,
+            doors: doors.ok_or_else(bad)?,
+            stopping: stopping.ok_or_else(bad)?,
+            indicator,
+            indicator_floor,
+            lit: lit.ok_or_else(bad)?,
+            crashed: crashed.ok_or_else(bad)?,
+        })
+    }
+
+    pub fn reply(&self) -> String {
+        let motor = match self.motor {
+            Motor::Up => "up",
+            Motor::Down => "down",
+            Motor::Off => "off",
+        };
+        let door = match self.door {
+            Door::Opening => "opening",
+            Door::Open => "open",
+            Door::Closing => "closing",
+            Door::Closed => "closed",
+        };
+        let doors = match (self.doors.front, self.doors.rear) {
+            (true, true) => "both",
+            (false, true) => "rear",
+            _ => "front",
+        };
+        let indicator = match self.indicator {
+            Indicator::Up => "up",
+            Indicator::Down => "down",
+            Indicator::Off => "off",
+        };
+        let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+        format!(
+            "Q floor={} motor={motor} door={door} doors={doors} stopping={} indicator={indicator}@{} lit={} crashed={}",
+            self.floor,
+            yes_no(self.stopping),
+            self.indicator_floor,
+            self.lit.join(","),
+            yes_no(self.crashed)
+        )
+    }
+
+    // Where a reset leaves Lifty
+    pub fn is_reset(&self) -> bool {
+        self.floor == LOWEST_FLOOR
+            && self.motor == Motor::Off
+            && self.door == Door::Closed
+            && self.lit.is_empty()
+            && !self.crashed
+    }
+
+    // What the elevator algorithm needs to know to carry on from here.  Lifty
+    // only knows which floor a moving car last left, not which it can still
+    // stop at, so for that wait for its next approach.
+    pub fn hardware_state(&self) -> HardwareState {
+        let activity = match (&self.motor, &self.door) {
+            (Motor::Up, _) => Activity::MovingUp,
+            (Motor::Down, _) => Activity::MovingDown,
+            (Motor::Off, Door::Closed) => Activity::Closed,
+            (Motor::Off, _) if self.indicator_floor == self.floor => {
+                Activity::Open(self.doors, self.indicator)
+            }
+            (Motor::Off, _) => Activity::Open(self.doors, Indicator::Off),
+        };
+        let calls = self
+            .lit
+            .iter()
+            .filter_map(|pressed| call(pressed))
+            .collect();
+        HardwareState {
+            floor: self.floor,
+            activity,
+            calls,
+        }
+    }
+}
+
+// The raw link to the elevator hardware.  Lifty speaks a small text protocol
+// over UDP, but anything that can carry the same messages (e.g. the in-process
+// simulator in sim.rs) can be plugged in instead of the socket.
+pub trait Transport {
+    fn send(&mut self, message: &str);
+
+    // Wait for the next message, giving up after `timeout` (if there is one).
+    // Returns None if nothing arrived.
+    fn receive(&mut self, timeout: Option<Duration>) -> Option<String>;
+
+    // Time elapsed since the transport was started
+    fn now(&self) -> Duration;
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    started: Instant,
+}
+
+impl UdpTransport {
+    pub fn new() -> UdpTransport {
+        UdpTransport {
+            socket: UdpSocket::bind(CONTROL_ADDRESS).expect("Couldn't bind to control address"),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Default for UdpTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, message: &str) {
+        self.socket
+            .send_to(message.as_bytes(), SIM_ADDRESS)
+            .unwrap();
+    }
+
+    fn receive(&mut self, timeout: Option<Duration>) -> Option<String> {
+        let deadline = timeout.map(|t| self.now() + t);
+        loop {
+            // A zero timeout would mean "block forever" to the socket.  The
+            // socket may also wake up a little early, so keep waiting until
+            // the deadline has really passed.
+            let timeout = deadline.map(|at| at.saturating_sub(self.now()));
+            if timeout.is_some_and(|t| t.is_zero()) {
+                return None;
+            }
+            self.socket.set_read_timeout(timeout).unwrap();
+
+            let mut buffer = [0; 2000];
+            match self.socket.recv_from(&mut buffer) {
+                // Bytes that aren't UTF-8 come out as U+FFFD, which nothing
+                // Lifty sends contains, so the message is taken as garbled
+                Ok((n, _)) => return Some(String::from_utf8_lossy(&buffer[0..n]).into_owned()),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => panic!("IO Error: {}", e),
+            }
+        }
+    }
+
+    fn now(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
 // Structure that abstracts details of the network simulator
 // out and provides a nicer high-level interface to what's happening.
 // Note: I'm calling this "LiftyController" to indicate that it is specifically
 // written for the simulator.   Maybe we want to run the elevator under a
 // MockController or some other kind of mechanism.
 
-pub struct LiftyController {
-    socket: UdpSocket,
-    stopping: bool,
+pub struct LiftyController<T: Transport = UdpTransport> {
+    transport: T,
+    stopping: Option<(u8, Indicator, Doors)>, // Stop under way, and the doors to open there
+    doors: Doors,                             // Doors used at the current stop
+    door_dwell: Duration,
+    doors_open: bool,
+    close_doors_at: Option<Duration>,
+    holding_doors: bool,
+    idle_timeout_at: Option<Duration>,
+    double_press: Duration,
+    panel_pressed: [[Option<Duration>; 2]; NUM_FLOORS as usize],
+    stop_window: Duration,
+    approached_at: Option<Duration>, // When the last approach event came in
+    overshot: Option<Event>,         // Missed stop, to report on the next receive
+    door_timeout: Duration,
+    travel_timeout: Duration,
+    watchdog: Option<(Duration, Failure)>, // When the hardware must next be heard from
+    resets: u32,                           // Resets in a row for `failed`
+    failed: Option<Failure>,               // What the last reset was for
+    lit: BTreeSet<String>,                 // Buttons Lifty has lit
+    answering: Vec<String>,                // Buttons put out by the stop under way
+    relit: Vec<String>,                    // Buttons pressed again after a reset
+    queued: VecDeque<String>,              // Came in earlier, yet to be decoded
+    held: Vec<String>,                     // Came in before the car stopped or carried on
+    metrics: Metrics,
+    metrics_file: Option<(String, Duration)>, // Where to save the metrics, and how often
+    save_metrics_at: Option<Duration>,
 }
 
-impl LiftyController {
+impl LiftyController<UdpTransport> {
     pub fn new() -> LiftyController {
-        let controller = LiftyController {
-            socket: UdpSocket::bind(CONTROL_ADDRESS).expect("Couldn't bind to control address"),
-            stopping: false,
-        };
+        LiftyController::with_transport(UdpTransport::new())
+    }
+}
 
-        controller._send("R");
+impl Default for LiftyController<UdpTransport> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+impl<T: Transport> LiftyController<T> {
+    pub fn with_transport(transport: T) -> LiftyController<T> {
+        let mut controller = LiftyController::connect(transport);
+        controller._send("R");
         controller
     }
 
+    // Take over from a controller that stopped mid-operation, without
+    // resetting Lifty, so that the car carries on from where it is.  Returns
+    // the state of the hardware for the elevator algorithm to resume from,
+    // or None if Lifty couldn't say, in which case it's reset as usual.
+    pub fn take_over(transport: T) -> (LiftyController<T>, Option<HardwareState>) {
+        LiftyController::take_over_with(transport, &BuildingConfig::new())
+    }
+
+    // The same, in a building with other timings than Lifty's own
+    pub fn take_over_with(
+        transport: T,
+        building: &BuildingConfig,
+    ) -> (LiftyController<T>, Option<HardwareState>) {
+        let mut controller = LiftyController::connect(transport);
+        controller.configure(building);
+        let hardware = controller.adopt();
+        if hardware.is_none() {
+            controller._send("R");
+        }
+        (controller, hardware)
+    }
+
+    // Give up on carrying on from where the last controller left off, if
+    // the elevator algorithm can't make sense of the hardware.  Lifty is
+    // reset, and the buttons that were lit are pressed again.
+    pub fn start_afresh(&mut self) {
+        self.watchdog = None;
+        self.close_doors_at = None;
+        self.queued.clear();
+        self.reset();
+        self.resets = 0;
+        self.press_waiting_buttons();
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    fn connect(transport: T) -> LiftyController<T> {
+        LiftyController {
+            transport,
+            stopping: None,
+            doors: Doors::FRONT,
+            door_dwell: DOOR_DWELL,
+            doors_open: false,
+            close_doors_at: None,
+            holding_doors: false,
+            idle_timeout_at: None,
+            double_press: DOUBLE_PRESS,
+            panel_pressed: [[None; 2]; NUM_FLOORS as usize],
+            stop_window: STOP_WINDOW,
+            approached_at: None,
+            overshot: None,
+            door_timeout: DOOR_TIMEOUT,
+            travel_timeout: TRAVEL_TIMEOUT,
+            watchdog: None,
+            resets: 0,
+            failed: None,
+            lit: BTreeSet::new(),
+            answering: Vec::new(),
+            relit: Vec::new(),
+            queued: VecDeque::new(),
+            held: Vec::new(),
+            metrics: Metrics::new(),
+            metrics_file: None,
+            save_metrics_at: None,
+        }
+    }
+
+    // Pick up the hardware as it is
+    fn adopt(&mut self) -> Option<HardwareState> {
+        let mut status = self.status().filter(|status| !status.crashed)?;
+        let mut heading = None;
+        if status.motor != Motor::Off {
+            // Wait for the car to reach a floor.  Either it approaches the
+            // next one, which is then where it's heading, or it stops where
+            // the last controller told it to.
+            let message = self.wait_for_floor()?;
+            if let Some(floor) = message.strip_prefix('A') {
+                heading = Some(floor.parse().ok()?);
+                self.queued.push_back(message);
+            } else {
+                status = self.status().filter(|status| !status.crashed)?;
+                if status.motor != Motor::Off {
+                    return None;
+                }
+            }
+        }
+        self.lit = status.lit.iter().cloned().collect();
+        self.doors = status.doors;
+        match status.door {
+            Door::Open => {
+                self.doors_open = true;
+                self.close_doors_at = Some(self.transport.now() + self.door_dwell);
+            }
+            Door::Opening | Door::Closing => self.expect(Failure::Doors),
+            // Lit for a stop that was never opened.  Otherwise it goes out
+            // when the doors close.
+            Door::Closed if status.indicator != Indicator::Off => {
+                self._send(&format!("CI{}", status.indicator_floor))
+            }
+            Door::Closed => {}
+        }
+        let mut hardware = status.hardware_state();
+        if let Some(floor) = heading {
+            hardware.floor = floor;
+        }
+        Some(hardware)
+    }
+
+    // The next approach (An) or stop (Sn) from a moving car.  Anything else
+    // that comes in meanwhile is kept for receive().
+    fn wait_for_floor(&mut self) -> Option<String> {
+        let deadline = self.transport.now() + self.travel_timeout;
+        loop {
+            let timeout = deadline.saturating_sub(self.transport.now());
+            if timeout.is_zero() {
+                return None;
+            }
+            match self._receive(Some(timeout))? {
+                message if message.starts_with('A') || message.starts_with('S') => {
+                    return Some(message)
+                }
+                message => self.queued.push_back(message),
+            }
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn now(&self) -> Duration {
+        self.transport.now()
+    }
+
+    // Take the timings of the car and the doors from the building
+    pub fn configure(&mut self, building: &BuildingConfig) {
+        self.set_door_dwell(Duration::from_millis(building.door_dwell));
+        self.set_double_press(Duration::from_millis(building.double_press));
+        self.set_stop_window(Duration::from_millis(building.tick_interval));
+        self.set_door_timeout(Duration::from_millis(building.door_timeout));
+        self.set_travel_timeout(Duration::from_millis(building.travel_timeout));
+    }
+
+    pub fn set_door_dwell(&mut self, dwell: Duration) {
+        self.door_dwell = dwell;
+    }
+
+    pub fn set_double_press(&mut self, window: Duration) {
+        self.double_press = window;
+    }
+
+    pub fn set_stop_window(&mut self, window: Duration) {
+        self.stop_window = window;
+    }
+
+    pub fn set_door_timeout(&mut self, timeout: Duration) {
+        self.door_timeout = timeout;
+    }
+
+    pub fn set_travel_timeout(&mut self, timeout: Duration) {
+        self.travel_timeout = timeout;
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    // Save the metrics to a file every so often (see metrics.rs)
+    pub fn save_metrics(&mut self, path: &str, every: Duration) {
+        self.metrics_file = Some((path.to_string(), every));
+        self.save_metrics_at = Some(self.transport.now() + every);
+    }
+
+    // Ask Lifty how it is.  Anything else that comes in meanwhile is kept
+    // for receive().  None if there's no sensible answer in time.
+    pub fn status(&mut self) -> Option<Status> {
+        self._send("Q");
+        let deadline = self.transport.now() + STATUS_TIMEOUT;
+        loop {
+            let timeout = deadline.saturating_sub(self.transport.now());
+            if timeout.is_zero() {
+                return None;
+            }
+            match self._receive(Some(timeout)) {
+                Some(reply) if reply.starts_with('Q') => return Status::parse(&reply).ok(),
+                Some(message) => self.queued.push_back(message),
+                None => return None,
+            }
+        }
+    }
+
     pub fn send(&mut self, command: Command) {
         match command {
-            Command::MoveUp => self._send("MU"),
-            Command::MoveDown => self._send("MD"),
+            Command::MoveUp => {
+                self._send("MU");
+                self.metrics.motor_started(true);
+                self.expect(Failure::Motion);
+            }
+            Command::MoveDown => {
+                self._send("MD");
+                self.metrics.motor_started(false);
+                self.expect(Failure::Motion);
+            }
 
-            Command::StopAndOpen { floor, direction } => {
-                self.stopping = true;
+            // Lifty would quietly ignore a stop that comes too late, so
+            // tell the algorithm the car is carrying on instead
+            Command::StopAndOpen { floor, .. }
+                if self.approached_at.is_some_and(|at| {
+                    self.transport.now() + STOP_LATENCY >= at + self.stop_window
+                }) =>
+            {
+                self.overshot = Some(Event::Overshot(floor));
+            }
+            Command::StopAndOpen {
+                floor,
+                direction,
+                doors,
+            } => {
+                self.stopping = Some((floor, direction, doors));
+                self.doors = doors;
                 self._send("S");
+                self.expect(Failure::Motion);
+                self.answer_calls(floor, direction);
+            }
 
-                self._send(&format!("CP{floor}"));
-
-                match direction {
-                    Indicator::Up => {
-                        self._send(&format!("IU{floor}"));
-                        self._send(&format!("CU{floor}"));
-                    }
-                    Indicator::Down => {
-                        self._send(&format!("ID{floor}"));
-                        self._send(&format!("CD{floor}"));
-                    }
-                    Indicator::Off => {}
-                }
+            Command::OpenDoor {
+                floor,
+                direction,
+                doors,
+            } => {
+                self.doors = doors;
+                self._send(open_command(doors));
+                self.expect(Failure::Doors);
+                self.answer_calls(floor, direction);
             }
 
-            Command::OpenDoor { floor, direction } => {
-                self._send("DO");
-                self._send(&format!("CP{floor}"));
+            Command::ChangeIndicator { floor, direction } => self.indicate(floor, direction),
 
-                match direction {
-                    Indicator::Up => {
-                        self._send(&format!("IU{floor}"));
-                        self._send(&format!("CU{floor}"));
-                    }
-                    Indicator::Down => {
-                        self._send(&format!("ID{floor}"));
-                        self._send(&format!("CD{floor}"));
-                    }
-                    Indicator::Off => {}
+            Command::RejectEvent(event) => match event {
+                Event::Panel(floor, side) | Event::PanelCancel(floor, side) => {
+                    self.clear(button(side, 'P', floor));
                 }
-            }
+                Event::Up(floor, side) => self.clear(button(side, 'U', floor)),
+                Event::Down(floor, side) => self.clear(button(side, 'D', floor)),
+                _ => (),
+            },
 
-            Command::ChangeIndicator { floor, direction } => match direction {
-                Indicator::Up => {
-                    self._send(&format!("IU{floor}"));
-                    self._send(&format!("CU{floor}"));
+            // Hold the doors open while the warning sounds
+            Command::Overload(true) => {
+                self.holding_doors = true;
+                self.close_doors_at = None;
+                self._send("OW");
+            }
+            Command::Overload(false) => {
+                self.holding_doors = false;
+                self._send("CW");
+                if self.doors_open {
+                    self.close_doors_at = Some(self.transport.now() + self.door_dwell);
                 }
-                Indicator::Down => {
-                    self._send(&format!("ID{floor}"));
-                    self._send(&format!("CD{floor}"));
+            }
+
+            Command::CancelCarCalls(calls) => {
+                for (floor, side) in calls {
+                    self.clear(button(side, 'P', floor));
                 }
-                Indicator::Off => {}
-            },
+            }
 
-            Command::RejectEvent(event) => match event {
-                Event::Panel(floor) => {
-                    self._send(&format!("CP{floor}"));
+            Command::Sequence(commands) => {
+                for command in commands {
+                    self.send(command);
                 }
-                Event::Up(floor) => {
-                    self._send(&format!("CU{floor}"));
+            }
+
+            // Only one idle timer runs at a time; starting it again resets it
+            Command::StartIdleTimer(delay) => {
+                self.idle_timeout_at = Some(self.transport.now() + delay);
+            }
+        }
+    }
+
+    // Opening the doors answers the car calls on those sides, and the hall
+    // calls in the direction shown on the indicator.
+    fn answer_calls(&mut self, floor: u8, direction: Indicator) {
+        for side in self.doors.sides() {
+            self.answer(button(side, 'P', floor));
+        }
+        self.indicate(floor, direction);
+    }
+
+    fn indicate(&mut self, floor: u8, direction: Indicator) {
+        let (light, hall) = match direction {
+            Indicator::Up => ("IU", 'U'),
+            Indicator::Down => ("ID", 'D'),
+            Indicator::Off => return,
+        };
+        self._send(&format!("{light}{floor}"));
+        for side in self.doors.sides() {
+            self.answer(button(side, hall, floor));
+        }
+    }
+
+    fn clear(&mut self, button: String) {
+        self._send(&format!("C{button}"));
+        self.metrics.dropped(&button);
+        self.lit.remove(&button);
+    }
+
+    // Put out the light of a call that the stop under way answers.  Until
+    // the doors have opened it isn't really answered, though: if Lifty has
+    // to be reset first the call still stands.
+    fn answer(&mut self, button: String) {
+        if self.lit.contains(&button) {
+            self.answering.push(button.clone());
+        }
+        self.metrics.answered(&button, self.transport.now());
+        self.clear(button);
+    }
+
+    pub fn receive(&mut self) -> Option<Event> {
+        if let Some(event) = self.overshot.take() {
+            return Some(event);
+        }
+        loop {
+            if let Some(message) = self.queued.pop_front() {
+                if let Some(event) = self.decode(&message) {
+                    return Some(event);
                 }
-                Event::Down(floor) => {
-                    self._send(&format!("CD{floor}"));
+                continue;
+            }
+            let now = self.transport.now();
+
+            // Close the doors once they've been open long enough
+            if self.close_doors_at.is_some_and(|at| at <= now) {
+                self.close_doors();
+                continue;
+            }
+            if self.idle_timeout_at.is_some_and(|at| at <= now) {
+                self.idle_timeout_at = None;
+                return Some(Event::IdleTimeout);
+            }
+            if let Some((_, failure)) = self.watchdog.filter(|&(at, _)| at <= now) {
+                return Some(self.recover(failure));
+            }
+            if self.save_metrics_at.is_some_and(|at| at <= now) {
+                self.write_metrics();
+                continue;
+            }
+
+            // Wait for a message, but no longer than the next timer
+            let deadline = self
+                .close_doors_at
+                .into_iter()
+                .chain(self.idle_timeout_at)
+                .chain(self.watchdog.map(|(at, _)| at))
+                .chain(self.save_metrics_at)
+                .min();
+            match self._receive(deadline.map(|at| at - now)) {
+                Some(message) => {
+                    if let Some(event) = self.decode(&message) {
+                        return Some(event);
+                    }
                 }
-                _ => (),
-            },
+                // A timer went off; deal with it on the next pass
+                None if deadline.is_some_and(|at| at <= self.transport.now()) => {}
+                None => return None,
+            }
+        }
+    }
+
+    fn write_metrics(&mut self) {
+        let Some((path, every)) = &self.metrics_file else {
+            return;
+        };
+        // Losing the metrics mustn't stop the car
+        if let Err(e) = self.metrics.save(path) {
+            eprintln!("{e}");
+        }
+        self.save_metrics_at = Some(self.transport.now() + *every);
+    }
+
+    fn close_doors(&mut self) {
+        self.close_doors_at = None;
+        self.doors_open = false;
+        self._send("DC");
+        self.expect(Failure::Doors);
+    }
+
+    // Start waiting for the hardware to answer the command just sent.  There's
+    // only ever one thing to wait for, since Lifty does one thing at a time.
+    fn expect(&mut self, failure: Failure) {
+        let timeout = match failure {
+            Failure::Doors => self.door_timeout,
+            Failure::Motion => self.travel_timeout,
+        };
+        self.watchdog = Some((self.transport.now() + timeout, failure));
+    }
+
+    // The hardware did what it was told.  If that's what it failed to do
+    // before, the reset fixed it.
+    fn answered(&mut self, response: Failure) {
+        self.watchdog = None;
+        if self.failed == Some(response) {
+            self.failed = None;
+            self.resets = 0;
+        }
+    }
+
+    // The hardware didn't answer in time.  Lifty may have crashed, which
+    // only a reset gets it out of, so try that.  If resetting doesn't help,
+    // whatever it does later on isn't to be trusted: the car is out of
+    // service until somebody has looked at it.
+    fn recover(&mut self, failure: Failure) -> Event {
+        self.watchdog = None;
+        self.stopping = None;
+        self.close_doors_at = None;
+        if self.failed != Some(failure) {
+            self.failed = Some(failure);
+            self.resets = 0;
         }
+        let mut event = Event::Fault(failure);
+        while self.resets < RESET_ATTEMPTS {
+            self.reset();
+            // Make sure the reset took.  No answer counts as a failed attempt:
+            // once the car is idle nothing is waiting on Lifty, so if it had
+            // died nobody would ever find out.
+            if self.status().is_some_and(|status| status.is_reset()) {
+                self.press_waiting_buttons();
+                event = Event::Reset;
+                break;
+            }
+        }
+        // What Lifty said about the car or the doors may have been from
+        // before the reset, which the algorithm mustn't hear after it.  A
+        // button pressed is still a call either way.
+        self.queued.retain(|message| call(message).is_some());
+        self.held.retain(|message| call(message).is_some());
+        event
+    }
+
+    // Reset Lifty and start again from the state that leaves it in: car on
+    // the lowest floor, doors closed and every light off
+    fn reset(&mut self) {
+        self._send("R");
+        self.resets += 1;
+        self.metrics.reset();
+        self.doors = Doors::FRONT;
+        self.doors_open = false;
+        self.holding_doors = false;
+        self.idle_timeout_at = None;
+        self.panel_pressed = [[None; 2]; NUM_FLOORS as usize];
+        self.approached_at = None;
+        self.overshot = None;
     }
 
-    pub fn receive(&mut self) -> Event {
-        let event = self._receive();
-        let floor = event[event.len() - 1..event.len()].parse().unwrap();
+    // Press the buttons of the calls that were waiting before a reset, so
+    // that they're lit and reported again
+    fn press_waiting_buttons(&mut self) {
+        let mut waiting = std::mem::take(&mut self.lit);
+        waiting.extend(self.answering.drain(..));
+        waiting.extend(
+            self.held
+                .drain(..)
+                .filter(|message| call(message).is_some()),
+        );
+        self.relit.clear();
+        for button in waiting {
+            self.press_again(button);
+        }
+    }
+
+    // Make calls that the hardware has lost, e.g. ones saved by a controller
+    // that died, by pressing their buttons.  Buttons that are still lit have
+    // been reported already.
+    pub fn press(&mut self, calls: &[Event]) {
+        for button in calls.iter().filter_map(|&call| call_button(call)) {
+            if !self.lit.contains(&button) {
+                self.press_again(button);
+            }
+        }
+    }
+
+    // Messages held back while the car was stopping go first
+    fn release_held(&mut self) {
+        for message in self.held.drain(..).rev() {
+            self.queued.push_front(message);
+        }
+    }
+
+    fn press_again(&mut self, button: String) {
+        self._send(&button);
+        if button.trim_start_matches('R').starts_with('P') {
+            self.relit.push(button);
+        }
+    }
+
+    // Turn a message from Lifty into an event for the elevator algorithm.
+    // Messages that the controller deals with on its own produce no event.
+    fn decode(&mut self, message: &str) -> Option<Event> {
+        // Until the car has either stopped or carried on, everything else
+        // waits, so that a missed stop is reported straight after it
+        if self.stopping.is_some() && !message.starts_with(['A', 'S']) {
+            self.held.push(message.to_string());
+            return None;
+        }
+        if let Some(load) = message.strip_prefix('L') {
+            return load
+                .parse()
+                .ok()
+                .map(Event::Load)
+                .or_else(|| garbled(message));
+        }
+        if let Some(id) = message.strip_prefix('K') {
+            return id
+                .parse()
+                .ok()
+                .map(Event::Credential)
+                .or_else(|| garbled(message));
+        }
+        // A status reply that nobody is waiting for any more
+        if message.starts_with('Q') {
+            return None;
+        }
+
+        // Buttons on the rear side are reported as RPn, RUn and RDn
+        let pressed = message;
+        let (side, message) = match message.strip_prefix('R') {
+            Some(message) => (Side::Rear, message),
+            None => (Side::Front, message),
+        };
+        let Some((kind, floor)) = message
+            .len()
+            .checked_sub(1)
+            .and_then(|last| Some((message.get(..last)?, message.get(last..)?)))
+            .and_then(|(kind, floor)| Some((kind, floor.parse().ok()?)))
+            .filter(|(_, floor)| (LOWEST_FLOOR..=HIGHEST_FLOOR).contains(floor))
+        else {
+            return garbled(pressed);
+        };
 
-        match &event[0..event.len() - 1] {
-            "P" => Event::Panel(floor),
-            "U" => Event::Up(floor),
-            "D" => Event::Down(floor),
+        // Lifty lights a button whenever it's pressed
+        if matches!(kind, "P" | "U" | "D") {
+            self.lit.insert(pressed.to_string());
+            self.metrics.called(pressed, self.transport.now());
+        }
+        match kind {
+            // Pressed by the controller after a reset, so not a double press
+            "P" if self.pressed_again(pressed) => Some(Event::Panel(floor, side)),
+            "P" => Some(self.panel_press(floor, side)),
+            "U" => Some(Event::Up(floor, side)),
+            "D" => Some(Event::Down(floor, side)),
             "C" => {
+                self.answered(Failure::Doors);
+                self.answering.clear();
                 self._send(&format!("CI{floor}"));
-                Event::Closed(floor)
+                Some(Event::Closed(floor))
             }
             "A" => {
-                if self.stopping {
-                    self.stopping = false;
+                // The stop got to Lifty too late after all, and the car
+                // carried on.  The calls it was to answer are still waiting,
+                // and the approach is passed on once the algorithm knows.
+                if let Some((missed, direction, _)) = self.stopping.take() {
+                    if direction != Indicator::Off {
+                        self._send(&format!("CI{missed}"));
+                    }
+                    for button in std::mem::take(&mut self.answering) {
+                        self.press_again(button);
+                    }
+                    self.release_held();
+                    self.queued.push_front(format!("A{floor}"));
+                    return Some(Event::Overshot(missed));
                 }
-                Event::Arrived(floor)
+                self.approached_at = Some(self.transport.now());
+                self.answered(Failure::Motion);
+                self.expect(Failure::Motion);
+                Some(Event::Arrived(floor))
             }
             "S" => {
-                if self.stopping {
-                    self.stopping = false;
-                    self._send("DO");
+                self.answered(Failure::Motion);
+                self.metrics.stopped(floor);
+                if let Some((_, _, doors)) = self.stopping.take() {
+                    self._send(open_command(doors));
+                    self.expect(Failure::Doors);
+                    self.release_held();
                 }
-                self.receive()
+                None
+            }
+            "O" => {
+                // Doors are fully open.  Let the passengers through before
+                // closing; Lifty reports Cn once it's safe to move again.
+                self.answered(Failure::Doors);
+                self.answering.clear();
+                self.metrics.doors_opened();
+                self.doors_open = true;
+                if !self.holding_doors {
+                    self.close_doors_at = Some(self.transport.now() + self.door_dwell);
+                }
+                None
+            }
+            _ => garbled(pressed),
+        }
+    }
+
+    fn pressed_again(&mut self, button: &str) -> bool {
+        match self.relit.iter().position(|b| b == button) {
+            Some(n) => {
+                self.relit.remove(n);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Lifty reports every press of a panel button, lit or not.  Two presses
+    // close together are a request to cancel the call.
+    fn panel_press(&mut self, floor: u8, side: Side) -> Event {
+        let now = self.transport.now();
+        let pressed = &mut self.panel_pressed[(floor - LOWEST_FLOOR) as usize][side as usize];
+        match pressed.replace(now) {
+            Some(last) if now - last <= self.double_press => {
+                *pressed = None;
+                Event::PanelCancel(floor, side)
             }
-            "O" => self.receive(),
-            _ => panic!("Unknown event"),
+            _ => Event::Panel(floor, side),
         }
     }
 
     // Low-level send/receive of raw Lifty commands (private)
-    fn _send(&self, command: &str) {
-        self.socket
-            .send_to(command.as_bytes(), SIM_ADDRESS)
-            .unwrap();
+    fn _send(&mut self, command: &str) {
+        self.transport.send(command);
     }
 
-    fn _receive(&mut self) -> String {
-        let mut buffer = [0; 2000];
-        let (n, _) = self.socket.recv_from(&mut buffer).unwrap();
-        String::from_utf8(buffer[0..n].to_vec()).unwrap()
+    fn _receive(&mut self, timeout: Option<Duration>) -> Option<String> {
+        self.transport.receive(timeout)
+    }
+}
+
+// Lifty's name for a button, e.g. "U3" or "RU3" for the rear up button
+fn button(side: Side, kind: char, floor: u8) -> String {
+    match side {
+        Side::Front => format!("{kind}{floor}"),
+        Side::Rear => format!("R{kind}{floor}"),
+    }
+}
+
+// The button that makes a call, e.g. "RU3" for Up(3, Rear)
+pub fn call_button(call: Event) -> Option<String> {
+    match call {
+        Event::Panel(floor, side) => Some(button(side, 'P', floor)),
+        Event::Up(floor, side) => Some(button(side, 'U', floor)),
+        Event::Down(floor, side) => Some(button(side, 'D', floor)),
+        _ => None,
+    }
+}
+
+// Lifty never sends anything like this, so it was mangled on the way.
+// There's no telling what it was, so it's dropped.
+fn garbled(message: &str) -> Option<Event> {
+    eprintln!("Garbled message from Lifty: {message:?}");
+    None
+}
+
+// The call a button makes
+pub fn call(pressed: &str) -> Option<Event> {
+    let (side, button) = match pressed.strip_prefix('R') {
+        Some(button) => (Side::Rear, button),
+        None => (Side::Front, pressed),
+    };
+    let floor = button.get(1..)?.parse().ok()?;
+    if !(LOWEST_FLOOR..=HIGHEST_FLOOR).contains(&floor) {
+        return None;
+    }
+    match button.chars().next()? {
+        'P' => Some(Event::Panel(floor, side)),
+        'U' => Some(Event::Up(floor, side)),
+        'D' => Some(Event::Down(floor, side)),
+        _ => None,
+    }
+}
+
+fn open_command(doors: Doors) -> &'static str {
+    match (doors.front, doors.rear) {
+        (true, true) => "DOB",
+        (false, true) => "DOR",
+        _ => "DO",
     }
 }
@@ -0,0 +1,344 @@
+// The state machine as a table of transitions, and as diagrams for design
+// reviews.  The transitions aren't written down anywhere but in the code, so
+// they're found by running it: starting from an idle car, every event the
+// hardware could report next is tried in every state reached, breadth
+// first, up to some number of events.  What's kept is the shape of each
+// transition, without the floors and calls:
+//
+//     idle Panel MoveUp moving-up
+//     moving-up Arrived StopAndOpen open-up
+//     open-up Closed - idle
+//
+// The table comes out the same for either implementation of the algorithm
+// (logic.rs or states.rs), and can be drawn with Graphviz or Mermaid:
+//
+//     cargo run --bin diagram -- dot | dot -Tsvg > elevator.svg
+
+use crate::logic::{Algorithm, Command, Elevator, Event, Failure, IdlePolicy, Settings, Side};
+use crate::logic::{HIGHEST_FLOOR, LOWEST_FLOOR, RATED_LOAD};
+use crate::snapshot::Snapshot;
+use crate::states::{ElevatorAfterEvent, IdleElevator};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Debug;
+
+// How many events deep to look by default.  With the settings tried so far,
+// no new transitions turn up after five or six.
+pub const DEFAULT_DEPTH: usize = 6;
+
+// What exploring needs of an implementation of the algorithm
+pub trait Machine: Algorithm + Clone + Debug {
+    fn with_settings(settings: Settings) -> Self;
+    fn snapshot(&self) -> Snapshot;
+    fn produce_future_event(&self) -> Option<Event>;
+}
+
+impl Machine for Elevator {
+    fn with_settings(settings: Settings) -> Self {
+        Elevator::with_settings(settings)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Elevator::snapshot(self)
+    }
+
+    fn produce_future_event(&self) -> Option<Event> {
+        Elevator::produce_future_event(self)
+    }
+}
+
+impl Machine for ElevatorAfterEvent {
+    fn with_settings(settings: Settings) -> Self {
+        ElevatorAfterEvent::Idle(IdleElevator::with_settings(settings))
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        ElevatorAfterEvent::snapshot(self)
+    }
+
+    fn produce_future_event(&self) -> Option<Event> {
+        ElevatorAfterEvent::produce_future_event(self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub from: String, // States as named in snapshots
+    pub event: String,
+    pub command: Option<String>, // e.g. "CancelCarCalls+MoveUp" for a sequence
+    pub to: String,
+}
+
+impl Edge {
+    fn label(&self) -> String {
+        match &self.command {
+            Some(command) => format!("{} / {command}", self.event),
+            None => self.event.clone(),
+        }
+    }
+}
+
+// The name of an enum variant, from its Debug form
+fn variant(debug: String) -> String {
+    let end = debug.find(['(', ' ']).unwrap_or(debug.len());
+    debug[..end].to_string()
+}
+
+fn command_name(command: &Command) -> String {
+    match command {
+        // Each kind once, however many calls there were
+        Command::Sequence(commands) => {
+            let mut names: Vec<String> = Vec::new();
+            for name in commands.iter().map(command_name) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            names.join("+")
+        }
+        command => variant(format!("{command:?}")),
+    }
+}
+
+// Events that could come next, other than an overshoot (see `explore`)
+fn events(settings: &Settings, snapshot: &Snapshot, next: Option<Event>) -> Vec<Event> {
+    let mut events: Vec<Event> = next.into_iter().collect();
+    for floor in LOWEST_FLOOR..=HIGHEST_FLOOR {
+        let doors = settings.served.doors(floor);
+        let sides: Vec<Side> = match doors.is_empty() {
+            true => vec![Side::Front], // The car's panel still has a button for it
+            false => doors.sides().collect(),
+        };
+        for side in sides {
+            events.push(Event::Panel(floor, side));
+            events.push(Event::PanelCancel(floor, side));
+            if floor < HIGHEST_FLOOR {
+                events.push(Event::Up(floor, side));
+            }
+            if floor > LOWEST_FLOOR {
+                events.push(Event::Down(floor, side));
+            }
+        }
+    }
+    events.extend([0, settings.bypass_load + 1, RATED_LOAD + 1].map(Event::Load));
+    if settings.idle_policy != IdlePolicy::StayPut {
+        events.push(Event::IdleTimeout);
+    }
+    // The controller only gives up on the hardware while it's waiting for
+    // the car or the doors to do something
+    match snapshot.state.as_str() {
+        "moving-up" | "moving-down" => {
+            events.extend([Event::Fault(Failure::Motion), Event::Reset]);
+        }
+        "open" | "open-up" | "open-down" => events.extend([
+            Event::Fault(Failure::Doors),
+            Event::Fault(Failure::Motion), // Still stopping
+            Event::Reset,
+        ]),
+        _ => {}
+    }
+    events
+}
+
+// What tells states apart.  The count of hall calls at each floor only ever
+// goes up, so it's left out, or no state would be seen twice.
+fn key(machine: &impl Debug) -> String {
+    let debug = format!("{machine:?}");
+    match debug.find("demand: [") {
+        Some(start) => {
+            let end = start + debug[start..].find(']').unwrap();
+            format!("{}{}", &debug[..start], &debug[end + 1..])
+        }
+        None => debug,
+    }
+}
+
+// The transitions of `M` reachable within `depth` events of an idle car
+pub fn explore<M: Machine>(settings: Settings, depth: usize) -> BTreeSet<Edge> {
+    let mut edges = BTreeSet::new();
+    let initial = M::with_settings(settings.clone());
+    let mut seen = HashSet::from([key(&initial)]);
+    let mut frontier = vec![initial];
+    for _ in 0..depth {
+        let mut next = Vec::new();
+        for machine in frontier {
+            let before = machine.snapshot();
+            let future = machine.produce_future_event();
+            for event in events(&settings, &before, future) {
+                let mut after = machine.clone();
+                let command = after.handle(event);
+                edges.insert(Edge {
+                    from: before.state.clone(),
+                    event: variant(format!("{event:?}")),
+                    command: command.as_ref().map(command_name),
+                    to: after.snapshot().state,
+                });
+                let mut reached = Vec::new();
+
+                // A stop ordered as the car arrives may come too late, if
+                // there's somewhere further on for the car to run on to
+                if let (Event::Arrived(floor), Some(Command::StopAndOpen { .. })) =
+                    (event, &command)
+                {
+                    let beyond = match before.state.as_str() {
+                        "moving-up" => settings.served.above(floor),
+                        _ => settings.served.below(floor),
+                    };
+                    if beyond.is_some() {
+                        let mut overshot = after.clone();
+                        let command = overshot.handle(Event::Overshot(floor));
+                        edges.insert(Edge {
+                            from: after.snapshot().state,
+                            event: "Overshot".to_string(),
+                            command: command.as_ref().map(command_name),
+                            to: overshot.snapshot().state,
+                        });
+                        reached.push(overshot);
+                    }
+                }
+
+                reached.push(after);
+                for machine in reached {
+                    if seen.insert(key(&machine)) {
+                        next.push(machine);
+                    }
+                }
+            }
+        }
+        frontier = next;
+    }
+    edges
+}
+
+// One edge per line, with "-" for no command
+pub fn table(edges: &BTreeSet<Edge>) -> String {
+    edges
+        .iter()
+        .map(|edge| {
+            let command = edge.command.as_deref().unwrap_or("-");
+            format!("{} {} {command} {}\n", edge.from, edge.event, edge.to)
+        })
+        .collect()
+}
+
+// Edges between the same two states, drawn as one arrow
+fn arrows(edges: &BTreeSet<Edge>) -> BTreeMap<(&str, &str), Vec<String>> {
+    let mut arrows: BTreeMap<_, Vec<String>> = BTreeMap::new();
+    for edge in edges {
+        arrows
+            .entry((edge.from.as_str(), edge.to.as_str()))
+            .or_default()
+            .push(edge.label());
+    }
+    arrows
+}
+
+pub fn dot(edges: &BTreeSet<Edge>) -> String {
+    let mut out = String::from("digraph elevator {\n");
+    out.push_str("    node [shape=box, style=rounded];\n");
+    out.push_str("    start [shape=point];\n");
+    out.push_str("    start -> \"idle\";\n");
+    for ((from, to), labels) in arrows(edges) {
+        let label = labels.join("\\n");
+        out.push_str(&format!(
+            "    \"{from}\" -> \"{to}\" [label=\"{label}\"];\n"
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+pub fn mermaid(edges: &BTreeSet<Edge>) -> String {
+    // Mermaid names can't have dashes in them
+    let name = |state: &str| state.replace('-', "_");
+    let mut out = String::from("stateDiagram-v2\n");
+    let states: BTreeSet<&str> = edges
+        .iter()
+        .flat_map(|edge| [edge.from.as_str(), edge.to.as_str()])
+        .collect();
+    for state in states {
+        out.push_str(&format!("    state \"{state}\" as {}\n", name(state)));
+    }
+    out.push_str("    [*] --> idle\n");
+    for ((from, to), labels) in arrows(edges) {
+        let label = labels.join("<br>");
+        out.push_str(&format!("    {} --> {} : {label}\n", name(from), name(to)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logic::ServedFloors;
+
+    fn edge(from: &str, event: &str, command: Option<&str>, to: &str) -> Edge {
+        Edge {
+            from: from.to_string(),
+            event: event.to_string(),
+            command: command.map(str::to_string),
+            to: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn both_implementations_agree() {
+        let settings = [
+            Settings::new(),
+            Settings {
+                idle_policy: IdlePolicy::ReturnToLobby,
+                served: ServedFloors::with_doors(&[1, 2, 4, 5], &[2, 5]),
+                ..Settings::new()
+            },
+        ];
+        for settings in settings {
+            let logic = explore::<Elevator>(settings.clone(), 4);
+            let states = explore::<ElevatorAfterEvent>(settings, 4);
+            assert_eq!(table(&logic), table(&states));
+        }
+    }
+
+    #[test]
+    fn transitions_found() {
+        let edges = explore::<Elevator>(Settings::new(), 5);
+        for expected in [
+            edge("idle", "Panel", Some("MoveUp"), "moving-up"),
+            edge("idle", "Panel", Some("OpenDoor"), "open"),
+            edge("moving-up", "Arrived", Some("StopAndOpen"), "open-up"),
+            edge("open-up", "Overshot", None, "moving-up"),
+            edge("open-up", "Closed", None, "idle"),
+            edge("open-up", "Load", Some("Overload"), "open-up"),
+            edge(
+                "moving-down",
+                "Fault",
+                Some("CancelCarCalls"),
+                "fault-motion",
+            ),
+            edge("fault-doors", "Panel", Some("RejectEvent"), "fault-doors"),
+        ] {
+            assert!(edges.contains(&expected), "{expected:?} not found");
+        }
+        // Every state is reached
+        let states: BTreeSet<&str> = edges.iter().map(|edge| edge.to.as_str()).collect();
+        assert_eq!(states.len(), 8);
+    }
+
+    #[test]
+    fn diagrams() {
+        let edges = BTreeSet::from([
+            edge("idle", "Panel", Some("MoveUp"), "moving-up"),
+            edge("idle", "Up", Some("MoveUp"), "moving-up"),
+            edge("moving-up", "Arrived", Some("StopAndOpen"), "open-up"),
+        ]);
+        assert_eq!(
+            table(&edges),
+            "idle Panel MoveUp moving-up\nidle Up MoveUp moving-up\nmoving-up Arrived StopAndOpen open-up\n"
+        );
+        let dot = dot(&edges);
+        assert!(dot
+            .contains("    \"idle\" -> \"moving-up\" [label=\"Panel / MoveUp\\nUp / MoveUp\"];\n"));
+        let mermaid = mermaid(&edges);
+        assert!(mermaid.starts_with("stateDiagram-v2\n"));
+        assert!(mermaid.contains("    state \"moving-up\" as moving_up\n"));
+        assert!(mermaid.contains("    idle --> moving_up : Panel / MoveUp<br>Up / MoveUp\n"));
+    }
+}
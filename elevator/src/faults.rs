@@ -0,0 +1,212 @@
+// Hardware faults for the simulator, to see how the controller copes when
+// Lifty or the network misbehaves.  A `FaultPlan` says which faults happen
+// and when: at set ticks, at random, or both.
+//
+//     # faults.conf
+//     seed = 7
+//     rate = 0.001                 # chance per tick of a random fault
+//     random = press, drop-event   # faults picked at random
+//     fault = 600 stuck-door       # the doors jam at tick 600
+//     fault = 900 press U3         # somebody presses the up button on 3
+
+use crate::config;
+use crate::logic::{HIGHEST_FLOOR, LOWEST_FLOOR};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    DropEvent,        // The next event from Lifty is lost
+    DuplicateEvent,   // The next event from Lifty arrives twice
+    DelayEvent(u64),  // The next event from Lifty arrives this many ticks late
+    DropCommand,      // The next command from the controller is lost
+    DuplicateCommand, // The next command from the controller arrives twice
+    Disconnect,       // Nothing gets through either way from then on
+    Garble(String),   // Lifty sends this mangled datagram ("" for any)
+    StuckDoor,        // The doors stop moving (no more On or Cn) until a reset
+    MotorWontStart,   // The next motor start command does nothing
+    MissApproach,     // The next approach event is never sent
+    Press(String),    // Somebody presses a button ("" for any button)
+    Reset,            // Lifty resets itself
+}
+
+impl Fault {
+    pub fn parse(value: &str) -> Result<Fault, String> {
+        let bad = || format!("bad fault ({value})");
+        let mut words = value.split_whitespace();
+        let fault = match (words.next(), words.next()) {
+            (Some("drop-event"), None) => Fault::DropEvent,
+            (Some("duplicate-event"), None) => Fault::DuplicateEvent,
+            (Some("delay-event"), Some(ticks)) => {
+                Fault::DelayEvent(ticks.parse().map_err(|_| bad())?)
+            }
+            (Some("drop-command"), None) => Fault::DropCommand,
+            (Some("duplicate-command"), None) => Fault::DuplicateCommand,
+            (Some("disconnect"), None) => Fault::Disconnect,
+            (Some("garble"), datagram) => Fault::Garble(datagram.unwrap_or("").to_string()),
+            (Some("stuck-door"), None) => Fault::StuckDoor,
+            (Some("motor-wont-start"), None) => Fault::MotorWontStart,
+            (Some("miss-approach"), None) => Fault::MissApproach,
+            (Some("press"), button) => Fault::Press(button.unwrap_or("").to_string()),
+            (Some("reset"), None) => Fault::Reset,
+            _ => return Err(bad()),
+        };
+        match words.next() {
+            None => Ok(fault),
+            Some(_) => Err(bad()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FaultPlan {
+    script: Vec<(u64, Fault)>, // Faults at set ticks
+    rate: f64,                 // Chance per tick of a random fault
+    random: Vec<Fault>,        // Faults to pick from at random
+    rng: fastrand::Rng,
+}
+
+impl FaultPlan {
+    // No faults at all
+    pub fn new() -> Self {
+        FaultPlan::random(1, 0.0, Vec::new())
+    }
+
+    pub fn scripted(script: Vec<(u64, Fault)>) -> Self {
+        FaultPlan {
+            script,
+            ..FaultPlan::new()
+        }
+    }
+
+    pub fn random(seed: u64, rate: f64, faults: Vec<Fault>) -> Self {
+        FaultPlan {
+            script: Vec::new(),
+            rate,
+            random: faults,
+            rng: fastrand::Rng::with_seed(seed),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut plan = FaultPlan::new();
+        let mut seed = 1;
+        for (key, value) in config::read(path)? {
+            match key.as_str() {
+                "seed" => seed = config::value(&key, &value)?,
+                "rate" => plan.rate = config::value(&key, &value)?,
+                "random" => {
+                    for fault in value.split(',') {
+                        let fault =
+                            Fault::parse(fault.trim()).map_err(|e| format!("{path}: {e}"))?;
+                        plan.random.push(fault);
+                    }
+                }
+                "fault" => {
+                    let bad = || format!("{path}: bad value for fault ({value})");
+                    let (tick, fault) = value.split_once(' ').ok_or_else(bad)?;
+                    let tick = tick.parse().map_err(|_| bad())?;
+                    let fault = Fault::parse(fault.trim()).map_err(|e| format!("{path}: {e}"))?;
+                    plan.script.push((tick, fault));
+                }
+                _ => return Err(format!("{path}: unknown setting {key}")),
+            }
+        }
+        plan.rng = fastrand::Rng::with_seed(seed);
+        Ok(plan)
+    }
+
+    // The faults that strike on this tick
+    pub fn due(&mut self, tick: u64) -> Vec<Fault> {
+        let mut faults: Vec<Fault> = self
+            .script
+            .iter()
+            .filter(|(at, _)| *at == tick)
+            .map(|(_, fault)| fault.clone())
+            .collect();
+        if !self.random.is_empty() && self.rng.f64() < self.rate {
+            faults.push(self.random[self.rng.usize(..self.random.len())].clone());
+        }
+        for fault in faults.iter_mut() {
+            if *fault == Fault::Press(String::new()) {
+                *fault = Fault::Press(self.any_button());
+            }
+            if *fault == Fault::Garble(String::new()) {
+                *fault = Fault::Garble(self.any_rubbish());
+            }
+        }
+        faults
+    }
+
+    fn any_button(&mut self) -> String {
+        match self.rng.usize(..3) {
+            0 => format!("P{}", self.rng.u8(LOWEST_FLOOR..=HIGHEST_FLOOR)),
+            1 => format!("U{}", self.rng.u8(LOWEST_FLOOR..HIGHEST_FLOOR)),
+            _ => format!("D{}", self.rng.u8(LOWEST_FLOOR + 1..=HIGHEST_FLOOR)),
+        }
+    }
+
+    // Something that looks a bit like what Lifty sends, but isn't.  The
+    // U+FFFDs are what bytes that aren't UTF-8 turn into on the way in.
+    fn any_rubbish(&mut self) -> String {
+        let rubbish = [
+            "",
+            "L",
+            "L999",
+            "Lheavy",
+            "K",
+            "K-1",
+            "P",
+            "P0",
+            "P9",
+            "U12",
+            "X3",
+            "A\u{e9}",
+            "\u{0}",
+            "P\u{fffd}",
+            "A\u{fffd}\u{fffd}",
+        ];
+        rubbish[self.rng.usize(..rubbish.len())].to_string()
+    }
+}
+
+impl Default for FaultPlan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_faults() {
+        assert_eq!(Fault::parse("stuck-door"), Ok(Fault::StuckDoor));
+        assert_eq!(Fault::parse("disconnect"), Ok(Fault::Disconnect));
+        assert_eq!(
+            Fault::parse("garble P9"),
+            Ok(Fault::Garble("P9".to_string()))
+        );
+        assert_eq!(Fault::parse("garble"), Ok(Fault::Garble(String::new())));
+        assert_eq!(Fault::parse("delay-event 20"), Ok(Fault::DelayEvent(20)));
+        assert_eq!(
+            Fault::parse("press RU2"),
+            Ok(Fault::Press("RU2".to_string()))
+        );
+        assert_eq!(Fault::parse("press"), Ok(Fault::Press(String::new())));
+        assert!(Fault::parse("delay-event").is_err());
+        assert!(Fault::parse("reset now").is_err());
+    }
+
+    #[test]
+    fn scripted_and_random_faults() {
+        let mut plan = FaultPlan::scripted(vec![(5, Fault::Reset), (5, Fault::DropEvent)]);
+        assert!(plan.due(4).is_empty());
+        assert_eq!(plan.due(5), [Fault::Reset, Fault::DropEvent]);
+
+        let mut plan = FaultPlan::random(1, 1.0, vec![Fault::Press(String::new())]);
+        match &plan.due(1)[..] {
+            [Fault::Press(button)] => assert!(!button.is_empty()),
+            faults => panic!("unexpected faults {faults:?}"),
+        }
+    }
+}
@@ -0,0 +1,318 @@
+// A first-come, first-served dispatcher, as a yardstick for the collective
+// algorithm in logic.rs.  The car goes to the calls strictly in the order
+// they were made, stopping on the way only for the one it's heading for.
+// Nobody gets passed over for a later call, but everyone waits longer:
+// bench shows how much the collective algorithm gains by picking up people
+// going its way.
+//
+// Only the served floors are taken from the settings.  There's no bypassing
+// when full, no parking and no anti-nuisance.
+
+use crate::logic::{
+    Algorithm, Command, Doors, Event, Indicator, Settings, Side, LOWEST_FLOOR, RATED_LOAD,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Activity {
+    Idle, // Standing with the doors shut
+    MovingUp,
+    MovingDown,
+    Open,
+    OutOfService,
+}
+
+#[derive(Debug, Clone)]
+pub struct FirstCome {
+    settings: Settings,
+    activity: Activity,
+    floor: u8,
+    load: u8,
+    calls: Vec<Event>,                    // Waiting, oldest first
+    stop: Option<(Activity, Vec<Event>)>, // Calls answered by the stop just ordered
+}
+
+impl FirstCome {
+    pub fn new() -> Self {
+        FirstCome::with_settings(Settings::new())
+    }
+
+    pub fn with_settings(settings: Settings) -> Self {
+        FirstCome {
+            settings,
+            activity: Activity::Idle,
+            floor: LOWEST_FLOOR,
+            load: 0,
+            calls: Vec::new(),
+            stop: None,
+        }
+    }
+
+    // The floor of the oldest call
+    fn target(&self) -> Option<u8> {
+        self.calls.first().map(|&call| floor(call))
+    }
+
+    // The direction to show at a stop: the way the oldest call there wants
+    // to go, or else the way to the next call after it
+    fn direction(&self, floor: u8) -> Indicator {
+        let hall_call = self
+            .calls
+            .iter()
+            .find(|&&call| self::floor(call) == floor && !matches!(call, Event::Panel(..)));
+        let next = self
+            .calls
+            .iter()
+            .map(|&call| self::floor(call))
+            .find(|&next| next != floor);
+        match (hall_call, next) {
+            (Some(Event::Up(..)), _) => Indicator::Up,
+            (Some(_), _) => Indicator::Down,
+            (None, Some(next)) if next > floor => Indicator::Up,
+            (None, Some(_)) => Indicator::Down,
+            (None, None) => Indicator::Off,
+        }
+    }
+
+    // Take the calls a stop at `floor` answers off the list: car calls, and
+    // hall calls going the way the indicator shows
+    fn answer(&mut self, floor: u8) -> (Indicator, Doors) {
+        let direction = self.direction(floor);
+        let answered = |call: &Event| match *call {
+            Event::Panel(f, _) => f == floor,
+            Event::Up(f, _) => f == floor && direction == Indicator::Up,
+            Event::Down(f, _) => f == floor && direction == Indicator::Down,
+            _ => false,
+        };
+        let (answered, waiting) = self.calls.iter().partition(|call| answered(call));
+        self.calls = waiting;
+        let doors = answered
+            .iter()
+            .map(|&call| Doors::from(side(call)))
+            .fold(Doors::NONE, |doors, side| doors | side);
+        let doors = match doors.is_empty() {
+            true => self.settings.served.default_door(floor),
+            false => doors,
+        };
+        self.stop = Some((self.activity, answered));
+        (direction, doors)
+    }
+
+    // Standing with the doors shut: open them for a call here, or set off
+    // for the oldest one
+    fn dispatch(&mut self) -> Option<Command> {
+        let target = self.target()?;
+        if target > self.floor {
+            self.activity = Activity::MovingUp;
+            Some(Command::MoveUp)
+        } else if target < self.floor {
+            self.activity = Activity::MovingDown;
+            Some(Command::MoveDown)
+        } else {
+            let floor = self.floor;
+            let (direction, doors) = self.answer(floor);
+            self.activity = Activity::Open;
+            Some(Command::OpenDoor {
+                floor,
+                direction,
+                doors,
+            })
+        }
+    }
+
+    // The car is coming up to a floor.  It stops for the oldest call, and
+    // also if it has nowhere further to go in this direction.
+    fn arrived(&mut self, floor: u8) -> Option<Command> {
+        self.floor = floor;
+        let up = self.activity == Activity::MovingUp;
+        let beyond = match up {
+            true => self.settings.served.above(floor),
+            false => self.settings.served.below(floor),
+        };
+        let stop = match self.target() {
+            _ if beyond.is_none() => true,
+            Some(target) if up => target <= floor,
+            Some(target) => target >= floor,
+            None => true,
+        };
+        if !stop {
+            return None;
+        }
+        let (direction, doors) = self.answer(floor);
+        self.activity = Activity::Open;
+        Some(Command::StopAndOpen {
+            floor,
+            direction,
+            doors,
+        })
+    }
+
+    // Too late to stop: the car carries on, and the calls go back first in
+    // line.  The next floor is then past the oldest call, so it stops there.
+    fn overshot(&mut self) -> Option<Command> {
+        if let Some((activity, answered)) = self.stop.take() {
+            self.activity = activity;
+            self.calls.splice(0..0, answered);
+        }
+        None
+    }
+
+    fn call(&mut self, event: Event) -> Option<Command> {
+        if self.activity == Activity::OutOfService || !self.settings.served.valid_call(event) {
+            return Some(Command::RejectEvent(event));
+        }
+        if !self.calls.contains(&event) {
+            self.calls.push(event);
+        }
+        match self.activity {
+            Activity::Idle => self.dispatch(),
+            _ => None,
+        }
+    }
+
+    fn cancel(&mut self, floor: u8, side: Side) -> Option<Command> {
+        let call = Event::Panel(floor, side);
+        match self.calls.iter().position(|&waiting| waiting == call) {
+            Some(n) => {
+                self.calls.remove(n);
+                Some(Command::CancelCarCalls(vec![(floor, side)]))
+            }
+            None => Some(Command::RejectEvent(Event::PanelCancel(floor, side))),
+        }
+    }
+
+    fn load(&mut self, load: u8) -> Option<Command> {
+        let was_overloaded = self.load > RATED_LOAD;
+        self.load = load;
+        let overloaded = load > RATED_LOAD;
+        match self.activity {
+            Activity::Open if overloaded || was_overloaded => Some(Command::Overload(overloaded)),
+            _ => None,
+        }
+    }
+
+    // Put out the lights of every call, since none of them will be answered
+    fn fault(&mut self) -> Option<Command> {
+        self.activity = Activity::OutOfService;
+        let mut car_calls = Vec::new();
+        let mut commands = Vec::new();
+        for call in self.calls.drain(..) {
+            match call {
+                Event::Panel(floor, side) => car_calls.push((floor, side)),
+                _ => commands.push(Command::RejectEvent(call)),
+            }
+        }
+        Command::cancelling(car_calls, Command::sequence(commands))
+    }
+}
+
+impl Default for FirstCome {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Algorithm for FirstCome {
+    fn handle(&mut self, event: Event) -> Option<Command> {
+        // An overshoot is only ever reported straight after the stop
+        if !matches!(event, Event::Overshot(_)) {
+            self.stop = None;
+        }
+        match (self.activity, event) {
+            (_, Event::Panel(..) | Event::Up(..) | Event::Down(..)) => self.call(event),
+            (_, Event::Fault(_)) => self.fault(),
+            (_, Event::Reset) => {
+                self.calls.clear();
+                self.floor = LOWEST_FLOOR;
+                self.load = 0;
+                self.activity = Activity::Idle;
+                None
+            }
+            (Activity::OutOfService, _) => None,
+            (_, Event::PanelCancel(floor, side)) => self.cancel(floor, side),
+            (_, Event::Load(load)) => self.load(load),
+            (_, Event::Overshot(_)) => self.overshot(),
+            (Activity::MovingUp | Activity::MovingDown, Event::Arrived(floor)) => {
+                self.arrived(floor)
+            }
+            (Activity::Open, Event::Closed(floor)) => {
+                self.floor = floor;
+                self.activity = Activity::Idle;
+                self.dispatch()
+            }
+            _ => None,
+        }
+    }
+}
+
+fn floor(call: Event) -> u8 {
+    match call {
+        Event::Panel(floor, _) | Event::Up(floor, _) | Event::Down(floor, _) => floor,
+        _ => LOWEST_FLOOR,
+    }
+}
+
+fn side(call: Event) -> Side {
+    match call {
+        Event::Panel(_, side) | Event::Up(_, side) | Event::Down(_, side) => side,
+        _ => Side::Front,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::building::BuildingConfig;
+    use crate::sim;
+    use crate::traffic::TrafficProfile;
+
+    fn open(floor: u8, direction: Indicator) -> Option<Command> {
+        Some(Command::StopAndOpen {
+            floor,
+            direction,
+            doors: Doors::FRONT,
+        })
+    }
+
+    #[test]
+    fn calls_are_served_in_order() {
+        let mut elevator = FirstCome::new();
+        assert_eq!(
+            elevator.handle(Event::Panel(4, Side::Front)),
+            Some(Command::MoveUp)
+        );
+        assert_eq!(elevator.handle(Event::Down(2, Side::Front)), None);
+        assert_eq!(elevator.handle(Event::Panel(3, Side::Front)), None);
+        // Passes 2 and 3, which called later
+        assert_eq!(elevator.handle(Event::Arrived(2)), None);
+        assert_eq!(elevator.handle(Event::Arrived(3)), None);
+        assert_eq!(elevator.handle(Event::Arrived(4)), open(4, Indicator::Down));
+        assert_eq!(elevator.handle(Event::Closed(4)), Some(Command::MoveDown));
+        assert_eq!(elevator.handle(Event::Arrived(3)), None);
+        assert_eq!(elevator.handle(Event::Arrived(2)), open(2, Indicator::Down));
+        assert_eq!(elevator.handle(Event::Closed(2)), Some(Command::MoveUp));
+        assert_eq!(elevator.handle(Event::Arrived(3)), open(3, Indicator::Off));
+        assert_eq!(elevator.handle(Event::Closed(3)), None);
+    }
+
+    #[test]
+    fn overshot_call_is_answered_on_the_way_back() {
+        let mut elevator = FirstCome::new();
+        elevator.handle(Event::Panel(3, Side::Front));
+        elevator.handle(Event::Arrived(2));
+        assert_eq!(elevator.handle(Event::Arrived(3)), open(3, Indicator::Off));
+        assert_eq!(elevator.handle(Event::Overshot(3)), None);
+        assert_eq!(elevator.handle(Event::Arrived(4)), open(4, Indicator::Down));
+        assert_eq!(elevator.handle(Event::Closed(4)), Some(Command::MoveDown));
+        assert_eq!(elevator.handle(Event::Arrived(3)), open(3, Indicator::Off));
+    }
+
+    #[test]
+    fn delivers_everyone() {
+        let config = BuildingConfig::new();
+        let passengers = TrafficProfile::new().generate(&config);
+        let stats = sim::run(&mut FirstCome::new(), &config, &passengers, 1_000_000);
+        assert_eq!(stats.crash, None);
+        assert!(stats.violations.is_empty());
+        assert_eq!(stats.delivered, stats.passengers);
+    }
+}
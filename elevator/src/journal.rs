@@ -0,0 +1,372 @@
+// A record of everything the controller was told and did, for reproducing
+// problems from the field.  Each line is one entry, stamped with the
+// controller's clock in milliseconds:
+//
+//     0 start
+//     1520 event Panel(3, Front)
+//     1520 command MoveUp
+//     1520 state moving-up 1
+//
+// A controller that took the car over from another one starts with the
+// hardware it found instead, e.g.
+//
+//     0 resume floor=3 activity=open-up doors=front calls=P5,U2
+//
+// With access control (see security.rs), the events it deals with itself,
+// cards shown and calls to locked floors, never reach the state machine:
+//
+//     2210 secured Panel(4, Front)
+//
+// Replaying a journal feeds its events back through the state machine and
+// checks that the same commands come out (see bin/replay.rs).
+
+use crate::controller::{call, call_button};
+use crate::logic::{
+    Activity, Command, Doors, Event, Failure, HardwareState, Indicator, Settings, Side,
+    HIGHEST_FLOOR, LOWEST_FLOOR,
+};
+use crate::states::{ElevatorAfterEvent, IdleElevator};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Start(Option<HardwareState>), // A controller starting up, fresh or taking over
+    Event(Event),
+    Secured(Event),  // Taken or turned away by access control, not the state machine
+    Command(String), // As printed with {:?}
+    State(String),   // State and floor after handling an event
+}
+
+impl Entry {
+    pub fn parse(text: &str) -> Result<Entry, String> {
+        let bad = || format!("bad journal entry ({text})");
+        let (kind, rest) = text.split_once(' ').unwrap_or((text, ""));
+        match kind {
+            "start" if rest.is_empty() => Ok(Entry::Start(None)),
+            "resume" => Ok(Entry::Start(Some(parse_hardware(rest).ok_or_else(bad)?))),
+            "event" => Ok(Entry::Event(parse_event(rest).ok_or_else(bad)?)),
+            "secured" => Ok(Entry::Secured(parse_event(rest).ok_or_else(bad)?)),
+            "command" if !rest.is_empty() => Ok(Entry::Command(rest.to_string())),
+            "state" if !rest.is_empty() => Ok(Entry::State(rest.to_string())),
+            _ => Err(bad()),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match self {
+            Entry::Start(None) => "start".to_string(),
+            Entry::Start(Some(hardware)) => format!("resume {}", hardware_text(hardware)),
+            Entry::Event(event) => format!("event {event:?}"),
+            Entry::Secured(event) => format!("secured {event:?}"),
+            Entry::Command(command) => format!("command {command}"),
+            Entry::State(state) => format!("state {state}"),
+        }
+    }
+}
+
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    // Entries are added to the end of the file, after any earlier runs
+    pub fn open(path: &str) -> Result<Journal, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("{path}: {e}"))?;
+        Ok(Journal { file })
+    }
+
+    pub fn record(&mut self, at: Duration, entry: &Entry) {
+        // Losing the journal mustn't stop the car
+        if let Err(e) = writeln!(self.file, "{} {}", at.as_millis(), entry.text()) {
+            eprintln!("journal: {e}");
+        }
+    }
+
+    // The entries for an event and what the state machine did about it
+    pub fn handled(
+        &mut self,
+        at: Duration,
+        event: Event,
+        command: Option<&Command>,
+        elevator: &ElevatorAfterEvent,
+    ) {
+        self.record(at, &Entry::Event(event));
+        if let Some(command) = command {
+            self.record(at, &Entry::Command(format!("{command:?}")));
+        }
+        self.record(at, &Entry::State(state(elevator)));
+    }
+}
+
+pub fn read(path: &str) -> Result<Vec<(Duration, Entry)>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let mut entries = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let error = |e| format!("{path}: line {}: {e}", n + 1);
+        let (at, entry) = line
+            .split_once(' ')
+            .ok_or_else(|| error("no time".into()))?;
+        let at = at.parse().map_err(|_| error(format!("bad time ({at})")))?;
+        let entry = Entry::parse(entry).map_err(error)?;
+        entries.push((Duration::from_millis(at), entry));
+    }
+    Ok(entries)
+}
+
+// Feed the journalled events back through the state machine, in a building
+// with the given settings.  Those access control dealt with are skipped.
+// Returns how many there were, or where the state machine first did
+// something else.
+pub fn replay(entries: &[(Duration, Entry)], settings: Settings) -> Result<usize, String> {
+    let mut elevator = ElevatorAfterEvent::Idle(IdleElevator::with_settings(settings.clone()));
+    let mut produced: Option<String> = None;
+    let mut events = 0;
+    for (at, entry) in entries {
+        let at = at.as_millis();
+        if let (Some(command), Entry::Start(_) | Entry::Event(_) | Entry::Secured(_)) =
+            (&produced, entry)
+        {
+            return Err(format!("{at}: unexpected command {command}"));
+        }
+        match entry {
+            Entry::Start(None) => {
+                elevator = ElevatorAfterEvent::Idle(IdleElevator::with_settings(settings.clone()))
+            }
+            Entry::Start(Some(hardware)) => {
+                let (command, resumed) = ElevatorAfterEvent::resume(settings.clone(), hardware)
+                    .map_err(|e| format!("{at}: can't resume with {e}"))?;
+                produced = command.map(|command| format!("{command:?}"));
+                elevator = resumed;
+            }
+            Entry::Event(event) => {
+                let (command, next) = elevator.handle(*event);
+                produced = command.map(|command| format!("{command:?}"));
+                elevator = next;
+                events += 1;
+            }
+            Entry::Secured(_) => {}
+            Entry::Command(expected) => match produced.take() {
+                Some(command) if command == *expected => {}
+                Some(command) => return Err(format!("{at}: expected {expected}, got {command}")),
+                None => return Err(format!("{at}: expected {expected}, got nothing")),
+            },
+            Entry::State(expected) => {
+                let state = state(&elevator);
+                if state != *expected {
+                    return Err(format!("{at}: expected state {expected}, got {state}"));
+                }
+            }
+        }
+    }
+    match produced {
+        Some(command) => Err(format!("unexpected command {command} at the end")),
+        None => Ok(events),
+    }
+}
+
+fn state(elevator: &ElevatorAfterEvent) -> String {
+    let snapshot = elevator.snapshot();
+    format!("{} {}", snapshot.state, snapshot.floor)
+}
+
+// Events are written the way {:?} prints them, e.g. "Panel(3, Front)"
+fn parse_event(text: &str) -> Option<Event> {
+    let (name, args) = match text.strip_suffix(')') {
+        Some(call) => {
+            let (name, args) = call.split_once('(')?;
+            (name, args.split(", ").collect())
+        }
+        None => (text, Vec::new()),
+    };
+    let floor = |n: usize| {
+        let floor = args.get(n)?.parse().ok()?;
+        (LOWEST_FLOOR..=HIGHEST_FLOOR)
+            .contains(&floor)
+            .then_some(floor)
+    };
+    let side = |n: usize| match *args.get(n)? {
+        "Front" => Some(Side::Front),
+        "Rear" => Some(Side::Rear),
+        _ => None,
+    };
+    let event = match (name, args.len()) {
+        ("Panel", 2) => Event::Panel(floor(0)?, side(1)?),
+        ("Up", 2) => Event::Up(floor(0)?, side(1)?),
+        ("Down", 2) => Event::Down(floor(0)?, side(1)?),
+        ("Arrived", 1) => Event::Arrived(floor(0)?),
+        ("Closed", 1) => Event::Closed(floor(0)?),
+        ("Load", 1) => Event::Load(args[0].parse().ok()?),
+        ("IdleTimeout", 0) => Event::IdleTimeout,
+        ("PanelCancel", 2) => Event::PanelCancel(floor(0)?, side(1)?),
+        ("Credential", 1) => Event::Credential(args[0].parse().ok()?),
+        ("Overshot", 1) => Event::Overshot(floor(0)?),
+        ("Fault", 1) => Event::Fault(match args[0] {
+            "Doors" => Failure::Doors,
+            "Motion" => Failure::Motion,
+            _ => return None,
+        }),
+        ("Reset", 0) => Event::Reset,
+        _ => return None,
+    };
+    Some(event)
+}
+
+fn hardware_text(hardware: &HardwareState) -> String {
+    let activity = match hardware.activity {
+        Activity::Closed => "closed".to_string(),
+        Activity::Open(doors, direction) => {
+            let doors = match (doors.front, doors.rear) {
+                (true, true) => "both",
+                (false, true) => "rear",
+                _ => "front",
+            };
+            let open = match direction {
+                Indicator::Up => "open-up",
+                Indicator::Down => "open-down",
+                Indicator::Off => "open",
+            };
+            format!("{open} doors={doors}")
+        }
+        Activity::MovingUp => "moving-up".to_string(),
+        Activity::MovingDown => "moving-down".to_string(),
+    };
+    let calls: Vec<String> = hardware
+        .calls
+        .iter()
+        .filter_map(|&c| call_button(c))
+        .collect();
+    format!(
+        "floor={} activity={activity} calls={}",
+        hardware.floor,
+        calls.join(",")
+    )
+}
+
+fn parse_hardware(text: &str) -> Option<HardwareState> {
+    let (mut floor, mut activity, mut doors, mut calls) = (None, None, Doors::FRONT, None);
+    for word in text.split_whitespace() {
+        match word.split_once('=')? {
+            ("floor", value) => floor = Some(value.parse().ok()?),
+            ("activity", value) => activity = Some(value),
+            ("doors", "front") => doors = Doors::FRONT,
+            ("doors", "rear") => doors = Doors::REAR,
+            ("doors", "both") => doors = Doors::BOTH,
+            ("calls", value) => {
+                calls = value
+                    .split(',')
+                    .filter(|button| !button.is_empty())
+                    .map(call)
+                    .collect()
+            }
+            _ => return None,
+        }
+    }
+    let activity = match activity? {
+        "closed" => Activity::Closed,
+        "open" => Activity::Open(doors, Indicator::Off),
+        "open-up" => Activity::Open(doors, Indicator::Up),
+        "open-down" => Activity::Open(doors, Indicator::Down),
+        "moving-up" => Activity::MovingUp,
+        "moving-down" => Activity::MovingDown,
+        _ => return None,
+    };
+    Some(HardwareState {
+        floor: floor.filter(|floor| (LOWEST_FLOOR..=HIGHEST_FLOOR).contains(floor))?,
+        activity,
+        calls: calls?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::building::BuildingConfig;
+    use crate::controller::LiftyController;
+    use crate::sim::Simulation;
+    use crate::traffic::TrafficProfile;
+
+    #[test]
+    fn journal_entries() {
+        for text in [
+            "start",
+            "resume floor=3 activity=open-up doors=rear calls=P5,RU2",
+            "resume floor=1 activity=closed calls=",
+            "event Panel(3, Front)",
+            "event IdleTimeout",
+            "event Fault(Motion)",
+            "secured Credential(1001)",
+            "command MoveUp",
+            "state moving-up 1",
+        ] {
+            assert_eq!(
+                Entry::parse(text).map(|entry| entry.text()),
+                Ok(text.into())
+            );
+        }
+        assert!(Entry::parse("event Panel(9, Front)").is_err());
+        assert!(Entry::parse("event Panel(3)").is_err());
+        assert!(Entry::parse("resume floor=3").is_err());
+    }
+
+    #[test]
+    fn replay_finds_differences() {
+        let at = Duration::ZERO;
+        let mut entries = vec![
+            (at, Entry::Start(None)),
+            (at, Entry::Event(Event::Panel(3, Side::Front))),
+            (at, Entry::Command("MoveUp".into())),
+            (at, Entry::State("moving-up 1".into())),
+            (at, Entry::Secured(Event::Panel(4, Side::Front))),
+            (at, Entry::Event(Event::Arrived(2))),
+            (at, Entry::State("moving-up 2".into())),
+        ];
+        assert_eq!(replay(&entries, Settings::new()), Ok(2));
+
+        entries[2].1 = Entry::Command("MoveDown".into());
+        assert_eq!(
+            replay(&entries, Settings::new()),
+            Err("0: expected MoveDown, got MoveUp".into())
+        );
+        entries.remove(2);
+        assert_eq!(
+            replay(&entries, Settings::new()),
+            Err("0: unexpected command MoveUp".into())
+        );
+    }
+
+    #[test]
+    fn replay_a_simulated_run() {
+        let path = std::env::temp_dir().join(format!("elevator-{}.journal", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut journal = Journal::open(path).unwrap();
+        let config = BuildingConfig::new();
+        let profile = TrafficProfile {
+            passengers: 20,
+            ..TrafficProfile::new()
+        };
+        let passengers = profile.generate(&config);
+        let mut controller =
+            LiftyController::with_transport(Simulation::new(&config, &passengers, 1_000_000));
+        journal.record(controller.now(), &Entry::Start(None));
+        let mut elevator = ElevatorAfterEvent::Idle(IdleElevator::new());
+        let mut events = 0;
+        while let Some(event) = controller.receive() {
+            let (command, next) = elevator.handle(event);
+            journal.handled(controller.now(), event, command.as_ref(), &next);
+            if let Some(command) = command {
+                controller.send(command);
+            }
+            elevator = next;
+            events += 1;
+        }
+        let entries = read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(replay(&entries, Settings::new()), Ok(events));
+    }
+}
@@ -0,0 +1,22 @@
+pub mod building;
+pub mod capture;
+pub mod config;
+pub mod controller;
+pub mod diagram;
+pub mod faults;
+pub mod fcfs;
+pub mod journal;
+pub mod logic;
+pub mod metrics;
+pub mod motion;
+#[cfg(kani)]
+mod proofs;
+pub mod rules;
+pub mod security;
+pub mod sim;
+pub mod snapshot;
+pub mod states;
+pub mod tla;
+pub mod traffic;
+pub mod transitions;
+pub mod tui;
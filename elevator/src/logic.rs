@@ -1,439 +1,1056 @@
+use crate::rules::{self, Action, Atom, Dir, Emit, EventKind, State, To};
+use crate::snapshot::Snapshot;
+use std::ops::BitOr;
+use std::time::Duration;
+
 pub const LOWEST_FLOOR: u8 = 1;
 pub const HIGHEST_FLOOR: u8 = 5;
 
 pub const NUM_FLOORS: u8 = HIGHEST_FLOOR - LOWEST_FLOOR + 1;
 
+// Car load is measured in percent of the rated load.  Above RATED_LOAD the
+// car must not move; above the bypass load it's too full to pick anyone up.
+pub const RATED_LOAD: u8 = 100;
+pub const DEFAULT_BYPASS_LOAD: u8 = 80;
+
+// Anti-nuisance: every passenger adds at least this much load, so a car with
+// more calls than that allows (plus one, for sensor slack) has had buttons
+// pressed by somebody who isn't going there.  The same goes for a run of
+// stops at car calls where nobody gets out.
+pub const MIN_PASSENGER_LOAD: u8 = 5;
+pub const NUISANCE_STOPS: u8 = 3;
+
+// How long an idle car waits before parking
+pub const DEFAULT_IDLE_DELAY: Duration = Duration::from_secs(30);
+
+// Where the car goes once it runs out of work
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdlePolicy {
+    StayPut,
+    ReturnToLobby,
+    ParkAtBusiestFloor,
+}
+
+// Which side of the car a door (or the button for it) is on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Front,
+    Rear,
+}
+
+// A set of car doors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Doors {
+    pub front: bool,
+    pub rear: bool,
+}
+
+impl Doors {
+    pub const NONE: Doors = Doors {
+        front: false,
+        rear: false,
+    };
+    pub const FRONT: Doors = Doors {
+        front: true,
+        rear: false,
+    };
+    pub const REAR: Doors = Doors {
+        front: false,
+        rear: true,
+    };
+    pub const BOTH: Doors = Doors {
+        front: true,
+        rear: true,
+    };
+
+    pub fn is_empty(self) -> bool {
+        !self.front && !self.rear
+    }
+
+    pub fn has(self, side: Side) -> bool {
+        match side {
+            Side::Front => self.front,
+            Side::Rear => self.rear,
+        }
+    }
+
+    pub fn contains(self, other: Doors) -> bool {
+        (self.front || !other.front) && (self.rear || !other.rear)
+    }
+
+    pub fn without(self, side: Side) -> Doors {
+        match side {
+            Side::Front => Doors {
+                front: false,
+                ..self
+            },
+            Side::Rear => Doors {
+                rear: false,
+                ..self
+            },
+        }
+    }
+
+    pub fn sides(self) -> impl Iterator<Item = Side> {
+        [Side::Front, Side::Rear]
+            .into_iter()
+            .filter(move |&side| self.has(side))
+    }
+}
+
+impl From<Side> for Doors {
+    fn from(side: Side) -> Doors {
+        match side {
+            Side::Front => Doors::FRONT,
+            Side::Rear => Doors::REAR,
+        }
+    }
+}
+
+impl BitOr for Doors {
+    type Output = Doors;
+
+    fn bitor(self, other: Doors) -> Doors {
+        Doors {
+            front: self.front || other.front,
+            rear: self.rear || other.rear,
+        }
+    }
+}
+
+// The doors the car has on each floor.  Floors without any are an express
+// zone that the car passes straight through.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServedFloors([Doors; NUM_FLOORS as usize]);
+
+impl ServedFloors {
+    pub fn all() -> Self {
+        ServedFloors([Doors::FRONT; NUM_FLOORS as usize])
+    }
+
+    // Front doors on the given floors only
+    pub fn only(floors: &[u8]) -> Self {
+        ServedFloors::with_doors(floors, &[])
+    }
+
+    pub fn with_doors(front: &[u8], rear: &[u8]) -> Self {
+        let mut doors = [Doors::NONE; NUM_FLOORS as usize];
+        for &floor in front {
+            doors[(floor - LOWEST_FLOOR) as usize].front = true;
+        }
+        for &floor in rear {
+            doors[(floor - LOWEST_FLOOR) as usize].rear = true;
+        }
+        ServedFloors(doors)
+    }
+
+    pub fn doors(&self, floor: u8) -> Doors {
+        if (LOWEST_FLOOR..=HIGHEST_FLOOR).contains(&floor) {
+            self.0[(floor - LOWEST_FLOOR) as usize]
+        } else {
+            Doors::NONE
+        }
+    }
+
+    // The door to open when nobody asked for a particular one
+    pub fn default_door(&self, floor: u8) -> Doors {
+        if self.doors(floor).front {
+            Doors::FRONT
+        } else {
+            Doors::REAR
+        }
+    }
+
+    pub fn serves(&self, floor: u8) -> bool {
+        !self.doors(floor).is_empty()
+    }
+
+    pub fn floors(&self) -> impl Iterator<Item = u8> + '_ {
+        (LOWEST_FLOOR..=HIGHEST_FLOOR).filter(|&floor| self.serves(floor))
+    }
+
+    pub fn lowest(&self) -> u8 {
+        self.floors().next().unwrap_or(LOWEST_FLOOR)
+    }
+
+    pub fn highest(&self) -> u8 {
+        self.floors().last().unwrap_or(HIGHEST_FLOOR)
+    }
+
+    // Next served floor going up (or down) from `floor`
+    pub fn above(&self, floor: u8) -> Option<u8> {
+        self.floors().find(|&f| f > floor)
+    }
+
+    pub fn below(&self, floor: u8) -> Option<u8> {
+        self.floors().filter(|&f| f < floor).last()
+    }
+
+    // Is there a button for this call?  Buttons only exist on the side of a
+    // door, and there's no going up from the highest served floor or down
+    // from the lowest.
+    pub fn valid_call(&self, event: Event) -> bool {
+        match event {
+            Event::Panel(floor, side) | Event::PanelCancel(floor, side) => {
+                self.doors(floor).has(side)
+            }
+            Event::Up(floor, side) => self.doors(floor).has(side) && floor < self.highest(),
+            Event::Down(floor, side) => self.doors(floor).has(side) && floor > self.lowest(),
+            _ => true,
+        }
+    }
+}
+
+impl Default for ServedFloors {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+// Tunable parameters of the elevator algorithm
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub served: ServedFloors,
+    pub bypass_load: u8,
+    pub idle_policy: IdlePolicy,
+    pub idle_delay: Duration,
+    pub anti_nuisance: bool, // needs a working load sensor
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Settings {
+            served: ServedFloors::all(),
+            bypass_load: DEFAULT_BYPASS_LOAD,
+            idle_policy: IdlePolicy::StayPut,
+            idle_delay: DEFAULT_IDLE_DELAY,
+            anti_nuisance: false,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Command {
     MoveUp,
     MoveDown,
-    StopAndOpen { floor: u8, direction: Indicator },
-    OpenDoor { floor: u8, direction: Indicator },
-    ChangeIndicator { floor: u8, direction: Indicator },
+    StopAndOpen {
+        floor: u8,
+        direction: Indicator,
+        doors: Doors,
+    },
+    OpenDoor {
+        floor: u8,
+        direction: Indicator,
+        doors: Doors,
+    },
+    ChangeIndicator {
+        floor: u8,
+        direction: Indicator,
+    },
     RejectEvent(Event),
+    Overload(bool),
+    StartIdleTimer(Duration),
+    CancelCarCalls(Vec<(u8, Side)>),
+    Sequence(Vec<Command>),
 }
 
-#[derive(Debug, PartialEq)]
+impl Command {
+    pub fn sequence(mut commands: Vec<Command>) -> Option<Command> {
+        match commands.len() {
+            0 => None,
+            1 => commands.pop(),
+            _ => Some(Command::Sequence(commands)),
+        }
+    }
+
+    // Cancel car calls before carrying on with `command`
+    pub fn cancelling(calls: Vec<(u8, Side)>, command: Option<Command>) -> Option<Command> {
+        match command {
+            _ if calls.is_empty() => command,
+            None => Some(Command::CancelCarCalls(calls)),
+            Some(command) => Some(Command::Sequence(vec![
+                Command::CancelCarCalls(calls),
+                command,
+            ])),
+        }
+    }
+}
+
+// What the car was doing when the controller took over from another one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activity {
+    Closed,                 // Standing at a floor with the doors shut
+    Open(Doors, Indicator), // Doors open (or opening, or closing), showing a direction
+    MovingUp,
+    MovingDown,
+}
+
+// The hardware as the controller found it on taking over without a reset.
+// Lit buttons are calls that are still waiting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HardwareState {
+    pub floor: u8, // The floor the car is at, or for a moving car the next it can stop at
+    pub activity: Activity,
+    pub calls: Vec<Event>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Indicator {
     Up,
     Down,
     Off,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Event {
-    Panel(u8),
-    Up(u8),
-    Down(u8),
+    Panel(u8, Side),
+    Up(u8, Side),
+    Down(u8, Side),
     Arrived(u8),
     Closed(u8),
+    Load(u8),
+    IdleTimeout,
+    PanelCancel(u8, Side), // Panel button pressed twice in quick succession
+    Credential(u32),       // Card presented at the reader in the car
+    Overshot(u8),          // Too late to stop at the floor; the car is running past it
+    Fault(Failure),        // The hardware stopped answering (see controller.rs)
+    Reset,                 // Lifty was reset: car on the lowest floor, doors shut, lights off
 }
 
-#[derive(Debug, Clone)]
-pub enum ElevatorState {
-    Idle,
-    MovingUp,
-    MovingDown,
-    Open,
-    OpenUp,
-    OpenDown,
+// What the hardware failed to do in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    Doors,  // The doors didn't finish opening or closing
+    Motion, // The car didn't reach the next floor, or didn't stop at it
 }
 
-#[derive(Debug)]
-pub struct Elevator {
-    state: ElevatorState,
+// The calls a stop was about to answer, kept until the stop is known to
+// have been made in time.  If the car overshoots they're put back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stop {
     pub floor: u8,
-    destinations: [bool; NUM_FLOORS as usize],
-    up_requests: [bool; NUM_FLOORS as usize],
-    down_requests: [bool; NUM_FLOORS as usize],
+    pub going_up: bool,
+    pub destination: Doors,
+    pub up: Doors,
+    pub down: Doors,
+    pub stop_load: Option<u8>,
 }
 
-impl Elevator {
+// Common interface to the different implementations of the elevator
+// algorithm, so that they can be driven (and compared) interchangeably.
+pub trait Algorithm {
+    fn handle(&mut self, event: Event) -> Option<Command>;
+
+    // For algorithms that care when an event happened.  `now` is the
+    // controller's clock.
+    fn handle_at(&mut self, event: Event, _now: Duration) -> Option<Command> {
+        self.handle(event)
+    }
+}
+
+// The name a state goes by in snapshots, diagrams and the dashboard
+fn state_name(state: &ElevatorState) -> &'static str {
+    match state {
+        ElevatorState::Idle => "idle",
+        ElevatorState::MovingUp => "moving-up",
+        ElevatorState::MovingDown => "moving-down",
+        ElevatorState::Open => "open",
+        ElevatorState::OpenUp => "open-up",
+        ElevatorState::OpenDown => "open-down",
+        ElevatorState::Fault(Failure::Doors) => "fault-doors",
+        ElevatorState::Fault(Failure::Motion) => "fault-motion",
+    }
+}
+
+// The calls and the rest of what the car keeps track of, whatever state
+// it's in.  Both machines keep their calls in one of these: `Elevator`
+// below, following the table in rules.rs, and the typestates in states.rs.
+#[derive(Debug, Clone)]
+pub struct ElevatorData {
+    pub floor: u8,
+    pub(crate) destinations: [bool; NUM_FLOORS as usize],
+    pub(crate) up_requests: [bool; NUM_FLOORS as usize],
+    pub(crate) down_requests: [bool; NUM_FLOORS as usize],
+    // Which doors each call is waiting at
+    pub(crate) destination_doors: [Doors; NUM_FLOORS as usize],
+    pub(crate) up_doors: [Doors; NUM_FLOORS as usize],
+    pub(crate) down_doors: [Doors; NUM_FLOORS as usize],
+    pub(crate) open_doors: Doors,
+    pub load: u8,
+    pub(crate) parking: Option<u8>,
+    pub(crate) demand: [u32; NUM_FLOORS as usize],
+    pub(crate) stop_load: Option<u8>, // load on stopping for a car call, until someone gets out
+    pub(crate) empty_stops: u8,
+    pub(crate) stop: Option<Stop>, // Stop just ordered, in case the car can't make it
+    pub(crate) settings: Settings,
+}
+
+impl ElevatorData {
     pub fn new() -> Self {
-        let elevator = Elevator {
-            state: ElevatorState::Idle,
+        ElevatorData::with_settings(Settings::new())
+    }
+
+    pub fn with_settings(settings: Settings) -> Self {
+        ElevatorData {
             floor: LOWEST_FLOOR,
             destinations: [false; NUM_FLOORS as usize],
             up_requests: [false; NUM_FLOORS as usize],
             down_requests: [false; NUM_FLOORS as usize],
+            destination_doors: [Doors::NONE; NUM_FLOORS as usize],
+            up_doors: [Doors::NONE; NUM_FLOORS as usize],
+            down_doors: [Doors::NONE; NUM_FLOORS as usize],
+            open_doors: Doors::NONE,
+            load: 0,
+            parking: None,
+            demand: [0; NUM_FLOORS as usize],
+            stop_load: None,
+            empty_stops: 0,
+            stop: None,
+            settings,
+        }
+    }
+
+    // Taking over from another controller: where the car is and what it's
+    // doing.  A moving car is between floors, on its way to the next one it
+    // can stop at.  With nothing else to do, it stops there.  Hardware that
+    // makes no sense for this building (say the settings changed since the
+    // last controller, or Lifty is confused) is an error, and the caller
+    // starts afresh instead.
+    pub(crate) fn resume(
+        settings: Settings,
+        hardware: &HardwareState,
+    ) -> Result<(Self, ElevatorState), String> {
+        let mut data = ElevatorData::with_settings(settings);
+        let served = data.settings.served;
+        let floor = hardware.floor;
+        if !(LOWEST_FLOOR..=HIGHEST_FLOOR).contains(&floor) || !served.serves(floor) {
+            return Err(format!("car at floor {floor}, which isn't served"));
+        }
+        data.floor = floor;
+        let state = match hardware.activity {
+            Activity::Closed => ElevatorState::Idle,
+            Activity::Open(doors, _)
+                if doors.is_empty() || !served.doors(floor).contains(doors) =>
+            {
+                return Err(format!("doors {doors:?} open at floor {floor}"));
+            }
+            Activity::Open(doors, direction) => {
+                data.open_doors = doors;
+                // The indicator can't show a way the car can't go.  It goes
+                // out when the doors close anyway.
+                match direction {
+                    Indicator::Up if served.above(floor).is_some() => ElevatorState::OpenUp,
+                    Indicator::Down if served.below(floor).is_some() => ElevatorState::OpenDown,
+                    _ => ElevatorState::Open,
+                }
+            }
+            Activity::MovingUp => {
+                let Some(below) = served.below(floor) else {
+                    return Err(format!("car moving up to floor {floor} from nowhere"));
+                };
+                data.floor = below;
+                data.parking = Some(floor);
+                ElevatorState::MovingUp
+            }
+            Activity::MovingDown => {
+                let Some(above) = served.above(floor) else {
+                    return Err(format!("car moving down to floor {floor} from nowhere"));
+                };
+                data.floor = above;
+                data.parking = Some(floor);
+                ElevatorState::MovingDown
+            }
         };
-        elevator.check_invariants();
-        elevator
+        Ok((data, state))
     }
 
-    pub fn handle(&mut self, event: Event) -> Option<Command> {
-        let (command, new_state) = match &self.state {
-            ElevatorState::Idle => self.handle_idle(event),
-            ElevatorState::MovingUp => self.handle_moving_up(event),
-            ElevatorState::MovingDown => self.handle_moving_down(event),
-            ElevatorState::Open => self.handle_open(event),
-            ElevatorState::OpenUp => self.handle_open_up(event),
-            ElevatorState::OpenDown => self.handle_open_down(event),
+    // Floors are numbered from LOWEST_FLOOR, the request tables from 0
+    pub fn set_destination(&mut self, floor: usize, value: bool) {
+        self.destinations[floor - LOWEST_FLOOR as usize] = value;
+    }
+
+    pub fn get_destination(&self, floor: usize) -> bool {
+        self.destinations[floor - LOWEST_FLOOR as usize]
+    }
+
+    pub fn set_up_request(&mut self, floor: usize, value: bool) {
+        self.up_requests[floor - LOWEST_FLOOR as usize] = value;
+    }
+
+    pub fn get_up_request(&self, floor: usize) -> bool {
+        self.up_requests[floor - LOWEST_FLOOR as usize]
+    }
+
+    pub fn set_down_request(&mut self, floor: usize, value: bool) {
+        self.down_requests[floor - LOWEST_FLOOR as usize] = value;
+    }
+
+    pub fn get_down_request(&self, floor: usize) -> bool {
+        self.down_requests[floor - LOWEST_FLOOR as usize]
+    }
+
+    // Count hall calls towards the busiest floor, and remember which side
+    // of the car a call was made from, so that the right doors open when
+    // it's answered.
+    pub(crate) fn record_call(&mut self, event: Event) {
+        if let Event::Up(floor, _) | Event::Down(floor, _) = event {
+            let demand = &mut self.demand[(floor - LOWEST_FLOOR) as usize];
+            *demand = demand.saturating_add(1);
+        }
+        let (doors, floor, side) = match event {
+            Event::Panel(floor, side) => (&mut self.destination_doors, floor, side),
+            Event::Up(floor, side) => (&mut self.up_doors, floor, side),
+            Event::Down(floor, side) => (&mut self.down_doors, floor, side),
+            _ => return,
         };
+        let index = (floor - LOWEST_FLOOR) as usize;
+        doors[index] = doors[index] | side.into();
+    }
 
-        self.state = new_state;
-        self.check_invariants();
-        command
+    // The calls a stop at `floor` would answer, before it answers them
+    pub(crate) fn stop_at(&self, floor: u8, going_up: bool) -> Stop {
+        let index = (floor - LOWEST_FLOOR) as usize;
+        Stop {
+            floor,
+            going_up,
+            destination: self.destination_doors[index],
+            up: self.up_doors[index],
+            down: self.down_doors[index],
+            stop_load: self.stop_load,
+        }
     }
 
-    fn handle_idle(&mut self, event: Event) -> (Option<Command>, ElevatorState) {
-        match event {
-            Event::Panel(floor) => {
-                if floor != self.floor {
-                    self.set_destination(floor as usize, true);
-                    if floor > self.floor {
-                        (Some(Command::MoveUp), ElevatorState::MovingUp)
-                    } else {
-                        (Some(Command::MoveDown), ElevatorState::MovingDown)
-                    }
-                } else {
-                    (
-                        Some(Command::OpenDoor {
-                            floor,
-                            direction: Indicator::Off,
-                        }),
-                        ElevatorState::Open,
-                    )
-                }
-            }
-            Event::Up(floor) => {
-                if floor != self.floor {
-                    self.set_up_request(floor as usize, true);
-                    if floor > self.floor {
-                        (Some(Command::MoveUp), ElevatorState::MovingUp)
-                    } else {
-                        (Some(Command::MoveDown), ElevatorState::MovingDown)
-                    }
-                } else {
-                    (
-                        Some(Command::OpenDoor {
-                            floor,
-                            direction: Indicator::Up,
-                        }),
-                        ElevatorState::OpenUp,
-                    )
-                }
-            }
-            Event::Down(floor) => {
-                if floor != self.floor {
-                    self.set_down_request(floor as usize, true);
-                    if floor > self.floor {
-                        (Some(Command::MoveUp), ElevatorState::MovingUp)
-                    } else {
-                        (Some(Command::MoveDown), ElevatorState::MovingDown)
-                    }
-                } else {
-                    (
-                        Some(Command::OpenDoor {
-                            floor,
-                            direction: Indicator::Down,
-                        }),
-                        ElevatorState::OpenDown,
-                    )
-                }
-            }
-            _ => panic!("Unexpected event: {:?}", event),
+    // Keep track of what a command leaves behind: the doors it opens, and
+    // the stop it orders in case the car can't make it
+    pub(crate) fn ordered(&mut self, command: &Option<Command>, stopping: Option<Stop>) {
+        if let Some(Command::StopAndOpen { doors, .. } | Command::OpenDoor { doors, .. }) = command
+        {
+            self.open_doors = *doors;
+        }
+        if let Some(Command::StopAndOpen { .. }) = command {
+            self.stop = stopping;
         }
     }
 
-    fn handle_moving_up(&mut self, event: Event) -> (Option<Command>, ElevatorState) {
-        match event {
-            Event::Panel(floor) => {
-                self.set_destination(floor as usize, true);
-                (None, ElevatorState::MovingUp)
-            }
-            Event::Up(floor) => {
-                self.set_up_request(floor as usize, true);
-                (None, ElevatorState::MovingUp)
+    // The calls waiting to be answered
+    pub fn calls(&self) -> Vec<Event> {
+        let tables = [
+            (
+                Event::Panel as fn(u8, Side) -> Event,
+                &self.destination_doors,
+            ),
+            (Event::Up, &self.up_doors),
+            (Event::Down, &self.down_doors),
+        ];
+        let mut calls = Vec::new();
+        for (call, doors) in tables {
+            for floor in LOWEST_FLOOR..=HIGHEST_FLOOR {
+                let sides = doors[(floor - LOWEST_FLOOR) as usize].sides();
+                calls.extend(sides.map(|side| call(floor, side)));
             }
-            Event::Down(floor) => {
-                self.set_down_request(floor as usize, true);
-                (None, ElevatorState::MovingUp)
+        }
+        calls
+    }
+
+    // What's worth saving in case the controller dies.  The stop just
+    // ordered isn't: it only matters until the car reports arriving.
+    pub(crate) fn snapshot(&self, state: &ElevatorState) -> Snapshot {
+        Snapshot {
+            state: state_name(state).to_string(),
+            floor: self.floor,
+            load: self.load,
+            parking: self.parking,
+            demand: self.demand,
+            stop_load: self.stop_load,
+            empty_stops: self.empty_stops,
+            calls: self.calls(),
+        }
+    }
+
+    // Carry on with what a controller that died knew and the hardware
+    // doesn't.  How busy each floor has been always carries over.  Calls
+    // come back as their buttons are pressed again (see
+    // `LiftyController::press`), counting towards the demand as they do, so
+    // they're taken off it here.  Where the car was parking and who got out
+    // at the last stops only carry over if the car is still where the
+    // snapshot left it, in the same state.
+    pub(crate) fn restore(&mut self, state: &ElevatorState, snapshot: &Snapshot) {
+        let mut demand = snapshot.demand;
+        for &call in &snapshot.calls {
+            if let Event::Up(floor, _) | Event::Down(floor, _) = call {
+                let count = &mut demand[(floor - LOWEST_FLOOR) as usize];
+                *count = count.saturating_sub(1);
             }
-            Event::Arrived(floor) => {
-                self.floor = floor;
-                if self.get_destination(floor as usize) {
-                    if self.highest_request() > floor || self.get_up_request(floor as usize) {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Up,
-                            }),
-                            ElevatorState::OpenUp,
-                        )
-                    } else if self.have_requests() && self.highest_request() <= floor {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Down,
-                            }),
-                            ElevatorState::OpenDown,
-                        )
-                    } else {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Off,
-                            }),
-                            ElevatorState::Open,
-                        )
-                    }
-                } else if self.get_up_request(floor as usize) && self.highest_request() == floor {
-                    (
-                        Some(Command::StopAndOpen {
-                            floor,
-                            direction: Indicator::Down,
-                        }),
-                        ElevatorState::OpenDown,
-                    )
-                } else {
-                    (None, ElevatorState::MovingUp)
-                }
+        }
+        for (count, restored) in self.demand.iter_mut().zip(demand) {
+            *count = count.saturating_add(restored);
+        }
+        if snapshot.state != state_name(state) || snapshot.floor != self.floor {
+            return;
+        }
+        // A car taken over on the move parks at the next floor unless told
+        // otherwise
+        let served = &self.settings.served;
+        if let (Some(_), Some(parking)) = (self.parking, snapshot.parking) {
+            if served.serves(parking) {
+                self.parking = Some(parking);
             }
-            _ => panic!("Unexpected event: {:?}", event),
         }
+        self.stop_load = snapshot.stop_load;
+        self.empty_stops = snapshot.empty_stops;
     }
 
-    fn handle_moving_down(&mut self, event: Event) -> (Option<Command>, ElevatorState) {
-        self.set_destination(self.floor as usize, false);
+    pub(crate) fn forget_calls(&mut self) {
+        self.destinations = [false; NUM_FLOORS as usize];
+        self.up_requests = [false; NUM_FLOORS as usize];
+        self.down_requests = [false; NUM_FLOORS as usize];
+        self.parking = None;
+        self.stop_load = None;
+        self.empty_stops = 0;
+        self.open_doors = Doors::NONE;
+        self.forget_answered_sides();
+    }
 
-        match event {
-            Event::Panel(floor) => {
-                self.set_destination(floor as usize, true);
-                (None, ElevatorState::MovingDown)
+    // Once a call is answered (or rejected) its sides are no longer wanted
+    pub(crate) fn forget_answered_sides(&mut self) {
+        for index in 0..NUM_FLOORS as usize {
+            if !self.destinations[index] {
+                self.destination_doors[index] = Doors::NONE;
             }
-            Event::Up(floor) => {
-                self.set_up_request(floor as usize, true);
-                (None, ElevatorState::MovingDown)
+            if !self.up_requests[index] {
+                self.up_doors[index] = Doors::NONE;
             }
-            Event::Down(floor) => {
-                self.set_down_request(floor as usize, true);
-                (None, ElevatorState::MovingDown)
+            if !self.down_requests[index] {
+                self.down_doors[index] = Doors::NONE;
             }
-            Event::Arrived(floor) => {
-                self.floor = floor;
-                if self.get_destination(floor as usize) {
-                    if self.lowest_request() < floor || self.get_down_request(floor as usize) {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Down,
-                            }),
-                            ElevatorState::OpenDown,
-                        )
-                    } else if self.have_requests() && self.lowest_request() >= floor {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Up,
-                            }),
-                            ElevatorState::OpenUp,
-                        )
-                    } else {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Off,
-                            }),
-                            ElevatorState::Open,
-                        )
-                    }
-                } else if self.get_down_request(floor as usize) {
-                    (
-                        Some(Command::StopAndOpen {
-                            floor,
-                            direction: Indicator::Down,
-                        }),
-                        ElevatorState::OpenDown,
-                    )
-                } else if self.get_up_request(floor as usize) && self.lowest_request() == floor {
-                    (
-                        Some(Command::StopAndOpen {
-                            floor,
-                            direction: Indicator::Up,
-                        }),
-                        ElevatorState::OpenUp,
-                    )
-                } else {
-                    (None, ElevatorState::MovingDown)
-                }
+        }
+    }
+
+    // The doors to open at `floor`: those of the car call and of the hall
+    // call in the direction shown on the indicator.  Stops that nobody asked
+    // for (like parking) use the landing's usual door.
+    pub(crate) fn doors_at(&self, floor: u8, direction: Indicator) -> Doors {
+        let index = (floor - LOWEST_FLOOR) as usize;
+        let doors = self.destination_doors[index]
+            | match direction {
+                Indicator::Up => self.up_doors[index],
+                Indicator::Down => self.down_doors[index],
+                Indicator::Off => Doors::NONE,
+            };
+        if doors.is_empty() {
+            self.settings.served.default_door(floor)
+        } else {
+            doors
+        }
+    }
+
+    pub fn overloaded(&self) -> bool {
+        self.load > RATED_LOAD
+    }
+
+    // Too full to pick anyone else up.  Hall calls stay registered and are
+    // answered once there's room again.
+    pub fn bypassing_hall_calls(&self) -> bool {
+        self.load > self.settings.bypass_load
+    }
+
+    // The load can change at any time, but it only matters with the doors
+    // open: an overloaded car holds them open and sounds the warning until
+    // somebody gets out.
+    pub(crate) fn weigh(&mut self, load: u8, state: &ElevatorState) -> Option<Command> {
+        let was_overloaded = self.overloaded();
+        if self.stop_load.is_some_and(|stop_load| load < stop_load) {
+            self.stop_load = None;
+            self.empty_stops = 0;
+        }
+        self.load = load;
+        match state {
+            ElevatorState::Open | ElevatorState::OpenUp | ElevatorState::OpenDown
+                if self.overloaded() || was_overloaded =>
+            {
+                Some(Command::Overload(self.overloaded()))
             }
-            _ => panic!("Unexpected event: {:?}", event),
+            _ => None,
+        }
+    }
+
+    // A double-pressed panel button takes back the car call.  The car can't
+    // stop between floors, so if that leaves nothing ahead it stops at the
+    // next floor (like a parking floor, with no button lit) and goes idle.
+    pub(crate) fn cancel_car_call(
+        &mut self,
+        floor: u8,
+        side: Side,
+        state: &ElevatorState,
+    ) -> Command {
+        let index = (floor - LOWEST_FLOOR) as usize;
+        if !self.destination_doors[index].has(side) {
+            return Command::RejectEvent(Event::PanelCancel(floor, side));
+        }
+        // The call stands while the button on the other side is still lit
+        self.destination_doors[index] = self.destination_doors[index].without(side);
+        if self.destination_doors[index].is_empty() {
+            self.set_destination(floor as usize, false);
         }
+        match state {
+            ElevatorState::MovingUp => self.stop_if_nothing_ahead(true),
+            ElevatorState::MovingDown => self.stop_if_nothing_ahead(false),
+            _ => {}
+        }
+        Command::CancelCarCalls(vec![(floor, side)])
     }
 
-    fn handle_open(&mut self, event: Event) -> (Option<Command>, ElevatorState) {
-        self.set_destination(self.floor as usize, false);
+    // A moving car with nothing further on stops at the next floor
+    fn stop_if_nothing_ahead(&mut self, going_up: bool) {
+        let served = self.settings.served;
+        if going_up && (!self.have_requests() || self.highest_request() <= self.floor) {
+            self.parking = served.above(self.floor);
+        }
+        if !going_up && (!self.have_requests() || self.lowest_request() >= self.floor) {
+            self.parking = served.below(self.floor);
+        }
+    }
 
-        match event {
-            Event::Panel(floor) => {
-                if floor != self.floor {
-                    self.set_destination(floor as usize, true);
-                    if floor > self.floor {
-                        (
-                            Some(Command::ChangeIndicator {
-                                floor,
-                                direction: Indicator::Up,
-                            }),
-                            ElevatorState::OpenUp,
-                        )
-                    } else {
-                        (
-                            Some(Command::ChangeIndicator {
-                                floor,
-                                direction: Indicator::Down,
-                            }),
-                            ElevatorState::OpenDown,
-                        )
-                    }
-                } else {
-                    (Some(Command::RejectEvent(event)), ElevatorState::Open)
-                }
+    // The stop was ordered too late and the car is carrying on past the
+    // floor.  Its calls are still waiting, so put them back.  If there's
+    // nothing further on, the car stops at the next floor and comes back.
+    pub(crate) fn overshot(&mut self, stop: Stop) {
+        let index = (stop.floor - LOWEST_FLOOR) as usize;
+        self.destination_doors[index] = self.destination_doors[index] | stop.destination;
+        self.up_doors[index] = self.up_doors[index] | stop.up;
+        self.down_doors[index] = self.down_doors[index] | stop.down;
+        self.destinations[index] = !self.destination_doors[index].is_empty();
+        self.up_requests[index] = !self.up_doors[index].is_empty();
+        self.down_requests[index] = !self.down_doors[index].is_empty();
+        self.stop_load = stop.stop_load;
+        self.open_doors = Doors::NONE;
+        self.stop_if_nothing_ahead(stop.going_up);
+    }
+
+    // Called as the doors close.  Returns the car calls that were cancelled.
+    pub(crate) fn cancel_nuisance_calls(&mut self) -> Vec<(u8, Side)> {
+        if self.overloaded() {
+            // The doors are going to open again
+            return Vec::new();
+        }
+        let empty_stop = self.stop_load.take().is_some();
+        if !self.settings.anti_nuisance {
+            return Vec::new();
+        }
+        if empty_stop {
+            self.empty_stops += 1;
+        }
+
+        let calls: Vec<u8> = (LOWEST_FLOOR..=HIGHEST_FLOOR)
+            .filter(|&floor| self.get_destination(floor as usize))
+            .collect();
+        let plausible = (self.load / MIN_PASSENGER_LOAD) as usize + 1;
+        if calls.len() > plausible || self.empty_stops >= NUISANCE_STOPS {
+            let buttons = calls
+                .into_iter()
+                .flat_map(|floor| {
+                    let doors = self.destination_doors[(floor - LOWEST_FLOOR) as usize];
+                    doors.sides().map(move |side| (floor, side))
+                })
+                .collect();
+            self.destinations = [false; NUM_FLOORS as usize];
+            self.destination_doors = [Doors::NONE; NUM_FLOORS as usize];
+            self.empty_stops = 0;
+            buttons
+        } else {
+            Vec::new()
+        }
+    }
+
+    // The hardware has stopped answering, so there's no telling what the car
+    // or the doors will do next.  Drop every call, turning its light off so
+    // that nobody waits for a car that isn't coming.
+    pub(crate) fn drop_calls(&mut self) -> Option<Command> {
+        let mut commands = Vec::new();
+        let car_calls: Vec<(u8, Side)> = (LOWEST_FLOOR..=HIGHEST_FLOOR)
+            .flat_map(|floor| {
+                let doors = self.destination_doors[(floor - LOWEST_FLOOR) as usize];
+                doors.sides().map(move |side| (floor, side))
+            })
+            .collect();
+        if !car_calls.is_empty() {
+            commands.push(Command::CancelCarCalls(car_calls));
+        }
+        for floor in LOWEST_FLOOR..=HIGHEST_FLOOR {
+            let index = (floor - LOWEST_FLOOR) as usize;
+            for side in self.up_doors[index].sides() {
+                commands.push(Command::RejectEvent(Event::Up(floor, side)));
             }
-            Event::Up(floor) => {
-                if floor != self.floor {
-                    self.set_up_request(floor as usize, true);
-                    (None, ElevatorState::Open)
-                } else {
-                    (
-                        Some(Command::ChangeIndicator {
-                            floor,
-                            direction: Indicator::Up,
-                        }),
-                        ElevatorState::OpenUp,
-                    )
-                }
+            for side in self.down_doors[index].sides() {
+                commands.push(Command::RejectEvent(Event::Down(floor, side)));
             }
-            Event::Down(floor) => {
-                if floor != self.floor {
-                    self.set_down_request(floor as usize, true);
-                    (None, ElevatorState::Open)
-                } else {
+        }
+        self.forget_calls();
+        Command::sequence(commands)
+    }
+
+    // Lifty has been reset, so start again from where that leaves the car.
+    // The controller presses the buttons of the calls that were waiting
+    // again, which lights them and registers them afresh.
+    pub(crate) fn reset(&mut self) {
+        self.forget_calls();
+        self.floor = LOWEST_FLOOR;
+        self.load = 0;
+    }
+
+    // Ask the controller to wake us up if nothing happens for a while, so
+    // that the car can be parked.
+    pub(crate) fn idle_timer(&self) -> Option<Command> {
+        match self.settings.idle_policy {
+            IdlePolicy::StayPut => None,
+            _ => Some(Command::StartIdleTimer(self.settings.idle_delay)),
+        }
+    }
+
+    pub fn parking_floor(&self) -> u8 {
+        match self.settings.idle_policy {
+            IdlePolicy::StayPut => self.floor,
+            IdlePolicy::ReturnToLobby => self.settings.served.lowest(),
+            // Ties (including no calls at all) favour the current floor
+            IdlePolicy::ParkAtBusiestFloor => (self.settings.served.floors())
+                .max_by_key(|&floor| {
                     (
-                        Some(Command::ChangeIndicator {
-                            floor,
-                            direction: Indicator::Down,
-                        }),
-                        ElevatorState::OpenDown,
+                        self.demand[(floor - LOWEST_FLOOR) as usize],
+                        floor == self.floor,
                     )
-                }
-            }
-            Event::Closed(_) => {
-                if !self.have_requests() {
-                    (None, ElevatorState::Idle)
-                } else {
-                    let next = self.highest_request();
-                    if next > self.floor {
-                        (Some(Command::MoveUp), ElevatorState::MovingUp)
-                    } else if next < self.floor {
-                        (Some(Command::MoveDown), ElevatorState::MovingDown)
-                    } else {
-                        panic!("I'm supposed to move!")
-                    }
-                }
-            }
-            _ => panic!("Unexpected event: {:?}", event),
+                })
+                .unwrap(),
         }
     }
 
-    fn handle_open_up(&mut self, event: Event) -> (Option<Command>, ElevatorState) {
-        self.set_destination(self.floor as usize, false);
-        self.set_up_request(self.floor as usize, false);
+    // The parking floor is treated like a car call without a button.  A real
+    // call takes over when the car reaches the next floor; dropping it any
+    // earlier could leave the car moving away from all of its requests.
+    pub(crate) fn abandon_parking(&mut self) {
+        if self.have_calls() {
+            self.parking = None;
+        }
+    }
 
-        match event {
-            Event::Panel(floor) => {
-                if floor != self.floor {
-                    self.set_destination(floor as usize, true);
-                    (None, ElevatorState::OpenUp)
-                } else {
-                    (Some(Command::RejectEvent(event)), ElevatorState::OpenUp)
-                }
+    fn have_calls(&self) -> bool {
+        self.destinations.iter().any(|&x| x)
+            || self.up_requests.iter().any(|&x| x)
+            || self.down_requests.iter().any(|&x| x)
+    }
+
+    pub fn have_requests(&self) -> bool {
+        self.have_calls() || self.parking.is_some()
+    }
+
+    fn has_request_at_floor(&self, floor: u8) -> bool {
+        self.get_destination(floor as usize)
+            || self.get_up_request(floor as usize)
+            || self.get_down_request(floor as usize)
+            || self.parking == Some(floor)
+    }
+
+    // With no requests at all these are the car's own floor: there's
+    // nowhere to go in either direction
+    pub fn highest_request(&self) -> u8 {
+        (LOWEST_FLOOR..=HIGHEST_FLOOR)
+            .rev()
+            .find(|&floor| self.has_request_at_floor(floor))
+            .unwrap_or(self.floor)
+    }
+
+    pub fn lowest_request(&self) -> u8 {
+        (LOWEST_FLOOR..=HIGHEST_FLOOR)
+            .find(|&floor| self.has_request_at_floor(floor))
+            .unwrap_or(self.floor)
+    }
+
+    // Calls, arrivals, closing doors and the idle timer in `state`, as the
+    // table in rules.rs says.  Both state machines come here for them.
+    pub(crate) fn follow_rules(&mut self, state: State, event: Event) -> (Option<Command>, State) {
+        let kind = match event {
+            Event::Panel(..) => EventKind::Panel,
+            Event::Up(..) => EventKind::Up,
+            Event::Down(..) => EventKind::Down,
+            Event::Arrived(_) => EventKind::Arrived,
+            Event::Closed(_) => EventKind::Closed,
+            Event::IdleTimeout => EventKind::IdleTimeout,
+            _ => panic!("Unexpected event: {:?}", event),
+        };
+        for &(_, _, action) in rules::FIRST
+            .iter()
+            .filter(|&&(s, k, _)| (s, k) == (state, kind))
+        {
+            self.act(action, event);
+        }
+        let Some(rule) = rules::find(state, kind, |atom| self.holds(atom, event)) else {
+            panic!("Unexpected event: {:?}", event)
+        };
+        for &action in rule.actions {
+            self.act(action, event);
+        }
+
+        let floor = self.floor;
+        let indicator = |dir| match dir {
+            Dir::Up => Indicator::Up,
+            Dir::Down => Indicator::Down,
+            Dir::Off => Indicator::Off,
+            Dir::Call => match event {
+                Event::Up(..) => Indicator::Up,
+                Event::Down(..) => Indicator::Down,
+                _ => Indicator::Off,
+            },
+        };
+        let command = match rule.command {
+            Emit::Nothing => None,
+            Emit::MoveUp => Some(Command::MoveUp),
+            Emit::MoveDown => Some(Command::MoveDown),
+            Emit::OpenDoor(dir) => Some(Command::OpenDoor {
+                floor,
+                direction: indicator(dir),
+                doors: self.doors_at(floor, indicator(dir)),
+            }),
+            Emit::Reopen(dir) => Some(Command::OpenDoor {
+                floor,
+                direction: indicator(dir),
+                doors: self.open_doors,
+            }),
+            Emit::StopAndOpen(dir) => Some(Command::StopAndOpen {
+                floor,
+                direction: indicator(dir),
+                doors: self.doors_at(floor, indicator(dir)),
+            }),
+            // The indicator people at the car's floor go by, not the one at
+            // the floor called
+            Emit::ChangeIndicator(dir) => Some(Command::ChangeIndicator {
+                floor,
+                direction: indicator(dir),
+            }),
+            Emit::Reject => Some(Command::RejectEvent(event)),
+            Emit::IdleTimer => self.idle_timer(),
+        };
+        let next = match rule.next {
+            To::Stay => state,
+            To::Idle => State::Idle,
+            To::MovingUp => State::MovingUp,
+            To::MovingDown => State::MovingDown,
+            To::Open(dir) => match indicator(dir) {
+                Indicator::Up => State::OpenUp,
+                Indicator::Down => State::OpenDown,
+                Indicator::Off => State::Open,
+            },
+            To::Unreachable(reason) => panic!("{reason}"),
+        };
+        (command, next)
+    }
+
+    // Whether a fact in a guard holds, for `event`
+    fn holds(&self, atom: Atom, event: Event) -> bool {
+        let floor = self.floor;
+        let index = (floor - LOWEST_FLOOR) as usize;
+        let (call, side) = match event {
+            Event::Panel(call, side) | Event::Up(call, side) | Event::Down(call, side) => {
+                (call, Some(side))
             }
-            Event::Up(floor) => {
-                if floor != self.floor {
-                    self.set_up_request(floor as usize, true);
-                    (None, ElevatorState::OpenUp)
-                } else {
-                    (Some(Command::RejectEvent(event)), ElevatorState::OpenUp)
-                }
+            _ => (floor, None),
+        };
+        let calls_at = |index: usize| {
+            self.destinations[index] || self.up_requests[index] || self.down_requests[index]
+        };
+        match atom {
+            Atom::Here => call == floor,
+            Atom::Above => call > floor,
+            Atom::SideOpen => side.is_some_and(|side| self.open_doors.has(side)),
+            Atom::Overloaded => self.overloaded(),
+            Atom::Requests => self.have_requests(),
+            Atom::HighestAbove => self.highest_request() > floor,
+            Atom::HighestBelow => self.highest_request() < floor,
+            Atom::LowestAbove => self.lowest_request() > floor,
+            Atom::LowestBelow => self.lowest_request() < floor,
+            Atom::ParkAbove => self.parking_floor() > floor,
+            Atom::ParkBelow => self.parking_floor() < floor,
+            Atom::StopGoingUp => {
+                self.destinations[index]
+                    || (self.up_requests[index] && !self.bypassing_hall_calls())
+                    || self.highest_request() <= floor
             }
-            Event::Down(floor) => {
-                self.set_down_request(floor as usize, true);
-                (None, ElevatorState::OpenUp)
-            }
-            Event::Closed(floor) => {
-                if !self.have_requests() {
-                    (None, ElevatorState::Idle)
-                } else if self.highest_request() > floor {
-                    (Some(Command::MoveUp), ElevatorState::MovingUp)
-                } else if self.highest_request() < floor {
-                    (Some(Command::MoveDown), ElevatorState::MovingDown)
-                } else {
-                    (
-                        Some(Command::OpenDoor {
-                            floor,
-                            direction: Indicator::Up,
-                        }),
-                        ElevatorState::OpenDown,
-                    )
-                }
+            Atom::StopGoingDown => {
+                self.destinations[index]
+                    || (self.down_requests[index] && !self.bypassing_hall_calls())
+                    || self.lowest_request() >= floor
+            }
+            Atom::UpwardCalls => {
+                self.up_requests[index] || (index + 1..NUM_FLOORS as usize).any(calls_at)
+            }
+            Atom::DownwardCalls => self.down_requests[index] || (0..index).any(calls_at),
+            Atom::OtherCalls => {
+                self.up_requests
+                    .iter()
+                    .chain(&self.down_requests)
+                    .any(|&x| x)
+                    || (0..NUM_FLOORS as usize).any(|i| i != index && self.destinations[i])
             }
-            _ => panic!("Unexpected event: {:?}", event),
         }
     }
 
-    fn handle_open_down(&mut self, event: Event) -> (Option<Command>, ElevatorState) {
-        self.set_destination(self.floor as usize, false);
-        self.set_down_request(self.floor as usize, false);
-
-        match event {
-            Event::Panel(floor) => {
-                if floor != self.floor {
-                    self.set_destination(floor as usize, true);
-                    (None, ElevatorState::OpenDown)
-                } else {
-                    (Some(Command::RejectEvent(event)), ElevatorState::OpenDown)
-                }
+    fn act(&mut self, action: Action, event: Event) {
+        match (action, event) {
+            (Action::Register, Event::Panel(floor, _)) => {
+                self.set_destination(floor as usize, true)
             }
-            Event::Up(floor) => {
-                self.set_up_request(floor as usize, true);
-                (None, ElevatorState::OpenDown)
+            (Action::Register, Event::Up(floor, _)) => self.set_up_request(floor as usize, true),
+            (Action::Register, Event::Down(floor, _)) => {
+                self.set_down_request(floor as usize, true)
             }
-            Event::Down(floor) => {
-                if floor != self.floor {
-                    self.set_down_request(floor as usize, true);
-                    (None, ElevatorState::OpenDown)
-                } else {
-                    (Some(Command::RejectEvent(event)), ElevatorState::OpenDown)
-                }
+            (Action::Arrive, Event::Arrived(floor)) => {
+                self.floor = floor;
+                self.abandon_parking();
             }
-            Event::Closed(floor) => {
-                if !self.have_requests() {
-                    (None, ElevatorState::Idle)
-                } else if self.lowest_request() < floor {
-                    (Some(Command::MoveDown), ElevatorState::MovingDown)
-                } else if self.lowest_request() > floor {
-                    (Some(Command::MoveUp), ElevatorState::MovingUp)
-                } else {
-                    (
-                        Some(Command::ChangeIndicator {
-                            floor: self.floor,
-                            direction: Indicator::Up,
-                        }),
-                        ElevatorState::OpenUp,
-                    )
+            (Action::Stop, _) => {
+                if self.get_destination(self.floor as usize) {
+                    self.stop_load = Some(self.load);
                 }
+                self.set_destination(self.floor as usize, false);
+                self.parking = None;
             }
-            _ => panic!("Unexpected event: {:?}", event),
+            (Action::Park, _) => self.parking = Some(self.parking_floor()),
+            _ => panic!("{action:?} on {event:?}"),
         }
     }
 
-    fn check_invariants(&self) {
-        match &self.state {
-            ElevatorState::Idle => {
-                // Should not be idle if there are requests
-                assert!(!self.have_requests());
+    // What must hold after every event, in `state`.  The typestates can't
+    // rule out the ones about the calls, so they're checked there too (see
+    // proofs.rs).
+    pub(crate) fn check_invariants(&self, state: &ElevatorState) {
+        let served = &self.settings.served;
+        // Every call knows which doors it's waiting at, and those doors exist
+        for floor in LOWEST_FLOOR..=HIGHEST_FLOOR {
+            let index = (floor - LOWEST_FLOOR) as usize;
+            for (requested, sides) in [
+                (self.destinations[index], self.destination_doors[index]),
+                (self.up_requests[index], self.up_doors[index]),
+                (self.down_requests[index], self.down_doors[index]),
+            ] {
+                assert_eq!(requested, !sides.is_empty());
+                assert!(served.doors(floor).contains(sides));
             }
+        }
+
+        let floor = self.floor as usize;
+        let open = || {
+            assert!(self.parking.is_none());
+            assert!(!self.open_doors.is_empty());
+            assert!(served.doors(self.floor).contains(self.open_doors));
+            assert!(!self.get_destination(floor));
+        };
+        match state {
+            // Should not be idle if there are requests
+            ElevatorState::Idle => assert!(!self.have_requests()),
             ElevatorState::MovingUp => {
                 assert!(self.floor < HIGHEST_FLOOR);
                 assert!(self.have_requests());
@@ -445,93 +1062,281 @@ impl Elevator {
                 assert!(self.lowest_request() < self.floor);
             }
             ElevatorState::Open => {
-                assert!(!self.get_up_request(self.floor as usize));
-                assert!(!self.get_down_request(self.floor as usize));
-                for floor in LOWEST_FLOOR..HIGHEST_FLOOR + 1 {
-                    assert!(!self.get_destination(floor as usize));
-                }
+                open();
+                assert!(!self.get_up_request(floor));
+                assert!(!self.get_down_request(floor));
+                assert!(!self.destinations.contains(&true));
             }
             ElevatorState::OpenUp => {
+                open();
                 assert!(self.floor < HIGHEST_FLOOR);
-                assert!(!self.get_up_request(self.floor as usize));
-                assert!(!self.get_destination(self.floor as usize));
+                assert!(!self.get_up_request(floor));
             }
             ElevatorState::OpenDown => {
+                open();
                 assert!(self.floor > LOWEST_FLOOR);
-                assert!(!self.get_down_request(self.floor as usize));
-                assert!(!self.get_destination(self.floor as usize));
+                assert!(!self.get_down_request(floor));
+            }
+            ElevatorState::Fault(_) => {
+                assert!(!self.have_requests());
+                assert!(self.parking.is_none());
             }
         }
     }
+}
 
-    pub fn produce_future_event(&self) -> Option<Event> {
+impl Default for ElevatorData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ElevatorState {
+    Idle,
+    MovingUp,
+    MovingDown,
+    Open,
+    OpenUp,
+    OpenDown,
+    Fault(Failure), // Out of service until the hardware is seen to
+}
+
+#[derive(Debug, Clone)]
+pub struct Elevator {
+    state: ElevatorState,
+    data: ElevatorData,
+}
+
+impl Elevator {
+    pub fn new() -> Self {
+        Elevator::with_settings(Settings::new())
+    }
+
+    pub fn with_settings(settings: Settings) -> Self {
+        let elevator = Elevator {
+            state: ElevatorState::Idle,
+            data: ElevatorData::with_settings(settings),
+        };
+        elevator.check_invariants();
+        elevator
+    }
+
+    // Pick up where another controller left off, without resetting the
+    // hardware.  The waiting calls are handled as if they had just been
+    // made, which may get the car going.
+    pub fn resume(
+        settings: Settings,
+        hardware: &HardwareState,
+    ) -> Result<(Elevator, Option<Command>), String> {
+        let (data, state) = ElevatorData::resume(settings, hardware)?;
+        let mut elevator = Elevator { state, data };
+        elevator.check_invariants();
+        let mut commands: Vec<Command> = Vec::new();
+        if matches!(elevator.state, ElevatorState::Idle) {
+            commands.extend(elevator.data.idle_timer());
+        }
+        for &call in &hardware.calls {
+            commands.extend(elevator.handle(call));
+        }
+        Ok((elevator, Command::sequence(commands)))
+    }
+
+    pub fn handle(&mut self, event: Event) -> Option<Command> {
+        // An overshoot is only ever reported straight after the stop
+        let stop = self.data.stop.take();
+        if !self.data.settings.served.valid_call(event) {
+            return Some(Command::RejectEvent(event));
+        }
+        self.data.record_call(event);
+
+        // Nuisance calls are weeded out as the doors close, before deciding
+        // where to go next.
+        let cancelled = match (&self.state, event) {
+            (
+                ElevatorState::Open | ElevatorState::OpenUp | ElevatorState::OpenDown,
+                Event::Closed(_),
+            ) => self.data.cancel_nuisance_calls(),
+            _ => Vec::new(),
+        };
+        let stopping = match (&self.state, event) {
+            (ElevatorState::MovingUp, Event::Arrived(floor)) => {
+                Some(self.data.stop_at(floor, true))
+            }
+            (ElevatorState::MovingDown, Event::Arrived(floor)) => {
+                Some(self.data.stop_at(floor, false))
+            }
+            _ => None,
+        };
+
+        let (command, new_state) = match (&self.state, event) {
+            (_, Event::Load(load)) => (self.data.weigh(load, &self.state), self.state.clone()),
+            (_, Event::Overshot(floor)) => self.handle_overshot(floor, stop),
+            (_, Event::PanelCancel(floor, side)) => {
+                let command = self.data.cancel_car_call(floor, side, &self.state);
+                (Some(command), self.state.clone())
+            }
+            // Cards are checked by the security layer (security.rs), if any
+            (_, Event::Credential(_)) => (None, self.state.clone()),
+            (_, Event::Fault(failure)) => (self.data.drop_calls(), ElevatorState::Fault(failure)),
+            (_, Event::Reset) => {
+                self.data.reset();
+                (self.data.idle_timer(), ElevatorState::Idle)
+            }
+            (ElevatorState::Fault(_), _) => self.handle_out_of_service(event),
+            _ => self.follow_rules(event),
+        };
+
+        self.data.ordered(&command, stopping);
+        self.state = new_state;
+        self.clear_served_requests();
+        self.data.forget_answered_sides();
+        self.check_invariants();
+        Command::cancelling(cancelled, command)
+    }
+
+    // Opening the doors answers the car call for this floor along with the
+    // hall call matching the direction shown on the indicator.
+    fn clear_served_requests(&mut self) {
         match &self.state {
-            ElevatorState::Open | ElevatorState::OpenUp | ElevatorState::OpenDown => {
-                Some(Event::Closed(self.floor))
+            ElevatorState::Open => {
+                self.data.set_destination(self.data.floor as usize, false);
+            }
+            ElevatorState::OpenUp => {
+                self.data.set_destination(self.data.floor as usize, false);
+                self.data.set_up_request(self.data.floor as usize, false);
+            }
+            ElevatorState::OpenDown => {
+                self.data.set_destination(self.data.floor as usize, false);
+                self.data.set_down_request(self.data.floor as usize, false);
             }
-            ElevatorState::MovingUp => Some(Event::Arrived(self.floor + 1)),
-            ElevatorState::MovingDown => Some(Event::Arrived(self.floor - 1)),
-            ElevatorState::Idle => None,
+            _ => {}
         }
     }
 
-    pub fn set_destination(&mut self, floor: usize, status: bool) {
-        self.destinations[floor - LOWEST_FLOOR as usize] = status;
+    fn handle_overshot(
+        &mut self,
+        floor: u8,
+        stop: Option<Stop>,
+    ) -> (Option<Command>, ElevatorState) {
+        let stop = match stop {
+            Some(stop) if stop.floor == floor => stop,
+            _ => panic!("Unexpected event: {:?}", Event::Overshot(floor)),
+        };
+        let going_up = stop.going_up;
+        self.data.overshot(stop);
+        match going_up {
+            true => (None, ElevatorState::MovingUp),
+            false => (None, ElevatorState::MovingDown),
+        }
     }
 
-    pub fn get_destination(&self, floor: usize) -> bool {
-        self.destinations[floor - LOWEST_FLOOR as usize]
+    // The calls waiting to be answered
+    pub fn calls(&self) -> Vec<Event> {
+        self.data.calls()
     }
 
-    pub fn set_up_request(&mut self, floor: usize, status: bool) {
-        self.up_requests[floor - LOWEST_FLOOR as usize] = status;
+    // What's worth saving in case the controller dies
+    pub fn snapshot(&self) -> Snapshot {
+        self.data.snapshot(&self.state)
     }
 
-    pub fn get_up_request(&self, floor: usize) -> bool {
-        self.up_requests[floor - LOWEST_FLOOR as usize]
+    // Pick up what the last snapshot knew that the hardware doesn't
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.data.restore(&self.state, snapshot);
+        self.check_invariants();
     }
 
-    pub fn set_down_request(&mut self, floor: usize, status: bool) {
-        self.down_requests[floor - LOWEST_FLOOR as usize] = status;
+    // Out of service: calls are refused and the car doesn't move
+    fn handle_out_of_service(&mut self, event: Event) -> (Option<Command>, ElevatorState) {
+        match event {
+            Event::Panel(..) | Event::Up(..) | Event::Down(..) => {
+                (Some(Command::RejectEvent(event)), self.state.clone())
+            }
+            _ => (None, self.state.clone()),
+        }
     }
 
-    pub fn get_down_request(&self, floor: usize) -> bool {
-        self.down_requests[floor - LOWEST_FLOOR as usize]
+    // Calls, arrivals, closing doors and the idle timer, as the table in
+    // rules.rs says
+    fn follow_rules(&mut self, event: Event) -> (Option<Command>, ElevatorState) {
+        let state = match self.state {
+            ElevatorState::Idle => State::Idle,
+            ElevatorState::MovingUp => State::MovingUp,
+            ElevatorState::MovingDown => State::MovingDown,
+            ElevatorState::Open => State::Open,
+            ElevatorState::OpenUp => State::OpenUp,
+            ElevatorState::OpenDown => State::OpenDown,
+            ElevatorState::Fault(_) => unreachable!(),
+        };
+        let (command, next) = self.data.follow_rules(state, event);
+        let next = match next {
+            State::Idle => ElevatorState::Idle,
+            State::MovingUp => ElevatorState::MovingUp,
+            State::MovingDown => ElevatorState::MovingDown,
+            State::Open => ElevatorState::Open,
+            State::OpenUp => ElevatorState::OpenUp,
+            State::OpenDown => ElevatorState::OpenDown,
+        };
+        (command, next)
     }
 
-    pub fn have_requests(&self) -> bool {
-        self.destinations.iter().any(|x| *x)
-            || self.up_requests.iter().any(|x| *x)
-            || self.down_requests.iter().any(|x| *x)
+    fn check_invariants(&self) {
+        self.data.check_invariants(&self.state);
     }
 
-    fn has_request_at_floor(&self, floor_index: usize) -> bool {
-        self.destinations[floor_index]
-            || self.up_requests[floor_index]
-            || self.down_requests[floor_index]
+    pub fn produce_future_event(&self) -> Option<Event> {
+        match &self.state {
+            ElevatorState::Open | ElevatorState::OpenUp | ElevatorState::OpenDown => {
+                Some(Event::Closed(self.data.floor))
+            }
+            ElevatorState::MovingUp => self
+                .data
+                .settings
+                .served
+                .above(self.data.floor)
+                .map(Event::Arrived),
+            ElevatorState::MovingDown => self
+                .data
+                .settings
+                .served
+                .below(self.data.floor)
+                .map(Event::Arrived),
+            ElevatorState::Idle => match self.data.settings.idle_policy {
+                IdlePolicy::StayPut => None,
+                _ => Some(Event::IdleTimeout),
+            },
+            ElevatorState::Fault(_) => None,
+        }
     }
+}
 
-    pub fn highest_request(&self) -> u8 {
-        (0..self.destinations.len())
-            .rev()
-            .find(|&i| self.has_request_at_floor(i))
-            .map(|i| i as u8 + LOWEST_FLOOR)
-            .unwrap_or(LOWEST_FLOOR)
+impl Default for Elevator {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    pub fn lowest_request(&self) -> u8 {
-        (0..self.destinations.len())
-            .find(|&i| self.has_request_at_floor(i))
-            .map(|i| i as u8 + LOWEST_FLOOR)
-            .unwrap_or(LOWEST_FLOOR)
+impl Algorithm for Elevator {
+    fn handle(&mut self, event: Event) -> Option<Command> {
+        Elevator::handle(self, event)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::states::{ElevatorAfterEvent, IdleElevator};
     const FUZZSTEPS: usize = 1000000;
 
+    fn random_side() -> Side {
+        if fastrand::bool() {
+            Side::Front
+        } else {
+            Side::Rear
+        }
+    }
+
     fn random_possible_event(elev: &Elevator) -> Event {
         // If the current state leads to some future event (like changing a floor),
         // we'll prefer to do that most of the time.
@@ -541,20 +1346,794 @@ mod test {
             }
         }
         // Otherwise, press a random button
-        match fastrand::usize(..3) {
-            0 => Event::Panel(fastrand::u8(LOWEST_FLOOR..HIGHEST_FLOOR + 1)),
-            1 => Event::Up(fastrand::u8(LOWEST_FLOOR..HIGHEST_FLOOR)),
-            2 => Event::Down(fastrand::u8(LOWEST_FLOOR + 1..HIGHEST_FLOOR + 1)),
+        match fastrand::usize(..6) {
+            0 => Event::Panel(fastrand::u8(LOWEST_FLOOR..HIGHEST_FLOOR + 1), random_side()),
+            1 => Event::Up(fastrand::u8(LOWEST_FLOOR..HIGHEST_FLOOR), random_side()),
+            2 => Event::Down(
+                fastrand::u8(LOWEST_FLOOR + 1..HIGHEST_FLOOR + 1),
+                random_side(),
+            ),
+            3 => Event::Load(fastrand::u8(0..=RATED_LOAD + 20)),
+            4 => Event::IdleTimeout,
+            5 => Event::PanelCancel(fastrand::u8(LOWEST_FLOOR..HIGHEST_FLOOR + 1), random_side()),
             _ => panic!(),
         }
     }
 
-    #[test]
-    fn fuzz_test() {
-        let mut elevator = Elevator::new();
+    fn fuzz(mut elevator: Elevator) {
         for _ in 0..FUZZSTEPS {
             let evt = random_possible_event(&elevator);
             elevator.handle(evt);
+
+            // Now and then the stop comes too late (but the car never runs
+            // off the end of the shaft)
+            if let Some(stop) = elevator.data.stop.clone() {
+                let served = &elevator.data.settings.served;
+                let beyond = if stop.going_up {
+                    served.above(stop.floor)
+                } else {
+                    served.below(stop.floor)
+                };
+                if beyond.is_some() && fastrand::f64() < 0.1 {
+                    assert_eq!(elevator.handle(Event::Overshot(stop.floor)), None);
+                }
+            }
+        }
+    }
+
+    fn parking(idle_policy: IdlePolicy) -> Elevator {
+        Elevator::with_settings(Settings {
+            idle_policy,
+            ..Settings::new()
+        })
+    }
+
+    #[test]
+    fn fuzz_test() {
+        fuzz(Elevator::new());
+    }
+
+    #[test]
+    fn fuzz_test_anti_nuisance() {
+        fuzz(Elevator::with_settings(Settings {
+            anti_nuisance: true,
+            ..Settings::new()
+        }));
+    }
+
+    #[test]
+    fn fuzz_test_express_zone() {
+        fuzz(Elevator::with_settings(Settings {
+            served: ServedFloors::only(&[1, 4, 5]),
+            idle_policy: IdlePolicy::ReturnToLobby,
+            ..Settings::new()
+        }));
+    }
+
+    #[test]
+    fn fuzz_test_rear_doors() {
+        fuzz(Elevator::with_settings(Settings {
+            served: ServedFloors::with_doors(&[2, 3], &[1, 3, 5]),
+            idle_policy: IdlePolicy::ReturnToLobby,
+            ..Settings::new()
+        }));
+    }
+
+    #[test]
+    fn fuzz_test_return_to_lobby() {
+        fuzz(parking(IdlePolicy::ReturnToLobby));
+    }
+
+    #[test]
+    fn fuzz_test_park_at_busiest_floor() {
+        fuzz(parking(IdlePolicy::ParkAtBusiestFloor));
+    }
+
+    #[test]
+    fn parks_at_lobby_after_timeout() {
+        let mut elevator = parking(IdlePolicy::ReturnToLobby);
+        assert_eq!(
+            elevator.handle(Event::Panel(3, Side::Front)),
+            Some(Command::MoveUp)
+        );
+        elevator.handle(Event::Arrived(2));
+        elevator.handle(Event::Arrived(3));
+        assert_eq!(
+            elevator.handle(Event::Closed(3)),
+            Some(Command::StartIdleTimer(DEFAULT_IDLE_DELAY))
+        );
+        assert_eq!(elevator.handle(Event::IdleTimeout), Some(Command::MoveDown));
+        assert_eq!(elevator.handle(Event::Arrived(2)), None);
+        assert_eq!(
+            elevator.handle(Event::Arrived(1)),
+            Some(Command::StopAndOpen {
+                floor: 1,
+                direction: Indicator::Off,
+                doors: Doors::FRONT
+            })
+        );
+    }
+
+    #[test]
+    fn new_call_cancels_parking() {
+        let mut elevator = parking(IdlePolicy::ReturnToLobby);
+        elevator.handle(Event::Panel(4, Side::Front));
+        elevator.handle(Event::Arrived(2));
+        elevator.handle(Event::Arrived(3));
+        elevator.handle(Event::Arrived(4));
+        elevator.handle(Event::Closed(4));
+        assert_eq!(elevator.handle(Event::IdleTimeout), Some(Command::MoveDown));
+
+        // Somebody upstairs wants the car: turn around at the next floor
+        assert_eq!(elevator.handle(Event::Up(4, Side::Front)), None);
+        assert_eq!(
+            elevator.handle(Event::Arrived(3)),
+            Some(Command::StopAndOpen {
+                floor: 3,
+                direction: Indicator::Up,
+                doors: Doors::FRONT
+            })
+        );
+        assert_eq!(elevator.handle(Event::Closed(3)), Some(Command::MoveUp));
+    }
+
+    #[test]
+    fn parks_at_busiest_floor() {
+        let mut elevator = parking(IdlePolicy::ParkAtBusiestFloor);
+        for _ in 0..3 {
+            elevator.handle(Event::Down(4, Side::Front));
+            elevator.handle(Event::Arrived(2));
+            elevator.handle(Event::Arrived(3));
+            elevator.handle(Event::Arrived(4));
+            elevator.handle(Event::Panel(1, Side::Front));
+            elevator.handle(Event::Closed(4));
+            elevator.handle(Event::Arrived(3));
+            elevator.handle(Event::Arrived(2));
+            elevator.handle(Event::Arrived(1));
+            elevator.handle(Event::Closed(1));
+        }
+        assert_eq!(elevator.data.parking_floor(), 4);
+        assert_eq!(elevator.handle(Event::IdleTimeout), Some(Command::MoveUp));
+    }
+
+    fn anti_nuisance() -> Elevator {
+        Elevator::with_settings(Settings {
+            anti_nuisance: true,
+            ..Settings::new()
+        })
+    }
+
+    #[test]
+    fn cancels_too_many_car_calls_for_the_load() {
+        let mut elevator = anti_nuisance();
+        elevator.handle(Event::Up(1, Side::Front));
+        elevator.handle(Event::Load(6));
+        for floor in 2..=5 {
+            elevator.handle(Event::Panel(floor, Side::Front));
+        }
+        assert_eq!(
+            elevator.handle(Event::Closed(1)),
+            Some(Command::CancelCarCalls(vec![
+                (2, Side::Front),
+                (3, Side::Front),
+                (4, Side::Front),
+                (5, Side::Front)
+            ]))
+        );
+        assert!(!elevator.data.have_requests());
+    }
+
+    #[test]
+    fn keeps_car_calls_that_match_the_load() {
+        let mut elevator = anti_nuisance();
+        elevator.handle(Event::Up(1, Side::Front));
+        elevator.handle(Event::Load(30));
+        for floor in 2..=5 {
+            elevator.handle(Event::Panel(floor, Side::Front));
+        }
+        assert_eq!(elevator.handle(Event::Closed(1)), Some(Command::MoveUp));
+    }
+
+    #[test]
+    fn cancels_car_calls_after_empty_stops() {
+        let mut elevator = anti_nuisance();
+        elevator.handle(Event::Up(1, Side::Front));
+        elevator.handle(Event::Load(12));
+        elevator.handle(Event::Panel(2, Side::Front));
+        elevator.handle(Event::Panel(3, Side::Front));
+        assert_eq!(elevator.handle(Event::Closed(1)), Some(Command::MoveUp));
+
+        // Nobody gets out at 2 or 3, and then more calls are made
+        elevator.handle(Event::Arrived(2));
+        elevator.handle(Event::Panel(4, Side::Front));
+        assert_eq!(elevator.handle(Event::Closed(2)), Some(Command::MoveUp));
+        elevator.handle(Event::Arrived(3));
+        elevator.handle(Event::Panel(5, Side::Front));
+        assert_eq!(elevator.handle(Event::Closed(3)), Some(Command::MoveUp));
+        elevator.handle(Event::Arrived(4));
+        assert_eq!(
+            elevator.handle(Event::Closed(4)),
+            Some(Command::CancelCarCalls(vec![(5, Side::Front)]))
+        );
+    }
+
+    #[test]
+    fn passengers_getting_out_reset_empty_stops() {
+        let mut elevator = anti_nuisance();
+        elevator.handle(Event::Up(1, Side::Front));
+        elevator.handle(Event::Load(30));
+        for floor in 2..=5 {
+            elevator.handle(Event::Panel(floor, Side::Front));
+        }
+        elevator.handle(Event::Closed(1));
+        for (floor, load) in [(2, 20), (3, 10), (4, 5)] {
+            elevator.handle(Event::Arrived(floor));
+            elevator.handle(Event::Load(load));
+            assert_eq!(elevator.handle(Event::Closed(floor)), Some(Command::MoveUp));
+        }
+    }
+
+    #[test]
+    fn empty_stops_are_only_counted_with_anti_nuisance() {
+        let mut elevator = Elevator::new();
+        elevator.handle(Event::Load(12));
+        for _ in 0..150 {
+            assert_eq!(
+                elevator.handle(Event::Panel(2, Side::Front)),
+                Some(Command::MoveUp)
+            );
+            elevator.handle(Event::Arrived(2));
+            assert_eq!(elevator.handle(Event::Closed(2)), None);
+            assert_eq!(
+                elevator.handle(Event::Panel(1, Side::Front)),
+                Some(Command::MoveDown)
+            );
+            elevator.handle(Event::Arrived(1));
+            assert_eq!(elevator.handle(Event::Closed(1)), None);
+        }
+        assert_eq!(elevator.data.empty_stops, 0);
+    }
+
+    #[test]
+    fn double_press_cancels_car_call() {
+        let mut elevator = Elevator::new();
+        elevator.handle(Event::Panel(5, Side::Front));
+        elevator.handle(Event::Panel(3, Side::Front));
+        assert_eq!(
+            elevator.handle(Event::PanelCancel(3, Side::Front)),
+            Some(Command::CancelCarCalls(vec![(3, Side::Front)]))
+        );
+        assert_eq!(elevator.handle(Event::Arrived(2)), None);
+        assert_eq!(elevator.handle(Event::Arrived(3)), None);
+        assert_eq!(
+            elevator.handle(Event::PanelCancel(3, Side::Front)),
+            Some(Command::RejectEvent(Event::PanelCancel(3, Side::Front)))
+        );
+    }
+
+    #[test]
+    fn cancel_for_no_such_floor_is_rejected() {
+        let mut elevator = Elevator::new();
+        for floor in [0, HIGHEST_FLOOR + 1] {
+            let cancel = Event::PanelCancel(floor, Side::Front);
+            assert_eq!(elevator.handle(cancel), Some(Command::RejectEvent(cancel)));
+        }
+    }
+
+    #[test]
+    fn overshot_floor_is_served_on_the_way_back() {
+        let mut elevator = Elevator::new();
+        elevator.handle(Event::Panel(3, Side::Front));
+        elevator.handle(Event::Arrived(2));
+        assert_eq!(
+            elevator.handle(Event::Arrived(3)),
+            Some(Command::StopAndOpen {
+                floor: 3,
+                direction: Indicator::Off,
+                doors: Doors::FRONT
+            })
+        );
+        assert_eq!(elevator.handle(Event::Overshot(3)), None);
+        assert!(elevator.data.get_destination(3));
+        assert_eq!(
+            elevator.handle(Event::Arrived(4)),
+            Some(Command::StopAndOpen {
+                floor: 4,
+                direction: Indicator::Down,
+                doors: Doors::FRONT
+            })
+        );
+        assert_eq!(elevator.handle(Event::Closed(4)), Some(Command::MoveDown));
+        assert_eq!(
+            elevator.handle(Event::Arrived(3)),
+            Some(Command::StopAndOpen {
+                floor: 3,
+                direction: Indicator::Off,
+                doors: Doors::FRONT
+            })
+        );
+    }
+
+    #[test]
+    fn cancelling_last_car_call_stops_at_next_floor() {
+        let mut elevator = Elevator::new();
+        elevator.handle(Event::Panel(5, Side::Front));
+        elevator.handle(Event::Arrived(2));
+        assert_eq!(
+            elevator.handle(Event::PanelCancel(5, Side::Front)),
+            Some(Command::CancelCarCalls(vec![(5, Side::Front)]))
+        );
+        assert_eq!(
+            elevator.handle(Event::Arrived(3)),
+            Some(Command::StopAndOpen {
+                floor: 3,
+                direction: Indicator::Off,
+                doors: Doors::FRONT
+            })
+        );
+        assert_eq!(elevator.handle(Event::Closed(3)), None);
+        assert!(!elevator.data.have_requests());
+    }
+
+    #[test]
+    fn rejects_calls_for_unserved_floors() {
+        let mut elevator = Elevator::with_settings(Settings {
+            served: ServedFloors::only(&[1, 2, 4]),
+            ..Settings::new()
+        });
+        for event in [
+            Event::Panel(3, Side::Front),
+            Event::Up(3, Side::Front),
+            Event::Up(4, Side::Front),
+            Event::Down(5, Side::Front),
+        ] {
+            assert_eq!(elevator.handle(event), Some(Command::RejectEvent(event)));
+        }
+        assert_eq!(
+            elevator.handle(Event::Down(4, Side::Front)),
+            Some(Command::MoveUp)
+        );
+        assert_eq!(elevator.handle(Event::Arrived(2)), None);
+        assert_eq!(
+            elevator.handle(Event::Arrived(4)),
+            Some(Command::StopAndOpen {
+                floor: 4,
+                direction: Indicator::Down,
+                doors: Doors::FRONT
+            })
+        );
+    }
+
+    fn rear_doors() -> Elevator {
+        Elevator::with_settings(Settings {
+            served: ServedFloors::with_doors(&[1, 2, 3], &[1, 3, 4, 5]),
+            ..Settings::new()
+        })
+    }
+
+    #[test]
+    fn opens_the_door_the_call_was_made_from() {
+        let mut elevator = rear_doors();
+        assert_eq!(
+            elevator.handle(Event::Panel(3, Side::Rear)),
+            Some(Command::MoveUp)
+        );
+        assert_eq!(elevator.handle(Event::Arrived(2)), None);
+        assert_eq!(
+            elevator.handle(Event::Arrived(3)),
+            Some(Command::StopAndOpen {
+                floor: 3,
+                direction: Indicator::Off,
+                doors: Doors::REAR
+            })
+        );
+    }
+
+    #[test]
+    fn opens_both_doors_for_calls_on_both_sides() {
+        let mut elevator = rear_doors();
+        elevator.handle(Event::Panel(3, Side::Front));
+        elevator.handle(Event::Down(3, Side::Rear));
+        elevator.handle(Event::Arrived(2));
+        assert_eq!(
+            elevator.handle(Event::Arrived(3)),
+            Some(Command::StopAndOpen {
+                floor: 3,
+                direction: Indicator::Down,
+                doors: Doors::BOTH
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_calls_for_missing_doors() {
+        let mut elevator = rear_doors();
+        for event in [
+            Event::Panel(2, Side::Rear),
+            Event::Up(2, Side::Rear),
+            Event::Down(4, Side::Front),
+        ] {
+            assert_eq!(elevator.handle(event), Some(Command::RejectEvent(event)));
+        }
+    }
+
+    #[test]
+    fn hall_call_behind_shut_doors_is_rejected() {
+        let mut elevator = rear_doors();
+        assert_eq!(
+            elevator.handle(Event::Panel(1, Side::Front)),
+            Some(Command::OpenDoor {
+                floor: 1,
+                direction: Indicator::Off,
+                doors: Doors::FRONT
+            })
+        );
+        let event = Event::Up(1, Side::Rear);
+        assert_eq!(elevator.handle(event), Some(Command::RejectEvent(event)));
+        assert_eq!(
+            elevator.handle(Event::Up(1, Side::Front)),
+            Some(Command::ChangeIndicator {
+                floor: 1,
+                direction: Indicator::Up
+            })
+        );
+    }
+
+    #[test]
+    fn double_press_cancels_one_side_only() {
+        let mut elevator = rear_doors();
+        elevator.handle(Event::Panel(3, Side::Front));
+        elevator.handle(Event::Panel(3, Side::Rear));
+        assert_eq!(
+            elevator.handle(Event::PanelCancel(3, Side::Rear)),
+            Some(Command::CancelCarCalls(vec![(3, Side::Rear)]))
+        );
+        elevator.handle(Event::Arrived(2));
+        assert_eq!(
+            elevator.handle(Event::Arrived(3)),
+            Some(Command::StopAndOpen {
+                floor: 3,
+                direction: Indicator::Off,
+                doors: Doors::FRONT
+            })
+        );
+    }
+
+    #[test]
+    fn fault_takes_the_car_out_of_service() {
+        let mut elevator = Elevator::new();
+        elevator.handle(Event::Panel(5, Side::Front));
+        elevator.handle(Event::Down(3, Side::Front));
+        assert_eq!(
+            elevator.handle(Event::Fault(Failure::Motion)),
+            Some(Command::Sequence(vec![
+                Command::CancelCarCalls(vec![(5, Side::Front)]),
+                Command::RejectEvent(Event::Down(3, Side::Front)),
+            ]))
+        );
+        assert!(!elevator.data.have_requests());
+        assert_eq!(elevator.produce_future_event(), None);
+
+        // Nothing gets it moving again
+        let call = Event::Up(2, Side::Front);
+        assert_eq!(elevator.handle(call), Some(Command::RejectEvent(call)));
+        assert_eq!(elevator.handle(Event::Arrived(2)), None);
+        assert_eq!(elevator.handle(Event::IdleTimeout), None);
+        assert!(!elevator.data.have_requests());
+    }
+
+    #[test]
+    fn reset_starts_again_from_the_lowest_floor() {
+        let mut elevator = Elevator::new();
+        elevator.handle(Event::Panel(5, Side::Front));
+        elevator.handle(Event::Arrived(2));
+        elevator.handle(Event::Arrived(3));
+        assert_eq!(elevator.handle(Event::Reset), None);
+        assert_eq!(elevator.data.floor, LOWEST_FLOOR);
+        assert!(!elevator.data.have_requests());
+
+        // The controller presses the button again
+        assert_eq!(
+            elevator.handle(Event::Panel(5, Side::Front)),
+            Some(Command::MoveUp)
+        );
+        assert_eq!(elevator.handle(Event::Arrived(2)), None);
+    }
+
+    #[test]
+    fn resume_carries_on_from_the_hardware() {
+        // Doors open going up, with somebody waiting to go down
+        let (mut elevator, command) = Elevator::resume(
+            Settings::new(),
+            &HardwareState {
+                floor: 3,
+                activity: Activity::Open(Doors::FRONT, Indicator::Up),
+                calls: vec![Event::Down(3, Side::Front)],
+            },
+        )
+        .unwrap();
+        assert_eq!(command, None);
+        assert!(matches!(elevator.state, ElevatorState::OpenUp));
+        elevator.handle(Event::Closed(3));
+
+        // On the way up to 3, with a passenger for 5
+        let (mut elevator, command) = Elevator::resume(
+            Settings::new(),
+            &HardwareState {
+                floor: 3,
+                activity: Activity::MovingUp,
+                calls: vec![Event::Panel(5, Side::Front)],
+            },
+        )
+        .unwrap();
+        assert_eq!(command, None);
+        assert_eq!(elevator.data.floor, 2);
+        assert_eq!(elevator.handle(Event::Arrived(3)), None);
+        assert_eq!(elevator.handle(Event::Arrived(4)), None);
+        assert_eq!(
+            elevator.handle(Event::Arrived(5)),
+            Some(Command::StopAndOpen {
+                floor: 5,
+                direction: Indicator::Off,
+                doors: Doors::FRONT
+            })
+        );
+    }
+
+    #[test]
+    fn resume_refuses_hardware_that_makes_no_sense() {
+        let express = Settings {
+            served: ServedFloors::only(&[1, 4, 5]),
+            ..Settings::new()
+        };
+        for (settings, floor, activity) in [
+            // Nowhere for the car to be coming from
+            (Settings::new(), LOWEST_FLOOR, Activity::MovingUp),
+            (Settings::new(), HIGHEST_FLOOR, Activity::MovingDown),
+            // Floors that aren't served, or aren't there at all
+            (express.clone(), 3, Activity::Closed),
+            (express.clone(), 3, Activity::MovingUp),
+            (Settings::new(), HIGHEST_FLOOR + 1, Activity::Closed),
+            // Doors that aren't open, or aren't there
+            (
+                Settings::new(),
+                3,
+                Activity::Open(Doors::NONE, Indicator::Off),
+            ),
+            (
+                Settings::new(),
+                3,
+                Activity::Open(Doors::REAR, Indicator::Up),
+            ),
+        ] {
+            let hardware = HardwareState {
+                floor,
+                activity,
+                calls: vec![Event::Panel(2, Side::Front)],
+            };
+            assert!(Elevator::resume(settings.clone(), &hardware).is_err());
+            assert!(ElevatorAfterEvent::resume(settings, &hardware).is_err());
+        }
+    }
+
+    #[test]
+    fn resume_ignores_an_indicator_pointing_nowhere() {
+        for (floor, direction) in [
+            (HIGHEST_FLOOR, Indicator::Up),
+            (LOWEST_FLOOR, Indicator::Down),
+        ] {
+            let hardware = HardwareState {
+                floor,
+                activity: Activity::Open(Doors::FRONT, direction),
+                calls: Vec::new(),
+            };
+            let (elevator, command) = Elevator::resume(Settings::new(), &hardware).unwrap();
+            assert_eq!(command, None);
+            assert!(matches!(elevator.state, ElevatorState::Open));
+            let (command, elevator) =
+                ElevatorAfterEvent::resume(Settings::new(), &hardware).unwrap();
+            assert_eq!(command, None);
+            assert!(matches!(elevator, ElevatorAfterEvent::Open(_)));
+        }
+    }
+
+    #[test]
+    fn restore_carries_on_from_the_snapshot() {
+        // Stopped at 3 on the way up, with somebody waiting at 4 who had
+        // called twice before.  The car's doors are still open when the next
+        // controller takes over.
+        let hardware = HardwareState {
+            floor: 3,
+            activity: Activity::Open(Doors::FRONT, Indicator::Up),
+            calls: vec![],
+        };
+        let snapshot = Snapshot {
+            state: "open-up".to_string(),
+            floor: 3,
+            load: 40,
+            parking: None,
+            demand: [0, 0, 1, 3, 0],
+            stop_load: Some(40),
+            empty_stops: 1,
+            calls: vec![Event::Down(4, Side::Front)],
+        };
+        let (mut logic, _) = Elevator::resume(Settings::new(), &hardware).unwrap();
+        logic.restore(&snapshot);
+        assert_eq!(logic.data.demand, [0, 0, 1, 2, 0]);
+        assert_eq!(logic.data.stop_load, Some(40));
+        assert_eq!(logic.data.empty_stops, 1);
+        // The controller presses the button again
+        logic.handle(Event::Down(4, Side::Front));
+        assert_eq!(logic.data.demand, snapshot.demand);
+        let (_, states) = ElevatorAfterEvent::resume(Settings::new(), &hardware).unwrap();
+        let (_, states) = states
+            .restore(&snapshot)
+            .handle(Event::Down(4, Side::Front));
+        assert_eq!(states.snapshot(), logic.snapshot());
+
+        // Only the demand carries over once the car has moved on
+        let hardware = HardwareState {
+            floor: 4,
+            ..hardware
+        };
+        let (mut logic, _) = Elevator::resume(Settings::new(), &hardware).unwrap();
+        logic.restore(&snapshot);
+        assert_eq!(logic.data.demand, [0, 0, 1, 2, 0]);
+        assert_eq!(logic.data.stop_load, None);
+        assert_eq!(logic.data.empty_stops, 0);
+
+        // Taken over on the way down to park at the lowest floor, it doesn't
+        // stop at the next one
+        let hardware = HardwareState {
+            floor: 3,
+            activity: Activity::MovingDown,
+            calls: vec![],
+        };
+        let snapshot = Snapshot {
+            state: "moving-down".to_string(),
+            floor: 4,
+            parking: Some(1),
+            calls: vec![],
+            ..snapshot
+        };
+        let (mut logic, _) = Elevator::resume(Settings::new(), &hardware).unwrap();
+        assert_eq!(logic.data.parking, Some(3));
+        logic.restore(&snapshot);
+        assert_eq!(logic.data.parking, Some(1));
+        assert_eq!(logic.handle(Event::Arrived(3)), None);
+        let (_, states) = ElevatorAfterEvent::resume(Settings::new(), &hardware).unwrap();
+        let (_, states) = states.restore(&snapshot).handle(Event::Arrived(3));
+        assert_eq!(states.snapshot(), logic.snapshot());
+    }
+
+    // Runs the same events through both machines, checking the commands
+    // that come out.  Returns the machines for a look at their state.
+    fn script(steps: &[(Event, Option<Command>)]) -> (Elevator, ElevatorAfterEvent) {
+        let mut logic = Elevator::new();
+        let mut states = ElevatorAfterEvent::Idle(IdleElevator::new());
+        for (event, command) in steps {
+            assert_eq!(&logic.handle(*event), command, "{event:?}");
+            assert_eq!(
+                &Algorithm::handle(&mut states, *event),
+                command,
+                "{event:?} in states.rs"
+            );
         }
+        (logic, states)
+    }
+
+    fn stop(floor: u8, direction: Indicator) -> Option<Command> {
+        Some(Command::StopAndOpen {
+            floor,
+            direction,
+            doors: Doors::FRONT,
+        })
+    }
+
+    #[test]
+    fn request_tables_start_at_the_lowest_floor() {
+        // An entry for each floor the car can go to, the top one included
+        let mut data = ElevatorData::new();
+        data.set_destination(HIGHEST_FLOOR as usize, true);
+        data.set_up_request(LOWEST_FLOOR as usize, true);
+        data.set_down_request(HIGHEST_FLOOR as usize, true);
+        assert!(data.destinations[(NUM_FLOORS - 1) as usize]);
+        assert!(data.up_requests[0]);
+        assert!(data.down_requests[(NUM_FLOORS - 1) as usize]);
+        assert!(data.get_destination(HIGHEST_FLOOR as usize));
+        script(&[
+            (Event::Panel(5, Side::Front), Some(Command::MoveUp)),
+            (Event::Down(5, Side::Front), None),
+            (Event::Arrived(2), None),
+            (Event::Arrived(3), None),
+            (Event::Arrived(4), None),
+            (Event::Arrived(5), stop(5, Indicator::Down)),
+        ]);
+    }
+
+    #[test]
+    fn stops_for_hall_calls_going_its_way() {
+        script(&[
+            (Event::Panel(5, Side::Front), Some(Command::MoveUp)),
+            (Event::Up(2, Side::Front), None),
+            (Event::Down(3, Side::Front), None),
+            (Event::Arrived(2), stop(2, Indicator::Up)),
+            (Event::Closed(2), Some(Command::MoveUp)),
+            // Going the other way: picked up on the way back down
+            (Event::Arrived(3), None),
+            (Event::Arrived(4), None),
+            (Event::Arrived(5), stop(5, Indicator::Down)),
+            (Event::Closed(5), Some(Command::MoveDown)),
+            (Event::Arrived(4), None),
+            (Event::Arrived(3), stop(3, Indicator::Down)),
+        ]);
+    }
+
+    #[test]
+    fn stops_for_a_hall_call_at_the_end_of_its_travel() {
+        // Nobody further on, so the car turns round for the call the
+        // other way
+        script(&[
+            (Event::Down(4, Side::Front), Some(Command::MoveUp)),
+            (Event::Arrived(2), None),
+            (Event::Arrived(3), None),
+            (Event::Arrived(4), stop(4, Indicator::Down)),
+            (Event::Panel(1, Side::Front), None),
+            (Event::Up(2, Side::Front), None),
+            (Event::Closed(4), Some(Command::MoveDown)),
+            (Event::Arrived(3), None),
+            (Event::Arrived(2), None),
+            (Event::Arrived(1), stop(1, Indicator::Up)),
+        ]);
+    }
+
+    #[test]
+    fn opening_the_doors_answers_the_calls() {
+        // The car call and the call going the way the indicator shows are
+        // answered as soon as the stop is made, not on the next event
+        let (logic, states) = script(&[
+            (Event::Panel(3, Side::Front), Some(Command::MoveUp)),
+            (Event::Up(3, Side::Front), None),
+            (Event::Down(3, Side::Front), None),
+            (Event::Up(4, Side::Front), None),
+            (Event::Arrived(2), None),
+            (Event::Arrived(3), stop(3, Indicator::Up)),
+        ]);
+        let waiting = vec![Event::Up(4, Side::Front), Event::Down(3, Side::Front)];
+        assert_eq!(logic.calls(), waiting);
+        assert_eq!(states.snapshot().calls, waiting);
+    }
+
+    #[test]
+    fn indicator_follows_the_calls_while_the_doors_are_open() {
+        let open = |floor, direction| {
+            Some(Command::OpenDoor {
+                floor,
+                direction,
+                doors: Doors::FRONT,
+            })
+        };
+        script(&[
+            (Event::Panel(1, Side::Front), open(1, Indicator::Off)),
+            // The indicator is the one at the car's floor, not the call's
+            (
+                Event::Panel(3, Side::Front),
+                Some(Command::ChangeIndicator {
+                    floor: 1,
+                    direction: Indicator::Up,
+                }),
+            ),
+            (Event::Closed(1), Some(Command::MoveUp)),
+            (Event::Arrived(2), None),
+            (Event::Arrived(3), stop(3, Indicator::Off)),
+            (Event::Closed(3), None),
+            (Event::Up(3, Side::Front), open(3, Indicator::Up)),
+            // Nobody above, so once the doors close they open again for the
+            // call going down, showing down, and then the other way round
+            (Event::Down(3, Side::Front), None),
+            (Event::Closed(3), open(3, Indicator::Down)),
+            (Event::Up(3, Side::Front), None),
+            (Event::Closed(3), open(3, Indicator::Up)),
+        ]);
     }
 }
@@ -1,9 +1,40 @@
-use crate::logic::Event;
-use crate::states::{ElevatorAfterEvent, IdleElevator};
+use elevator::building::BuildingConfig;
+use elevator::capture::Recording;
+use elevator::controller::{LiftyController, Transport, UdpTransport};
+use elevator::journal::{Entry, Journal};
+use elevator::logic::{Algorithm, Event};
+use elevator::security::{Secured, SecurityConfig};
+use elevator::snapshot::SnapshotStore;
+use elevator::states::{ElevatorAfterEvent, IdleElevator};
+use elevator::transitions::{Format, Level, Transition, TransitionLog};
+use elevator::tui::{Dashboard, Hotkeys};
+use std::path::Path;
+use std::process::exit;
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
 
-mod controller;
-mod logic;
-mod states;
+// Where the controller saves its state, so that a new one can restore the
+// calls if it dies
+const SNAPSHOT: &str = "elevator.snapshot";
+
+// Where the controller records what it was told and did (see journal.rs)
+const JOURNAL: &str = "elevator.journal";
+
+// Where the controller captures its traffic with Lifty (see capture.rs)
+const CAPTURE: &str = "lifty.capture";
+
+// The floors, doors and timings of the building (see building.rs).  Without
+// it, the controller runs Lifty's own five floors with the defaults.
+const BUILDING: &str = "config/building.conf";
+
+// How the controller logs transitions.  Without it, everything is logged
+// to standard output.
+const LOGGING: &str = "config/logging.conf";
+
+// Where the controller saves its operating figures (see metrics.rs), and how
+// often
+const METRICS: &str = "elevator.prom";
+const METRICS_EVERY: Duration = Duration::from_secs(60);
 
 fn main() {
     println!("Hello, elevator!");
@@ -22,18 +53,167 @@ fn main() {
     //     }
     // }
 
-    let mut controller = controller::LiftyController::new();
-    let mut elevator = ElevatorAfterEvent::Idle(IdleElevator::new());
+    // cargo run -- --tui [security.conf] shows a dashboard instead of the
+    // log, with keys to press Lifty's buttons (see tui.rs)
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let tui = match args.iter().position(|arg| arg == "--tui") {
+        Some(n) => {
+            args.remove(n);
+            true
+        }
+        None => false,
+    };
+    let security = args.first().map(|path| {
+        SecurityConfig::load(path).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        })
+    });
 
-    loop {
-        println!("{:?}", elevator);
-        let event = controller.receive();
+    let building = if Path::new(BUILDING).exists() {
+        BuildingConfig::load(BUILDING).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        })
+    } else {
+        BuildingConfig::new()
+    };
 
-        elevator = handle_event(elevator, &mut controller, event);
+    // The dashboard has the terminal to itself, so nothing is logged there
+    let log = if tui {
+        TransitionLog::new(Level::All)
+    } else if Path::new(LOGGING).exists() {
+        TransitionLog::load(LOGGING).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        })
+    } else {
+        let mut log = TransitionLog::new(Level::All);
+        log.add_sink(Format::Text, Box::new(std::io::stdout()));
+        log
+    };
 
-        while let Some(future_event) = elevator.produce_future_event() {
-            elevator = handle_event(elevator, &mut controller, future_event);
+    if tui {
+        let (keys, pressed) = mpsc::channel();
+        let transport = Hotkeys::new(UdpTransport::new(), pressed);
+        run(transport, &building, security, log, Some(keys));
+    } else {
+        run(UdpTransport::new(), &building, security, log, None);
+    }
+}
+
+// `keys` is where the dashboard sends the keys pressed, if there's to be one
+fn run<T: Transport>(
+    transport: T,
+    building: &BuildingConfig,
+    security: Option<SecurityConfig>,
+    mut log: TransitionLog,
+    keys: Option<Sender<u8>>,
+) {
+    // If another controller was running the car, carry on from wherever it
+    // left off.  Otherwise (or if Lifty can't say, or what it says makes no
+    // sense for this building) it starts afresh.
+    let transport = Recording::create(transport, CAPTURE).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        exit(1)
+    });
+    let (mut controller, mut hardware) = LiftyController::take_over_with(transport, building);
+    controller.save_metrics(METRICS, METRICS_EVERY);
+    let resumed = hardware.as_ref().and_then(|hardware| {
+        ElevatorAfterEvent::resume(building.settings(), hardware)
+            .inspect_err(|e| eprintln!("Can't carry on with {e}, starting afresh"))
+            .ok()
+    });
+    if resumed.is_none() && hardware.take().is_some() {
+        controller.start_afresh();
+    }
+    let (command, elevator) = resumed.unwrap_or_else(|| {
+        let elevator = IdleElevator::with_settings(building.settings());
+        (None, ElevatorAfterEvent::Idle(elevator))
+    });
+    let mut journal = Journal::open(JOURNAL).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        exit(1)
+    });
+    journal.record(controller.now(), &Entry::Start(hardware));
+    if let Some(command) = command {
+        journal.record(controller.now(), &Entry::Command(format!("{command:?}")));
+        controller.send(command);
+    }
+
+    // What the last controller knew that the hardware doesn't, and the calls
+    // it had taken that the hardware has since lost
+    let store = SnapshotStore::new(SNAPSHOT);
+    let mut elevator = match store.load() {
+        Ok(Some(snapshot)) => {
+            controller.press(&snapshot.calls);
+            elevator.restore(&snapshot)
+        }
+        Ok(None) => elevator,
+        Err(e) => {
+            eprintln!("{e}");
+            elevator
+        }
+    };
+
+    // Started last, so that nothing above exits with the terminal in raw mode
+    let mut dashboard = keys.map(|keys| {
+        Dashboard::start(keys).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        })
+    });
+    if let Some(dashboard) = &dashboard {
+        dashboard.draw(&elevator.snapshot());
+    }
+
+    // With a security config (cargo run -- config/security.conf), locked
+    // floors need a card at the reader.  Replaying the journal only covers
+    // the state machine, so what access control deals with is marked as such.
+    if let Some(security) = security {
+        let mut elevator = Secured::new(elevator, security);
+        while let Some(event) = controller.receive() {
+            let before = elevator.elevator().snapshot();
+            let secured = elevator.intercepts(event, controller.now());
+            let command = elevator.handle_at(event, controller.now());
+            if secured {
+                journal.record(controller.now(), &Entry::Secured(event));
+            } else {
+                journal.handled(
+                    controller.now(),
+                    event,
+                    command.as_ref(),
+                    elevator.elevator(),
+                );
+            }
+            let after = elevator.elevator().snapshot();
+            let transition =
+                Transition::new(controller.now(), &before, event, command.as_ref(), &after);
+            log.log(&transition);
+            if let Some(dashboard) = &mut dashboard {
+                dashboard.show(&transition, &after);
+            }
+            if let Some(command) = command {
+                controller.send(command);
+            }
+            save(&store, elevator.elevator());
         }
+        return;
+    }
+
+    // Future events are what the state machine expects the hardware to
+    // report next.  They're handy for fuzzing, but with real hardware we wait
+    // for the hardware to actually report them.
+    while let Some(event) = controller.receive() {
+        elevator = handle_event(
+            elevator,
+            &mut controller,
+            &mut journal,
+            &mut log,
+            &mut dashboard,
+            event,
+        );
+        save(&store, &elevator);
     }
 }
 
@@ -47,12 +227,23 @@ fn main() {
 //     }
 // }
 
-fn handle_event(
+fn handle_event<T: Transport>(
     elevator: ElevatorAfterEvent, // Take ownership
-    controller: &mut controller::LiftyController,
+    controller: &mut LiftyController<T>,
+    journal: &mut Journal,
+    log: &mut TransitionLog,
+    dashboard: &mut Option<Dashboard>,
     event: Event,
 ) -> ElevatorAfterEvent {
+    let before = elevator.snapshot();
     let (command, new_elevator) = elevator.handle(event);
+    journal.handled(controller.now(), event, command.as_ref(), &new_elevator);
+    let after = new_elevator.snapshot();
+    let transition = Transition::new(controller.now(), &before, event, command.as_ref(), &after);
+    log.log(&transition);
+    if let Some(dashboard) = dashboard {
+        dashboard.show(&transition, &after);
+    }
 
     if let Some(command) = command {
         controller.send(command);
@@ -60,3 +251,9 @@ fn handle_event(
 
     new_elevator
 }
+
+fn save(store: &SnapshotStore, elevator: &ElevatorAfterEvent) {
+    if let Err(e) = store.save(&elevator.snapshot()) {
+        eprintln!("{e}");
+    }
+}
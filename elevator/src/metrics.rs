@@ -0,0 +1,305 @@
+// Operating figures for maintenance planning, kept by the controller from
+// what it sees go by: calls per floor, how long they waited to be answered,
+// door cycles, motor starts, reversals and distance travelled.  They're
+// written out in the Prometheus text format, e.g.
+//
+//     # TYPE elevator_hall_calls_total counter
+//     elevator_hall_calls_total{floor="3",direction="up"} 12
+//
+// Counts are kept from when the controller started.
+
+use crate::controller::call;
+use crate::logic::{Event, HIGHEST_FLOOR, LOWEST_FLOOR, NUM_FLOORS};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::time::Duration;
+
+// Upper bounds of the wait histogram buckets, in seconds
+const WAIT_BUCKETS: [f64; 8] = [5.0, 10.0, 20.0, 30.0, 45.0, 60.0, 120.0, 300.0];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    counts: [u64; WAIT_BUCKETS.len()], // Not cumulative; that's done on output
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            counts: [0; WAIT_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, seconds: f64) {
+        if let Some(n) = WAIT_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.counts[n] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in WAIT_BUCKETS.iter().zip(self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub hall_calls: [[u64; 2]; NUM_FLOORS as usize], // Up and down, per floor
+    pub car_calls: [u64; NUM_FLOORS as usize],
+    pub hall_waits: Histogram,
+    pub car_waits: Histogram,
+    pub door_cycles: u64,
+    pub motor_starts: u64,
+    pub reversals: u64,
+    pub distance: u64,                   // Floors travelled
+    waiting: BTreeMap<String, Duration>, // Calls not yet answered, by button
+    floor: u8,                           // Where the car last stopped
+    direction: Option<bool>,             // Up (or not) on the last start
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            hall_calls: [[0; 2]; NUM_FLOORS as usize],
+            car_calls: [0; NUM_FLOORS as usize],
+            hall_waits: Histogram::new(),
+            car_waits: Histogram::new(),
+            door_cycles: 0,
+            motor_starts: 0,
+            reversals: 0,
+            distance: 0,
+            waiting: BTreeMap::new(),
+            floor: LOWEST_FLOOR,
+            direction: None,
+        }
+    }
+
+    // A button was pressed.  Pressing it again before the call is answered
+    // doesn't make another call.
+    pub fn called(&mut self, button: &str, at: Duration) {
+        if self.waiting.contains_key(button) {
+            return;
+        }
+        let index = |floor: u8| (floor - LOWEST_FLOOR) as usize;
+        match call(button) {
+            Some(Event::Panel(floor, _)) => self.car_calls[index(floor)] += 1,
+            Some(Event::Up(floor, _)) => self.hall_calls[index(floor)][0] += 1,
+            Some(Event::Down(floor, _)) => self.hall_calls[index(floor)][1] += 1,
+            _ => return,
+        }
+        self.waiting.insert(button.to_string(), at);
+    }
+
+    // The car has come for a call
+    pub fn answered(&mut self, button: &str, at: Duration) {
+        if let Some(called) = self.waiting.remove(button) {
+            let wait = at.saturating_sub(called).as_secs_f64();
+            match call(button) {
+                Some(Event::Panel(..)) => self.car_waits.observe(wait),
+                _ => self.hall_waits.observe(wait),
+            }
+        }
+    }
+
+    // A call went away without being answered (rejected or cancelled)
+    pub fn dropped(&mut self, button: &str) {
+        self.waiting.remove(button);
+    }
+
+    pub fn doors_opened(&mut self) {
+        self.door_cycles += 1;
+    }
+
+    pub fn motor_started(&mut self, up: bool) {
+        self.motor_starts += 1;
+        if self.direction.is_some_and(|last| last != up) {
+            self.reversals += 1;
+        }
+        self.direction = Some(up);
+    }
+
+    pub fn stopped(&mut self, floor: u8) {
+        self.distance += floor.abs_diff(self.floor) as u64;
+        self.floor = floor;
+    }
+
+    // Lifty was reset, which puts the car back on the lowest floor
+    pub fn reset(&mut self) {
+        self.floor = LOWEST_FLOOR;
+    }
+
+    pub fn exposition(&self) -> String {
+        let mut out = String::new();
+        let floors = || (LOWEST_FLOOR..=HIGHEST_FLOOR).zip(0..);
+
+        let name = "elevator_hall_calls_total";
+        header(
+            &mut out,
+            name,
+            "counter",
+            "Hall calls made, by floor and direction",
+        );
+        for (floor, n) in floors() {
+            for (direction, count) in ["up", "down"].into_iter().zip(self.hall_calls[n]) {
+                let labels = format!("floor=\"{floor}\",direction=\"{direction}\"");
+                let _ = writeln!(out, "{name}{{{labels}}} {count}");
+            }
+        }
+
+        let name = "elevator_car_calls_total";
+        header(&mut out, name, "counter", "Car calls made, by floor");
+        for (floor, n) in floors() {
+            let _ = writeln!(out, "{name}{{floor=\"{floor}\"}} {}", self.car_calls[n]);
+        }
+
+        let name = "elevator_call_wait_seconds";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "Time from a call to the car coming for it",
+        );
+        self.hall_waits.write(&mut out, name, "call=\"hall\"");
+        self.car_waits.write(&mut out, name, "call=\"car\"");
+
+        for (name, help, count) in [
+            (
+                "elevator_door_cycles_total",
+                "Times the doors opened",
+                self.door_cycles,
+            ),
+            (
+                "elevator_motor_starts_total",
+                "Times the motor started",
+                self.motor_starts,
+            ),
+            (
+                "elevator_reversals_total",
+                "Motor starts in the other direction to the last",
+                self.reversals,
+            ),
+            (
+                "elevator_distance_floors_total",
+                "Floors travelled",
+                self.distance,
+            ),
+        ] {
+            header(&mut out, name, "counter", help);
+            let _ = writeln!(out, "{name} {count}");
+        }
+        out
+    }
+
+    // Write the figures to a file, replacing the last lot in one go
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let temporary = format!("{path}.tmp");
+        fs::write(&temporary, self.exposition()).map_err(|e| format!("{path}: {e}"))?;
+        fs::rename(&temporary, path).map_err(|e| format!("{path}: {e}"))
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::building::BuildingConfig;
+    use crate::controller::LiftyController;
+    use crate::logic::{Algorithm, Elevator};
+    use crate::sim::Simulation;
+    use crate::traffic::TrafficProfile;
+
+    #[test]
+    fn counts_and_waits() {
+        let mut metrics = Metrics::new();
+        let seconds = Duration::from_secs;
+        metrics.called("U2", seconds(0));
+        metrics.called("U2", seconds(3)); // Pressed again while waiting
+        metrics.called("RP4", seconds(1));
+        metrics.called("D5", seconds(2));
+        metrics.answered("U2", seconds(12));
+        metrics.dropped("D5");
+        metrics.answered("D5", seconds(20));
+        assert_eq!(metrics.hall_calls[1], [1, 0]);
+        assert_eq!(metrics.hall_calls[4], [0, 1]);
+        assert_eq!(metrics.car_calls[3], 1);
+        assert_eq!(metrics.hall_waits.count(), 1);
+        assert_eq!(metrics.car_waits.count(), 0);
+
+        metrics.motor_started(true);
+        metrics.stopped(4);
+        metrics.motor_started(false);
+        metrics.stopped(2);
+        assert_eq!((metrics.motor_starts, metrics.reversals), (2, 1));
+        assert_eq!(metrics.distance, 5);
+
+        let text = metrics.exposition();
+        assert!(text.contains("elevator_hall_calls_total{floor=\"2\",direction=\"up\"} 1\n"));
+        assert!(text.contains("elevator_call_wait_seconds_bucket{call=\"hall\",le=\"10\"} 0\n"));
+        assert!(text.contains("elevator_call_wait_seconds_bucket{call=\"hall\",le=\"20\"} 1\n"));
+        assert!(text.contains("elevator_call_wait_seconds_sum{call=\"hall\"} 12\n"));
+        assert!(text.contains("# TYPE elevator_distance_floors_total counter\n"));
+        assert!(text.contains("elevator_distance_floors_total 5\n"));
+    }
+
+    #[test]
+    fn kept_by_the_controller() {
+        let config = BuildingConfig::new();
+        let profile = TrafficProfile {
+            passengers: 50,
+            ..TrafficProfile::new()
+        };
+        let passengers = profile.generate(&config);
+        let mut controller =
+            LiftyController::with_transport(Simulation::new(&config, &passengers, 1_000_000));
+        let path = std::env::temp_dir().join(format!("elevator-{}.prom", std::process::id()));
+        let path = path.to_str().unwrap();
+        controller.save_metrics(path, Duration::from_secs(60));
+        let mut elevator = Elevator::new();
+        while let Some(event) = controller.receive() {
+            if let Some(command) = elevator.handle_at(event, controller.now()) {
+                controller.send(command);
+            }
+        }
+
+        // Much the same as the simulator makes it
+        let stats = controller.transport().stats();
+        let metrics = controller.metrics();
+        assert_eq!(metrics.distance, stats.distance as u64);
+        assert_eq!(metrics.reversals, stats.reversals as u64);
+        assert_eq!(metrics.door_cycles, stats.stops as u64);
+        let hall_calls: u64 = metrics.hall_calls.iter().flatten().sum();
+        assert!(hall_calls > 0 && hall_calls <= 50);
+        // Some are turned down, e.g. pressed as the doors open for them
+        assert!(metrics.hall_waits.count() > 0 && metrics.hall_waits.count() <= hall_calls);
+
+        let saved = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert!(saved.starts_with("# HELP elevator_hall_calls_total"));
+    }
+}
@@ -0,0 +1,229 @@
+// Physical model of the car's travel for the simulator.
+//
+// The car accelerates and brakes smoothly: the acceleration is limited, and
+// so is the rate at which it can change (the jerk).  It runs at the rated
+// speed when there's room to, and stopping takes as long as it really would,
+// so a car running flat out has to be told to stop well before it gets to
+// the floor.
+
+use crate::logic::{HIGHEST_FLOOR, LOWEST_FLOOR, NUM_FLOORS};
+
+// Speed at which the car creeps the last bit of the way into a floor
+const LEVELLING_SPEED: f64 = 0.1;
+
+// Slack for rounding errors when comparing positions
+const EPSILON: f64 = 1e-6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionProfile {
+    pub rated_speed: f64,                              // metres per second
+    pub acceleration: f64,                             // metres per second squared
+    pub jerk: f64,                                     // metres per second cubed
+    pub floor_heights: [f64; NUM_FLOORS as usize - 1], // metres from each floor to the next
+}
+
+impl MotionProfile {
+    pub fn new() -> Self {
+        MotionProfile {
+            rated_speed: 1.0,
+            acceleration: 0.8,
+            jerk: 1.0,
+            floor_heights: [3.5; NUM_FLOORS as usize - 1],
+        }
+    }
+
+    // Height of a floor above the lowest one
+    pub fn level(&self, floor: u8) -> f64 {
+        self.floor_heights[..(floor - LOWEST_FLOOR) as usize]
+            .iter()
+            .sum()
+    }
+
+    // Distance the car needs to come to a smooth stop.  Any acceleration has
+    // to ease off before the brakes can be applied.
+    pub fn stopping_distance(&self, speed: f64, acceleration: f64) -> f64 {
+        let (a, j) = (self.acceleration, self.jerk);
+        let (mut distance, mut speed) = (0.0, speed);
+        if acceleration > 0.0 {
+            let t = acceleration / j;
+            distance = speed * t + acceleration * t * t / 2.0 - j * t * t * t / 6.0;
+            speed += acceleration * acceleration / (2.0 * j);
+        }
+        distance
+            + if speed >= a * a / j {
+                // Full braking is reached
+                speed * speed / (2.0 * a) + speed * a / (2.0 * j)
+            } else {
+                speed * (speed / j).sqrt()
+            }
+    }
+
+    // The fastest the car can go (with no acceleration) and still stop
+    // within `distance`
+    fn braking_speed(&self, distance: f64) -> f64 {
+        let (a, j) = (self.acceleration, self.jerk);
+        let speed = (distance * distance * j).cbrt();
+        if speed < a * a / j {
+            speed
+        } else {
+            (-a * a / j + (a.powi(4) / (j * j) + 8.0 * a * distance).sqrt()) / 2.0
+        }
+    }
+}
+
+impl Default for MotionProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Motion {
+    pub position: f64,     // metres above the lowest floor
+    pub speed: f64,        // in the direction of travel, never negative
+    pub acceleration: f64, // in the direction of travel
+}
+
+impl Motion {
+    pub fn at_floor(profile: &MotionProfile, floor: u8) -> Self {
+        Motion {
+            position: profile.level(floor),
+            speed: 0.0,
+            acceleration: 0.0,
+        }
+    }
+
+    pub fn stopping_distance(&self, profile: &MotionProfile) -> f64 {
+        profile.stopping_distance(self.speed, self.acceleration)
+    }
+
+    // Distance still to go (up or down) to a floor.  Negative once it's
+    // been passed.
+    pub fn distance_to(&self, profile: &MotionProfile, floor: u8, up: bool) -> f64 {
+        let distance = profile.level(floor) - self.position;
+        if up {
+            distance
+        } else {
+            -distance
+        }
+    }
+
+    // The floor the car is at, or has most recently passed
+    pub fn floor(&self, profile: &MotionProfile, up: bool) -> u8 {
+        let passed = |&floor: &u8| self.distance_to(profile, floor, up) <= EPSILON;
+        if up {
+            (LOWEST_FLOOR..=HIGHEST_FLOOR)
+                .rev()
+                .find(passed)
+                .unwrap_or(LOWEST_FLOOR)
+        } else {
+            (LOWEST_FLOOR..=HIGHEST_FLOOR)
+                .find(passed)
+                .unwrap_or(HIGHEST_FLOOR)
+        }
+    }
+
+    // Move for `dt` seconds, running at the rated speed or, if there's a
+    // floor to stop at, braking for it.  Returns true once stopped there.
+    pub fn step(
+        &mut self,
+        profile: &MotionProfile,
+        dt: f64,
+        up: bool,
+        stop_at: Option<u8>,
+    ) -> bool {
+        let remaining = stop_at.map(|floor| self.distance_to(profile, floor, up).max(0.0));
+        let wanted = match remaining {
+            Some(distance) => profile
+                .braking_speed(distance)
+                .clamp(LEVELLING_SPEED, profile.rated_speed),
+            None => profile.rated_speed,
+        };
+
+        // Head for that speed, easing off the acceleration in time to reach
+        // it without overshooting
+        let gap = wanted - self.speed;
+        let wanted_acceleration = gap.signum()
+            * profile
+                .acceleration
+                .min((2.0 * profile.jerk * gap.abs()).sqrt());
+        let change = profile.jerk * dt;
+        self.acceleration += (wanted_acceleration - self.acceleration).clamp(-change, change);
+        self.speed = (self.speed + self.acceleration * dt).max(0.0);
+        if self.speed > profile.rated_speed {
+            self.speed = profile.rated_speed;
+            self.acceleration = 0.0;
+        }
+
+        let travel = self.speed * dt;
+        if let (Some(floor), Some(distance)) = (stop_at, remaining) {
+            if travel >= distance {
+                *self = Motion::at_floor(profile, floor);
+                return true;
+            }
+        }
+        self.position += if up { travel } else { -travel };
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DT: f64 = 0.1;
+
+    // Run up from the lowest floor, stopping at `floor` if given.  Returns
+    // the number of steps taken and the top speed.
+    fn run(profile: &MotionProfile, stop_at: Option<u8>, steps: usize) -> (usize, f64) {
+        let mut motion = Motion::at_floor(profile, LOWEST_FLOOR);
+        let mut top_speed: f64 = 0.0;
+        for n in 1..=steps {
+            if motion.step(profile, DT, true, stop_at) {
+                return (n, top_speed);
+            }
+            top_speed = top_speed.max(motion.speed);
+        }
+        (steps, top_speed)
+    }
+
+    #[test]
+    fn stops_level_with_the_floor() {
+        let profile = MotionProfile::new();
+        let mut motion = Motion::at_floor(&profile, 2);
+        let mut steps = 0;
+        while !motion.step(&profile, DT, true, Some(3)) {
+            steps += 1;
+            assert!(steps < 1000);
+        }
+        assert_eq!(motion, Motion::at_floor(&profile, 3));
+        assert_eq!(motion.floor(&profile, true), 3);
+    }
+
+    #[test]
+    fn braking_matches_stopping_distance() {
+        let profile = MotionProfile::new();
+        for speed in [0.2, 0.5, 1.0, 2.5] {
+            let distance = profile.stopping_distance(speed, 0.0);
+            assert!((profile.braking_speed(distance) - speed).abs() < 1e-9);
+        }
+        assert!(profile.stopping_distance(1.0, 0.5) > profile.stopping_distance(1.0, 0.0));
+    }
+
+    #[test]
+    fn express_runs_at_rated_speed() {
+        let profile = MotionProfile {
+            rated_speed: 4.0,
+            floor_heights: [4.0, 60.0, 60.0, 4.0],
+            ..MotionProfile::new()
+        };
+        let (steps, top_speed) = run(&profile, Some(4), 10_000);
+        assert!((top_speed - profile.rated_speed).abs() < 1e-9);
+        // Most of the way at full speed
+        assert!((steps as f64 * DT) < 1.3 * profile.level(4) / profile.rated_speed);
+
+        // One short floor never gets near it
+        let (_, top_speed) = run(&profile, Some(2), 10_000);
+        assert!(top_speed < profile.rated_speed / 2.0);
+    }
+}
@@ -0,0 +1,218 @@
+// Proofs, for Kani (https://model-checking.github.io/kani/), that the
+// typestate machine in states.rs copes with whatever the hardware reports:
+//
+//     cargo kani --harness handle_keeps_invariants
+//
+// Where the fuzz tests try random events, Kani tries every value of each
+// `kani::any()`.  It fails on any panic, including an array indexed out of
+// bounds or an overflow, and on any broken invariant.  The floors in calls
+// can be anything at all, as if Lifty sent rubbish; the floors the car
+// arrives at and closes its doors at are the ones it's due.
+//
+// handle_keeps_invariants covers every sequence of STEPS events from an
+// idle car, in each of a few buildings, and no more: a car with calls on
+// every floor, say, is never tried.  More steps take a lot longer to check,
+// so handle_keeps_invariants_anywhere starts instead from any state and
+// calls the invariants allow, and checks one event.  That could fail from a
+// state no run ever gets to, which would mean the invariants need tightening.
+
+use crate::logic::{Command, Event, Failure, IdlePolicy, ServedFloors, Settings, Side};
+use crate::logic::{Doors, ElevatorData, ElevatorState};
+use crate::logic::{HIGHEST_FLOOR, LOWEST_FLOOR, NUISANCE_STOPS};
+use crate::states::{ElevatorAfterEvent, IdleElevator};
+
+const STEPS: usize = 3;
+
+fn any_side() -> Side {
+    match kani::any() {
+        true => Side::Front,
+        false => Side::Rear,
+    }
+}
+
+// One of a few buildings, between them using every setting
+fn any_settings() -> Settings {
+    match kani::any::<u8>() {
+        0 => Settings::new(),
+        1 => Settings {
+            served: ServedFloors::with_doors(&[1, 2, 4, 5], &[2, 5]),
+            idle_policy: IdlePolicy::ReturnToLobby,
+            ..Settings::new()
+        },
+        _ => Settings {
+            anti_nuisance: true,
+            idle_policy: IdlePolicy::ParkAtBusiestFloor,
+            ..Settings::new()
+        },
+    }
+}
+
+// Any event the controller could pass on next.  `overshot` is the floor of
+// a stop that was just ordered, if the car could run on past it.
+fn any_event(elevator: &ElevatorAfterEvent, overshot: Option<u8>) -> Event {
+    let waiting_on_hardware = !matches!(
+        elevator,
+        ElevatorAfterEvent::Idle(_) | ElevatorAfterEvent::Fault(_)
+    );
+    match (kani::any::<u8>(), overshot) {
+        (0, _) => Event::Panel(kani::any(), any_side()),
+        (1, _) => Event::Up(kani::any(), any_side()),
+        (2, _) => Event::Down(kani::any(), any_side()),
+        (3, _) => Event::PanelCancel(kani::any(), any_side()),
+        (4, _) => Event::Load(kani::any()),
+        (5, _) => Event::IdleTimeout,
+        (6, _) => Event::Credential(kani::any()),
+        (7, _) => Event::Reset,
+        (8, _) if waiting_on_hardware => match kani::any() {
+            true => Event::Fault(Failure::Doors),
+            false => Event::Fault(Failure::Motion),
+        },
+        (9, Some(floor)) => Event::Overshot(floor),
+        _ => elevator
+            .produce_future_event()
+            .unwrap_or(Event::IdleTimeout),
+    }
+}
+
+#[kani::proof]
+#[kani::unwind(8)]
+fn handle_keeps_invariants() {
+    let settings = any_settings();
+    let served = settings.served;
+    let mut elevator = ElevatorAfterEvent::Idle(IdleElevator::with_settings(settings));
+    let mut overshot = None;
+    for _ in 0..STEPS {
+        let going_up = matches!(elevator, ElevatorAfterEvent::MovingUp(_));
+        let event = any_event(&elevator, overshot);
+        let (command, next) = elevator.handle(event);
+        elevator = next;
+        elevator.check_invariants();
+        overshot = match (event, command) {
+            (Event::Arrived(floor), Some(Command::StopAndOpen { .. })) => {
+                let beyond = match going_up {
+                    true => served.above(floor),
+                    false => served.below(floor),
+                };
+                beyond.map(|_| floor)
+            }
+            _ => None,
+        };
+    }
+}
+
+// Some, none or all of `doors`
+fn any_doors_of(doors: Doors) -> Doors {
+    Doors {
+        front: doors.front && kani::any(),
+        rear: doors.rear && kani::any(),
+    }
+}
+
+fn any_state() -> ElevatorState {
+    match kani::any::<u8>() {
+        0 => ElevatorState::Idle,
+        1 => ElevatorState::MovingUp,
+        2 => ElevatorState::MovingDown,
+        3 => ElevatorState::Open,
+        4 => ElevatorState::OpenUp,
+        5 => ElevatorState::OpenDown,
+        _ => match kani::any() {
+            true => ElevatorState::Fault(Failure::Doors),
+            false => ElevatorState::Fault(Failure::Motion),
+        },
+    }
+}
+
+// Any calls at the doors there are, at a served floor, with nothing left
+// over from a stop just ordered
+fn any_data(settings: Settings) -> ElevatorData {
+    let served = settings.served;
+    let mut data = ElevatorData::with_settings(settings);
+    data.floor = kani::any_where(|&floor| served.serves(floor));
+    for floor in LOWEST_FLOOR..=HIGHEST_FLOOR {
+        let index = (floor - LOWEST_FLOOR) as usize;
+        let doors = served.doors(floor);
+        data.destination_doors[index] = any_doors_of(doors);
+        if served.above(floor).is_some() {
+            data.up_doors[index] = any_doors_of(doors);
+        }
+        if served.below(floor).is_some() {
+            data.down_doors[index] = any_doors_of(doors);
+        }
+        data.destinations[index] = !data.destination_doors[index].is_empty();
+        data.up_requests[index] = !data.up_doors[index].is_empty();
+        data.down_requests[index] = !data.down_doors[index].is_empty();
+        data.demand[index] = kani::any();
+    }
+    if kani::any() {
+        data.parking = Some(kani::any_where(|&floor| served.serves(floor)));
+    }
+    data.load = kani::any();
+    data.stop_load = kani::any();
+    if data.settings.anti_nuisance {
+        data.empty_stops = kani::any_where(|&stops| stops < NUISANCE_STOPS);
+    }
+    data
+}
+
+// What check_invariants asserts about the state, as a condition
+fn consistent(data: &ElevatorData, state: &ElevatorState) -> bool {
+    let floor = data.floor as usize;
+    let open =
+        data.parking.is_none() && !data.open_doors.is_empty() && !data.get_destination(floor);
+    match state {
+        ElevatorState::Idle => !data.have_requests(),
+        ElevatorState::MovingUp => {
+            data.floor < HIGHEST_FLOOR && data.highest_request() > data.floor
+        }
+        ElevatorState::MovingDown => {
+            data.floor > LOWEST_FLOOR && data.lowest_request() < data.floor
+        }
+        ElevatorState::Open => {
+            open && !data.get_up_request(floor)
+                && !data.get_down_request(floor)
+                && !data.destinations.contains(&true)
+        }
+        ElevatorState::OpenUp => open && data.floor < HIGHEST_FLOOR && !data.get_up_request(floor),
+        ElevatorState::OpenDown => {
+            open && data.floor > LOWEST_FLOOR && !data.get_down_request(floor)
+        }
+        ElevatorState::Fault(_) => !data.have_requests(),
+    }
+}
+
+#[kani::proof]
+#[kani::unwind(8)]
+fn handle_keeps_invariants_anywhere() {
+    let settings = any_settings();
+    let served = settings.served;
+    let state = any_state();
+    let mut data = any_data(settings);
+    if matches!(
+        state,
+        ElevatorState::Open | ElevatorState::OpenUp | ElevatorState::OpenDown
+    ) {
+        data.open_doors = any_doors_of(served.doors(data.floor));
+    }
+    kani::assume(consistent(&data, &state));
+    data.check_invariants(&state);
+
+    let elevator = ElevatorAfterEvent::in_state(data, state);
+    let event = any_event(&elevator, None);
+    let (_, elevator) = elevator.handle(event);
+    elevator.check_invariants();
+}
+
+// The request tables are indexed by floor, which starts at LOWEST_FLOOR
+#[kani::proof]
+fn requests_indexed_in_bounds() {
+    let floor: u8 = kani::any_where(|floor| (LOWEST_FLOOR..=HIGHEST_FLOOR).contains(floor));
+    let floor = floor as usize;
+    let mut data = ElevatorData::new();
+    data.set_destination(floor, true);
+    data.set_up_request(floor, true);
+    data.set_down_request(floor, true);
+    assert!(data.get_destination(floor));
+    assert!(data.get_up_request(floor));
+    assert!(data.get_down_request(floor));
+}
@@ -0,0 +1,453 @@
+// The elevator algorithm's transitions as a table, which both state machines
+// (logic.rs and states.rs) follow.  Each row says what happens to an event
+// in a state, provided the guard holds:
+//
+//     Idle [Panel Up Down] if [!Here, Above] => MoveUp, MovingUp, [Register];
+//
+// reads "an idle car given a call from somewhere above it registers the
+// call, sends MoveUp and starts moving up".  A guard is a list of facts
+// about the car and the event (see `Atom`) that must all be true, or with a
+// `!`, false.  Rows for the same state and event must not overlap and must
+// leave nothing out: whatever the facts, exactly one of them applies.  That's
+// checked as the crate is built, so a table with a hole in it doesn't
+// compile.
+//
+// Events that every state treats alike (the load, faults, resets and so on)
+// are handled before the table is looked at.  ElevatorData::follow_rules
+// finds the row and carries it out; the typestates in states.rs then only
+// allow the moves their types have room for.
+
+// States of a car that's in service
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Idle,
+    MovingUp,
+    MovingDown,
+    Open,
+    OpenUp,
+    OpenDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    Panel,
+    Up,
+    Down,
+    Arrived,
+    Closed,
+    IdleTimeout,
+}
+
+// Facts that guards are made of.  "Floor" is the car's floor, and "call" the
+// floor and side of a Panel, Up or Down event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Atom {
+    Here,          // The call is at the car's floor
+    Above,         // The call is above the car
+    SideOpen,      // The doors on the call's side are open
+    Overloaded,    // Too heavy to move
+    Requests,      // Somewhere to go, a call or a parking floor
+    HighestAbove,  // The highest request is above the floor
+    HighestBelow,  // The highest request is below the floor
+    LowestAbove,   // The lowest request is above the floor
+    LowestBelow,   // The lowest request is below the floor
+    ParkAbove,     // The parking floor is above the floor
+    ParkBelow,     // The parking floor is below the floor
+    StopGoingUp,   // A car going up has a reason to stop at the floor
+    StopGoingDown, // A car going down has a reason to stop at the floor
+    UpwardCalls,   // An up call at the floor, or any call above it
+    DownwardCalls, // A down call at the floor, or any call below it
+    OtherCalls,    // Any call but the car call for the floor
+}
+
+// The direction shown on the indicator.  `Call` is the direction of the
+// call being handled: none for Panel, up for Up and down for Down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dir {
+    Up,
+    Down,
+    Off,
+    Call,
+}
+
+// The command a row sends (see logic::Command).  Doors open on the sides the
+// calls being answered were made from, except when they open again for an
+// overloaded car, which reopens the same doors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Nothing,
+    MoveUp,
+    MoveDown,
+    OpenDoor(Dir),
+    Reopen(Dir),
+    StopAndOpen(Dir),
+    ChangeIndicator(Dir),
+    Reject,    // Turn the call's light off again
+    IdleTimer, // If the car parks at all
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum To {
+    Stay,
+    Idle,
+    MovingUp,
+    MovingDown,
+    Open(Dir),
+    Unreachable(&'static str), // Ruled out by the state's invariants
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Register, // Light the call's button
+    Arrive,   // Take the floor arrived at; a parking trip gives way to calls
+    Stop,     // Answer the car call at the floor, and forget about parking
+    Park,     // Head for the parking floor
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub state: State,
+    pub events: &'static [EventKind],
+    pub guard: &'static [(Atom, bool)],
+    pub command: Emit,
+    pub next: To,
+    pub actions: &'static [Action],
+}
+
+// Actions taken on an event before any guard is looked at
+pub const FIRST: &[(State, EventKind, Action)] = &[
+    (State::MovingUp, EventKind::Arrived, Action::Arrive),
+    (State::MovingDown, EventKind::Arrived, Action::Arrive),
+];
+
+macro_rules! rules {
+    ($($state:ident [$($event:ident)+] if [$($guard:tt)*]
+        => $command:expr, $next:expr, [$($action:ident),*];)*) => {{
+        #[allow(unused_imports)]
+        use Dir::*;
+        use Emit::*;
+        use To::*;
+        &[$(Rule {
+            state: State::$state,
+            events: &[$(EventKind::$event),+],
+            guard: rules!(@guard [] $($guard)*),
+            command: $command,
+            next: $next,
+            actions: &[$(Action::$action),*],
+        }),*]
+    }};
+    (@guard [$($atoms:tt)*]) => { &[$($atoms)*] };
+    (@guard [$($atoms:tt)*] ! $atom:ident $(, $($rest:tt)*)?) => {
+        rules!(@guard [$($atoms)* (Atom::$atom, false),] $($($rest)*)?)
+    };
+    (@guard [$($atoms:tt)*] $atom:ident $(, $($rest:tt)*)?) => {
+        rules!(@guard [$($atoms)* (Atom::$atom, true),] $($($rest)*)?)
+    };
+}
+
+pub const RULES: &[Rule] = rules! {
+    // An idle car goes wherever it's called to, or opens up if it's there
+    Idle [Panel Up Down] if [Here] => OpenDoor(Call), Open(Call), [];
+    Idle [Panel Up Down] if [!Here, Above] => MoveUp, MovingUp, [Register];
+    Idle [Panel Up Down] if [!Here, !Above] => MoveDown, MovingDown, [Register];
+    Idle [IdleTimeout] if [ParkAbove] => MoveUp, MovingUp, [Park];
+    Idle [IdleTimeout] if [!ParkAbove, ParkBelow] => MoveDown, MovingDown, [Park];
+    Idle [IdleTimeout] if [!ParkAbove, !ParkBelow] => Nothing, Stay, [];
+
+    // A moving car takes calls on the way, and stops where it's wanted.
+    // Whichever way it's going, it shows the direction it'll go in next.
+    MovingUp [Panel Up Down] if [] => Nothing, Stay, [Register];
+    MovingUp [Arrived] if [!StopGoingUp] => Nothing, Stay, [];
+    MovingUp [Arrived] if [StopGoingUp, UpwardCalls] => StopAndOpen(Up), Open(Up), [Stop];
+    MovingUp [Arrived] if [StopGoingUp, !UpwardCalls, OtherCalls]
+        => StopAndOpen(Down), Open(Down), [Stop];
+    MovingUp [Arrived] if [StopGoingUp, !UpwardCalls, !OtherCalls]
+        => StopAndOpen(Off), Open(Off), [Stop];
+    MovingDown [Panel Up Down] if [] => Nothing, Stay, [Register];
+    MovingDown [Arrived] if [!StopGoingDown] => Nothing, Stay, [];
+    MovingDown [Arrived] if [StopGoingDown, DownwardCalls]
+        => StopAndOpen(Down), Open(Down), [Stop];
+    MovingDown [Arrived] if [StopGoingDown, !DownwardCalls, OtherCalls]
+        => StopAndOpen(Up), Open(Up), [Stop];
+    MovingDown [Arrived] if [StopGoingDown, !DownwardCalls, !OtherCalls]
+        => StopAndOpen(Off), Open(Off), [Stop];
+
+    // Open with no direction shown: the first call decides the direction.
+    // A hall call at the floor can only be answered through open doors; if
+    // the doors on its side are shut, the light goes off so that the button
+    // gets pressed again once the car has gone.
+    Open [Panel] if [Here] => Reject, Stay, [];
+    Open [Panel] if [!Here, Above] => ChangeIndicator(Up), Open(Up), [Register];
+    Open [Panel] if [!Here, !Above] => ChangeIndicator(Down), Open(Down), [Register];
+    Open [Up Down] if [!Here] => Nothing, Stay, [Register];
+    Open [Up Down] if [Here, !SideOpen] => Reject, Stay, [];
+    Open [Up Down] if [Here, SideOpen] => ChangeIndicator(Call), Open(Call), [];
+    Open [Closed] if [Overloaded] => Reopen(Off), Stay, [];
+    Open [Closed] if [!Overloaded, !Requests] => IdleTimer, Idle, [];
+    Open [Closed] if [!Overloaded, Requests, HighestAbove] => MoveUp, MovingUp, [];
+    Open [Closed] if [!Overloaded, Requests, !HighestAbove, HighestBelow]
+        => MoveDown, MovingDown, [];
+    Open [Closed] if [!Overloaded, Requests, !HighestAbove, !HighestBelow]
+        => Nothing, Unreachable("I'm supposed to move!"), [];
+
+    // Open going up: calls at the floor in the same direction are already
+    // answered.  Once the doors close the car carries on up if it can, and
+    // otherwise turns round, at the floor first if somebody's waiting there.
+    OpenUp [Panel Up] if [Here] => Reject, Stay, [];
+    OpenUp [Panel Up] if [!Here] => Nothing, Stay, [Register];
+    OpenUp [Down] if [] => Nothing, Stay, [Register];
+    OpenUp [Closed] if [Overloaded] => Reopen(Up), Stay, [];
+    OpenUp [Closed] if [!Overloaded, !Requests] => IdleTimer, Idle, [];
+    OpenUp [Closed] if [!Overloaded, Requests, HighestAbove] => MoveUp, MovingUp, [];
+    OpenUp [Closed] if [!Overloaded, Requests, !HighestAbove, HighestBelow]
+        => MoveDown, MovingDown, [];
+    OpenUp [Closed] if [!Overloaded, Requests, !HighestAbove, !HighestBelow]
+        => OpenDoor(Down), Open(Down), [];
+
+    // Open going down is the same the other way up
+    OpenDown [Panel Down] if [Here] => Reject, Stay, [];
+    OpenDown [Panel Down] if [!Here] => Nothing, Stay, [Register];
+    OpenDown [Up] if [] => Nothing, Stay, [Register];
+    OpenDown [Closed] if [Overloaded] => Reopen(Down), Stay, [];
+    OpenDown [Closed] if [!Overloaded, !Requests] => IdleTimer, Idle, [];
+    OpenDown [Closed] if [!Overloaded, Requests, LowestBelow] => MoveDown, MovingDown, [];
+    OpenDown [Closed] if [!Overloaded, Requests, !LowestBelow, LowestAbove]
+        => MoveUp, MovingUp, [];
+    OpenDown [Closed] if [!Overloaded, Requests, !LowestBelow, !LowestAbove]
+        => OpenDoor(Up), Open(Up), [];
+
+    // The car found something to do before the idle timer went off
+    MovingUp [IdleTimeout] if [] => Nothing, Stay, [];
+    MovingDown [IdleTimeout] if [] => Nothing, Stay, [];
+    Open [IdleTimeout] if [] => Nothing, Stay, [];
+    OpenUp [IdleTimeout] if [] => Nothing, Stay, [];
+    OpenDown [IdleTimeout] if [] => Nothing, Stay, [];
+};
+
+const STATES: usize = State::OpenDown as usize + 1;
+const EVENT_KINDS: usize = EventKind::IdleTimeout as usize + 1;
+
+const fn applies(rule: &Rule, state: usize, event: usize) -> bool {
+    if rule.state as usize != state {
+        return false;
+    }
+    let mut n = 0;
+    while n < rule.events.len() {
+        if rule.events[n] as usize == event {
+            return true;
+        }
+        n += 1;
+    }
+    false
+}
+
+// Whether the guard holds with the atoms in `facts` true and the rest false
+const fn holds(guard: &[(Atom, bool)], facts: u32) -> bool {
+    let mut n = 0;
+    while n < guard.len() {
+        let (atom, wanted) = guard[n];
+        if (facts & (1 << atom as u32) != 0) != wanted {
+            return false;
+        }
+        n += 1;
+    }
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flaw {
+    Overlap(usize, usize),   // Two rows (by index) that apply together
+    Gap(usize),              // No row applies, for the state and event of the row given
+    Unhandled(usize, usize), // A state (by number) that ignores a call (by kind)
+}
+
+// The first problem with a table, if there is one.  Every combination of
+// the facts the rows for a state and event depend on is tried.
+pub const fn flaw(rules: &[Rule]) -> Option<Flaw> {
+    let mut state = 0;
+    while state < STATES {
+        let mut event = 0;
+        while event < EVENT_KINDS {
+            // The atoms that matter, and a row to blame for a gap
+            let mut atoms: u32 = 0;
+            let mut some_row = None;
+            let mut n = 0;
+            while n < rules.len() {
+                if applies(&rules[n], state, event) {
+                    some_row = Some(n);
+                    let mut g = 0;
+                    while g < rules[n].guard.len() {
+                        atoms |= 1 << rules[n].guard[g].0 as u32;
+                        g += 1;
+                    }
+                }
+                n += 1;
+            }
+            let Some(some_row) = some_row else {
+                // Calls can come at any time; the rest only when expected
+                if event <= EventKind::Down as usize {
+                    return Some(Flaw::Unhandled(state, event));
+                }
+                event += 1;
+                continue;
+            };
+
+            // Each subset of the atoms, as the ones that are true
+            let mut facts = atoms;
+            loop {
+                let mut found = None;
+                let mut n = 0;
+                while n < rules.len() {
+                    if applies(&rules[n], state, event) && holds(rules[n].guard, facts) {
+                        if let Some(first) = found {
+                            return Some(Flaw::Overlap(first, n));
+                        }
+                        found = Some(n);
+                    }
+                    n += 1;
+                }
+                if found.is_none() {
+                    return Some(Flaw::Gap(some_row));
+                }
+                if facts == 0 {
+                    break;
+                }
+                facts = (facts - 1) & atoms;
+            }
+            event += 1;
+        }
+        state += 1;
+    }
+    None
+}
+
+const _: () = match flaw(RULES) {
+    None => {}
+    Some(Flaw::Overlap(..)) => panic!("rules.rs: two transitions apply at once"),
+    Some(Flaw::Gap(_)) => panic!("rules.rs: no transition applies"),
+    Some(Flaw::Unhandled(..)) => panic!("rules.rs: a state has no transition for a call"),
+};
+
+// The row for an event in a state, given what's true
+pub fn find(state: State, event: EventKind, holds: impl Fn(Atom) -> bool) -> Option<&'static Rule> {
+    RULES.iter().find(|rule| {
+        rule.state == state
+            && rule.events.contains(&event)
+            && rule
+                .guard
+                .iter()
+                .all(|&(atom, wanted)| holds(atom) == wanted)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logic::{
+        Algorithm, Command, Elevator, Event, IdlePolicy, ServedFloors, Settings, Side,
+        HIGHEST_FLOOR, LOWEST_FLOOR,
+    };
+    use crate::states::{ElevatorAfterEvent, IdleElevator};
+
+    #[test]
+    fn flaws_found() {
+        let overlapping: &[Rule] = rules! {
+            Idle [Panel Up Down] if [Here] => OpenDoor(Call), Open(Call), [];
+            Idle [Panel Up Down] if [] => MoveUp, MovingUp, [Register];
+        };
+        assert_eq!(flaw(overlapping), Some(Flaw::Overlap(0, 1)));
+        let missing: &[Rule] = rules! {
+            Idle [Panel Up Down] if [Here] => OpenDoor(Call), Open(Call), [];
+            Idle [Panel Up Down] if [!Here, Above] => MoveUp, MovingUp, [Register];
+        };
+        assert_eq!(flaw(missing), Some(Flaw::Gap(1)));
+        let deaf: &[Rule] = rules! {
+            Idle [Panel Down] if [] => MoveUp, MovingUp, [Register];
+        };
+        assert_eq!(
+            flaw(deaf),
+            Some(Flaw::Unhandled(
+                State::Idle as usize,
+                EventKind::Up as usize
+            ))
+        );
+    }
+
+    // The two machines agree, in buildings between them using every setting.
+    // They share the table but not what's done around it: the events
+    // handled first, and the calls answered on entering an open state.
+    #[test]
+    fn same_as_the_typestate_machine() {
+        let express = ServedFloors::only(&[1, 4, 5]);
+        let rear = ServedFloors::with_doors(&[1, 2, 4, 5], &[2, 5]);
+        for settings in [
+            Settings::new(),
+            Settings {
+                served: express,
+                ..Settings::new()
+            },
+            Settings {
+                served: rear,
+                ..Settings::new()
+            },
+            Settings {
+                anti_nuisance: true,
+                ..Settings::new()
+            },
+            Settings {
+                idle_policy: IdlePolicy::ReturnToLobby,
+                ..Settings::new()
+            },
+            Settings {
+                served: rear,
+                anti_nuisance: true,
+                idle_policy: IdlePolicy::ParkAtBusiestFloor,
+                ..Settings::new()
+            },
+        ] {
+            same_as_the_typestate_machine_with(settings);
+        }
+    }
+
+    fn same_as_the_typestate_machine_with(settings: Settings) {
+        let served = settings.served;
+        let mut logic = Elevator::with_settings(settings.clone());
+        let mut states = ElevatorAfterEvent::Idle(IdleElevator::with_settings(settings));
+        let side = || match fastrand::bool() {
+            true => Side::Front,
+            false => Side::Rear,
+        };
+        let mut overshot = None;
+        for _ in 0..100_000 {
+            let going_up = logic.snapshot().state == "moving-up";
+            let event = match (logic.produce_future_event(), overshot) {
+                // Now and then the stop comes too late
+                (_, Some(floor)) if fastrand::f64() < 0.1 => Event::Overshot(floor),
+                (Some(event), _) if fastrand::f64() < 0.75 => event,
+                _ => match fastrand::usize(..6) {
+                    0 => Event::Panel(fastrand::u8(LOWEST_FLOOR..=HIGHEST_FLOOR), side()),
+                    1 => Event::Up(fastrand::u8(LOWEST_FLOOR..HIGHEST_FLOOR), side()),
+                    2 => Event::Down(fastrand::u8(LOWEST_FLOOR + 1..=HIGHEST_FLOOR), side()),
+                    3 => Event::Load(fastrand::u8(0..=120)),
+                    4 => Event::IdleTimeout,
+                    _ => Event::PanelCancel(fastrand::u8(LOWEST_FLOOR..=HIGHEST_FLOOR), side()),
+                },
+            };
+            let command = logic.handle(event);
+            assert_eq!(command, Algorithm::handle(&mut states, event), "{event:?}");
+            assert_eq!(logic.snapshot(), states.snapshot());
+            states.check_invariants();
+            // The car can't run on past the end of the shaft
+            overshot = match (event, command) {
+                (Event::Arrived(floor), Some(Command::StopAndOpen { .. })) => {
+                    let beyond = match going_up {
+                        true => served.above(floor),
+                        false => served.below(floor),
+                    };
+                    beyond.map(|_| floor)
+                }
+                _ => None,
+            };
+        }
+    }
+}
@@ -0,0 +1,270 @@
+// Access control for floors that are locked after hours.  `Secured` sits in
+// front of an elevator algorithm and rejects car calls to a locked floor
+// unless a valid card was shown at the reader shortly before.
+//
+//     # security.conf
+//     window = 30               # seconds a card keeps locked floors open
+//     utc_offset = 60           # minutes east of UTC, for the schedule
+//     credential = 1001
+//     credential = 1002
+//     restrict = 4 18:00-07:00  # floor 4 is locked overnight
+//     restrict = 5 00:00-24:00  # floor 5 is always locked
+
+use crate::config;
+use crate::logic::{Algorithm, Command, Event, HIGHEST_FLOOR, LOWEST_FLOOR};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: u64 = 24 * 60 * 60;
+
+// A floor that is locked between two times of day (minutes after midnight).
+// The period may run past midnight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Restriction {
+    pub floor: u8,
+    pub from: u32,
+    pub until: u32,
+}
+
+impl Restriction {
+    fn parse(value: &str) -> Result<Self, String> {
+        let bad = || format!("bad value for restrict ({value})");
+        let (floor, period) = value.split_once(' ').ok_or_else(bad)?;
+        let (from, until) = period.trim().split_once('-').ok_or_else(bad)?;
+        let floor = floor.parse().map_err(|_| bad())?;
+        if !(LOWEST_FLOOR..=HIGHEST_FLOOR).contains(&floor) {
+            return Err(bad());
+        }
+        Ok(Restriction {
+            floor,
+            from: minutes(from).ok_or_else(bad)?,
+            until: minutes(until).ok_or_else(bad)?,
+        })
+    }
+
+    fn applies(&self, floor: u8, minute: u32) -> bool {
+        floor == self.floor
+            && if self.from <= self.until {
+                self.from <= minute && minute < self.until
+            } else {
+                minute >= self.from || minute < self.until
+            }
+    }
+}
+
+// "HH:MM" as minutes after midnight ("24:00" is the end of the day)
+fn minutes(time: &str) -> Option<u32> {
+    let (hours, mins) = time.split_once(':')?;
+    let (hours, mins): (u32, u32) = (hours.parse().ok()?, mins.parse().ok()?);
+    if mins < 60 && hours * 60 + mins <= 24 * 60 {
+        Some(hours * 60 + mins)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SecurityConfig {
+    pub credentials: Vec<u32>,
+    pub restrictions: Vec<Restriction>,
+    pub window: Duration,
+    pub utc_offset: i64, // minutes
+}
+
+impl SecurityConfig {
+    pub fn new() -> Self {
+        SecurityConfig {
+            credentials: Vec::new(),
+            restrictions: Vec::new(),
+            window: Duration::from_secs(30),
+            utc_offset: 0,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut security = SecurityConfig::new();
+        for (key, value) in config::read(path)? {
+            match key.as_str() {
+                "window" => security.window = Duration::from_secs(config::value(&key, &value)?),
+                "utc_offset" => security.utc_offset = config::value(&key, &value)?,
+                "credential" => security.credentials.push(config::value(&key, &value)?),
+                "restrict" => security
+                    .restrictions
+                    .push(Restriction::parse(&value).map_err(|e| format!("{path}: {e}"))?),
+                _ => return Err(format!("{path}: unknown setting {key}")),
+            }
+        }
+        Ok(security)
+    }
+
+    // Is the floor locked at this time of day?
+    pub fn restricted(&self, floor: u8, time_of_day: Duration) -> bool {
+        let minute = (time_of_day.as_secs() % DAY / 60) as u32;
+        self.restrictions.iter().any(|r| r.applies(floor, minute))
+    }
+
+    // Local time of day according to the system clock
+    pub fn time_of_day(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        Duration::from_secs((now + self.utc_offset * 60).rem_euclid(DAY as i64) as u64)
+    }
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct Secured<A> {
+    elevator: A,
+    config: SecurityConfig,
+    start: Duration, // Time of day when the controller clock read zero
+    now: Duration,
+    authorized_until: Option<Duration>,
+}
+
+impl<A: Algorithm> Secured<A> {
+    pub fn new(elevator: A, config: SecurityConfig) -> Self {
+        let start = config.time_of_day();
+        Secured::starting_at(elevator, config, start)
+    }
+
+    pub fn starting_at(elevator: A, config: SecurityConfig, start: Duration) -> Self {
+        Secured {
+            elevator,
+            config,
+            start,
+            now: Duration::ZERO,
+            authorized_until: None,
+        }
+    }
+
+    pub fn elevator(&self) -> &A {
+        &self.elevator
+    }
+
+    // Whether an event is dealt with here rather than passed on: a card
+    // shown at the reader, or a call to a floor that's locked at the time
+    pub fn intercepts(&self, event: Event, now: Duration) -> bool {
+        match event {
+            Event::Credential(_) => true,
+            Event::Panel(floor, _) => self.locked(floor, now),
+            _ => false,
+        }
+    }
+
+    fn locked(&self, floor: u8, now: Duration) -> bool {
+        self.config.restricted(floor, self.start + now)
+            && self.authorized_until.is_none_or(|until| now > until)
+    }
+}
+
+impl<A: Algorithm> Algorithm for Secured<A> {
+    // Without a timestamp, assume no time has passed since the last event
+    fn handle(&mut self, event: Event) -> Option<Command> {
+        self.handle_at(event, self.now)
+    }
+
+    fn handle_at(&mut self, event: Event, now: Duration) -> Option<Command> {
+        self.now = now;
+        if !self.intercepts(event, now) {
+            return self.elevator.handle_at(event, now);
+        }
+        match event {
+            Event::Credential(id) => {
+                if self.config.credentials.contains(&id) {
+                    self.authorized_until = Some(now + self.config.window);
+                }
+                None
+            }
+            _ => Some(Command::RejectEvent(event)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logic::{Elevator, Side};
+
+    const HOUR: u64 = 60 * 60;
+
+    fn after_hours() -> Secured<Elevator> {
+        let config = SecurityConfig {
+            credentials: vec![1001],
+            restrictions: vec![Restriction::parse("4 18:00-07:00").unwrap()],
+            ..SecurityConfig::new()
+        };
+        Secured::starting_at(Elevator::new(), config, Duration::from_secs(22 * HOUR))
+    }
+
+    #[test]
+    fn schedule_wraps_past_midnight() {
+        let config = SecurityConfig {
+            restrictions: vec![Restriction::parse("4 18:00-07:00").unwrap()],
+            ..SecurityConfig::new()
+        };
+        let at = |hour| Duration::from_secs(hour * HOUR);
+        assert!(config.restricted(4, at(18)));
+        assert!(config.restricted(4, at(3)));
+        assert!(config.restricted(4, at(24 + 3)));
+        assert!(!config.restricted(4, at(7)));
+        assert!(!config.restricted(4, at(12)));
+        assert!(!config.restricted(3, at(3)));
+    }
+
+    #[test]
+    fn rejects_bad_restrictions() {
+        assert!(Restriction::parse("4").is_err());
+        assert!(Restriction::parse("9 18:00-07:00").is_err());
+        assert!(Restriction::parse("4 18:60-07:00").is_err());
+        assert!(Restriction::parse("4 18:00").is_err());
+    }
+
+    #[test]
+    fn locked_floor_needs_a_card() {
+        let mut elevator = after_hours();
+        let now = Duration::from_secs(10);
+        assert!(elevator.intercepts(Event::Panel(4, Side::Front), now));
+        assert!(!elevator.intercepts(Event::Panel(3, Side::Front), now));
+        assert_eq!(
+            elevator.handle_at(Event::Panel(4, Side::Front), now),
+            Some(Command::RejectEvent(Event::Panel(4, Side::Front)))
+        );
+        assert_eq!(
+            elevator.handle_at(Event::Panel(3, Side::Front), now),
+            Some(Command::MoveUp)
+        );
+    }
+
+    #[test]
+    fn card_opens_locked_floor_for_a_while() {
+        let mut elevator = after_hours();
+        assert_eq!(
+            elevator.handle_at(Event::Credential(1001), Duration::from_secs(10)),
+            None
+        );
+        assert_eq!(
+            elevator.handle_at(Event::Panel(4, Side::Front), Duration::from_secs(20)),
+            Some(Command::MoveUp)
+        );
+        assert_eq!(
+            elevator.handle_at(Event::Panel(4, Side::Front), Duration::from_secs(60)),
+            Some(Command::RejectEvent(Event::Panel(4, Side::Front)))
+        );
+    }
+
+    #[test]
+    fn unknown_card_opens_nothing() {
+        let mut elevator = after_hours();
+        elevator.handle_at(Event::Credential(999), Duration::from_secs(10));
+        assert_eq!(
+            elevator.handle_at(Event::Panel(4, Side::Front), Duration::from_secs(11)),
+            Some(Command::RejectEvent(Event::Panel(4, Side::Front)))
+        );
+    }
+}
@@ -0,0 +1,1986 @@
+// In-process version of the Lifty hardware simulator.
+//
+// `Lifty` follows the same rules as lifty/lifty.rs (and crashes for the same
+// reasons), but it is driven by simulated clock ticks instead of a wall clock
+// and UDP.  With `physics` turned on in the building config the car moves
+// according to a `MotionProfile` rather than a fixed number of ticks per
+// floor, and approach events come when the car can only just stop in time.
+// `Simulation` wraps it up as a `Transport` for `LiftyController`,
+// plays a list of passengers against it and records how well they were served.
+// It can also inject faults (see faults.rs) and checks that the controller
+// never does anything unsafe.
+
+use crate::building::BuildingConfig;
+use crate::controller::{Door, LiftyController, Motor, Status, Transport};
+use crate::faults::{Fault, FaultPlan};
+use crate::logic::{
+    Algorithm, Doors, Event, Failure, Indicator, Side, HIGHEST_FLOOR, LOWEST_FLOOR, NUM_FLOORS,
+    RATED_LOAD,
+};
+use crate::motion::Motion;
+use crate::traffic::Passenger;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Heaviest load the sensor can report (percent of rated load)
+const MAX_LOAD: u8 = 200;
+
+// Turn this on if you want the simulator to be super picky
+const PEDANTIC: bool = false;
+
+// Slack for rounding errors when comparing positions
+const EPSILON: f64 = 1e-6;
+
+#[derive(Debug)]
+pub struct Lifty {
+    config: BuildingConfig,
+    pub floor: u8,
+    pub panel_buttons: [bool; NUM_FLOORS as usize],
+    pub up_buttons: [bool; NUM_FLOORS as usize],
+    pub down_buttons: [bool; NUM_FLOORS as usize],
+    pub rear_panel_buttons: [bool; NUM_FLOORS as usize],
+    pub rear_up_buttons: [bool; NUM_FLOORS as usize],
+    pub rear_down_buttons: [bool; NUM_FLOORS as usize],
+    pub indicator: Indicator,
+    pub indicator_floor: u8,
+    pub clock: usize,
+    pub motor: Motor,
+    pub door: Door,
+    pub doors: Doors, // Sides opened by the last door open command
+    pub stopping: bool,
+    pub motion: Motion,         // Only used with physics
+    pub stop_floor: Option<u8>, // Where the car is braking for
+    pub approached: Option<u8>, // Last floor announced (or too late to)
+    pub stuck_door: bool,
+    pub motor_wont_start: bool,
+    pub miss_approach: bool,
+    pub load: u8,
+    pub overload_warning: bool,
+    pub crashed: Option<String>,
+}
+
+fn index(floor: u8) -> usize {
+    (floor - LOWEST_FLOOR) as usize
+}
+
+// Split a command such as "CU3" into ("CU", 3)
+fn split_floor(cmd: &str) -> Option<(&str, u8)> {
+    let n = cmd.find(|c: char| c.is_ascii_digit())?;
+    let floor = cmd[n..].parse().ok()?;
+    if (LOWEST_FLOOR..=HIGHEST_FLOOR).contains(&floor) {
+        Some((&cmd[..n], floor))
+    } else {
+        None
+    }
+}
+
+impl Lifty {
+    pub fn new(config: &BuildingConfig) -> Lifty {
+        Lifty {
+            config: config.clone(),
+            floor: LOWEST_FLOOR,
+            panel_buttons: [false; NUM_FLOORS as usize],
+            up_buttons: [false; NUM_FLOORS as usize],
+            down_buttons: [false; NUM_FLOORS as usize],
+            rear_panel_buttons: [false; NUM_FLOORS as usize],
+            rear_up_buttons: [false; NUM_FLOORS as usize],
+            rear_down_buttons: [false; NUM_FLOORS as usize],
+            indicator: Indicator::Off,
+            indicator_floor: LOWEST_FLOOR,
+            clock: 0,
+            motor: Motor::Off,
+            door: Door::Closed,
+            doors: Doors::FRONT,
+            stopping: false,
+            motion: Motion::at_floor(&config.motion, LOWEST_FLOOR),
+            stop_floor: None,
+            approached: None,
+            stuck_door: false,
+            motor_wont_start: false,
+            miss_approach: false,
+            load: 0,
+            overload_warning: false,
+            crashed: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Lifty::new(&self.config);
+    }
+
+    fn crash(&mut self, reason: &str) {
+        if self.crashed.is_none() {
+            self.crashed = Some(reason.to_string());
+        }
+    }
+
+    // The row of buttons with a given name ("P", "RU", ...)
+    fn buttons(&mut self, button: &str) -> &mut [bool; NUM_FLOORS as usize] {
+        match button {
+            "P" => &mut self.panel_buttons,
+            "U" => &mut self.up_buttons,
+            "D" => &mut self.down_buttons,
+            "RP" => &mut self.rear_panel_buttons,
+            "RU" => &mut self.rear_up_buttons,
+            _ => &mut self.rear_down_buttons,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        let mut lit = Vec::new();
+        for row in ["P", "U", "D", "RP", "RU", "RD"] {
+            for floor in LOWEST_FLOOR..=HIGHEST_FLOOR {
+                let button = format!("{row}{floor}");
+                if self.button_lit(&button) {
+                    lit.push(button);
+                }
+            }
+        }
+        Status {
+            floor: self.floor,
+            motor: self.motor.clone(),
+            doors: self.doors,
+            door: self.door.clone(),
+            stopping: self.stopping,
+            indicator: self.indicator,
+            indicator_floor: self.indicator_floor,
+            lit,
+            crashed: self.crashed.is_some(),
+        }
+    }
+
+    pub fn button_lit(&self, button: &str) -> bool {
+        match split_floor(button) {
+            Some(("P", floor)) => self.panel_buttons[index(floor)],
+            Some(("U", floor)) => self.up_buttons[index(floor)],
+            Some(("D", floor)) => self.down_buttons[index(floor)],
+            Some(("RP", floor)) => self.rear_panel_buttons[index(floor)],
+            Some(("RU", floor)) => self.rear_up_buttons[index(floor)],
+            Some(("RD", floor)) => self.rear_down_buttons[index(floor)],
+            _ => false,
+        }
+    }
+
+    fn clear_button(&mut self, button: &str, floor: u8) {
+        let buttons = self.buttons(button);
+        if PEDANTIC && !buttons[index(floor)] {
+            self.crash("button not previously set");
+        } else {
+            buttons[index(floor)] = false;
+        }
+    }
+
+    fn set_indicator(&mut self, floor: u8, status: Indicator) {
+        if self.indicator != Indicator::Off && status != Indicator::Off {
+            self.crash("direction indicator already illuminated");
+        } else if PEDANTIC && self.indicator == Indicator::Off && status == Indicator::Off {
+            self.crash("direction indicator already off");
+        } else {
+            self.indicator = status;
+            self.indicator_floor = floor;
+        }
+    }
+
+    fn set_load(&mut self, load: u8) -> Option<String> {
+        if load > MAX_LOAD {
+            self.crash("load beyond sensor range");
+            return None;
+        }
+        self.load = load;
+        Some(format!("L{load}"))
+    }
+
+    fn set_motor(&mut self, status: Motor) {
+        if self.door != Door::Closed {
+            self.crash("motor command received while doors open");
+            return;
+        }
+        if status != Motor::Off && self.load > RATED_LOAD {
+            self.crash("motor command received while overloaded");
+            return;
+        }
+        if self.motor == Motor::Up && status == Motor::Down {
+            self.crash("violent direction switch (up->down)");
+            return;
+        }
+        if self.motor == Motor::Down && status == Motor::Up {
+            self.crash("violent direction switch (down->up)");
+            return;
+        }
+        if self.motor != status && status != Motor::Off && self.motor_wont_start {
+            self.motor_wont_start = false;
+        } else if self.motor != status {
+            self.motor = status;
+            self.clock = 0;
+        } else if status == Motor::Up {
+            self.crash("already moving up");
+        } else if status == Motor::Down {
+            self.crash("already moving down");
+        }
+    }
+
+    fn set_door(&mut self, status: Door) {
+        if self.motor != Motor::Off {
+            self.crash("door command received while moving");
+            return;
+        }
+        if !self.config.served.serves(self.floor) {
+            self.crash("no doors on this floor");
+            return;
+        }
+        if self.door == Door::Closing && status != Door::Closed {
+            self.crash("door command received while closing");
+            return;
+        }
+        if self.door == Door::Opening && status != Door::Open {
+            self.crash("door command received while opening");
+            return;
+        }
+        if self.door == Door::Open && status == Door::Opening {
+            self.crash("door already open");
+            return;
+        }
+        if self.door == Door::Closed && status == Door::Closing {
+            self.crash("door already closed");
+            return;
+        }
+        self.door = status;
+        self.clock = 0;
+    }
+
+    // Start opening the doors on the given sides
+    fn open_doors(&mut self, doors: Doors) {
+        self.set_door(Door::Opening);
+        if !self.config.served.doors(self.floor).contains(doors) {
+            self.crash("no such door on this floor");
+        }
+        self.doors = doors;
+    }
+
+    // A fault in the hardware itself.  Returns the event for a button press.
+    pub fn inject(&mut self, fault: &Fault) -> Option<String> {
+        match fault {
+            Fault::StuckDoor => self.stuck_door = true,
+            Fault::MotorWontStart => self.motor_wont_start = true,
+            Fault::MissApproach => self.miss_approach = true,
+            Fault::Press(button) => return self.handle_command(button),
+            Fault::Reset => self.reset(),
+            _ => {}
+        }
+        None
+    }
+
+    // Commands that would put passengers in danger, whatever state the
+    // controller thinks the car is in
+    pub fn unsafe_command(&self, cmd: &str) -> Option<&'static str> {
+        if self.crashed.is_some() {
+            return None;
+        }
+        match cmd {
+            "MU" | "MD" if self.door != Door::Closed => Some("car moved with the doors open"),
+            "MU" | "MD" if self.load > RATED_LOAD => Some("car moved while overloaded"),
+            "DO" | "DOR" | "DOB" if self.motor != Motor::Off => Some("doors opened while moving"),
+            "DO" | "DOR" | "DOB" if !self.config.served.serves(self.floor) => {
+                Some("doors opened where there's no landing")
+            }
+            _ => None,
+        }
+    }
+
+    fn approach(&mut self, floor: u8) -> Option<String> {
+        if self.miss_approach {
+            self.miss_approach = false;
+            None
+        } else {
+            Some(format!("A{floor}"))
+        }
+    }
+
+    // Same protocol as lifty.rs: button presses and the clock tick ("T")
+    // may produce an event for the controller.
+    pub fn handle_command(&mut self, cmd: &str) -> Option<String> {
+        if cmd == "R" {
+            self.reset();
+            return None;
+        }
+        if cmd == "Q" {
+            return Some(self.status().reply());
+        }
+        if self.crashed.is_some() {
+            return None;
+        }
+        match split_floor(cmd) {
+            Some(("U" | "CU" | "RU" | "CRU", HIGHEST_FLOOR)) => {
+                self.crash("No up button on top floor")
+            }
+            Some(("D" | "CD" | "RD" | "CRD", LOWEST_FLOOR)) => {
+                self.crash("No down button on bottom floor")
+            }
+            Some(("IU", HIGHEST_FLOOR)) => self.crash("No up indicator light on top floor"),
+            Some(("ID", LOWEST_FLOOR)) => self.crash("No down indicator light on bottom floor"),
+            Some((button @ ("P" | "U" | "D" | "RP" | "RU" | "RD"), floor)) => {
+                self.buttons(button)[index(floor)] = true;
+                return Some(cmd.to_string());
+            }
+            Some((button @ ("CP" | "CU" | "CD" | "CRP" | "CRU" | "CRD"), floor)) => {
+                self.clear_button(&button[1..], floor)
+            }
+            Some(("IU", floor)) => self.set_indicator(floor, Indicator::Up),
+            Some(("ID", floor)) => self.set_indicator(floor, Indicator::Down),
+            Some(("CI", floor)) => self.set_indicator(floor, Indicator::Off),
+            _ => match cmd {
+                "MU" => self.set_motor(Motor::Up),
+                "MD" => self.set_motor(Motor::Down),
+                "S" => {
+                    if self.stopping {
+                        self.crash("Already made a request to stop");
+                    } else if self.motor != Motor::Off && self.config.physics {
+                        // Stop at the floor just announced, if there's still
+                        // room to.  Otherwise the car carries on, as it does
+                        // without physics.
+                        self.stop_floor = self
+                            .floor_ahead(|distance, stopping| distance + EPSILON >= stopping)
+                            .filter(|&floor| Some(floor) == self.approached);
+                        self.stopping = self.stop_floor.is_some();
+                    } else if self.motor != Motor::Off {
+                        // If we can safely stop we will.
+                        if self.clock <= self.config.ticks_per_floor - self.config.approach_ticks {
+                            self.stopping = true;
+                        }
+                    } else {
+                        self.crash("Request to stop, but not moving");
+                    }
+                }
+                "DO" => self.open_doors(Doors::FRONT),
+                "DOR" => self.open_doors(Doors::REAR),
+                "DOB" => self.open_doors(Doors::BOTH),
+                "DC" => self.set_door(Door::Closing),
+                "OW" => self.overload_warning = true,
+                "CW" => self.overload_warning = false,
+                "T" => return self.handle_tick(),
+                _ if cmd.starts_with('L') && cmd[1..].parse::<u8>().is_ok() => {
+                    return self.set_load(cmd[1..].parse().unwrap());
+                }
+                _ if cmd.starts_with('K') && cmd[1..].parse::<u32>().is_ok() => {
+                    return Some(cmd.to_string());
+                }
+                _ => self.crash("Unrecognized command"),
+            },
+        }
+        None
+    }
+
+    // The first served floor ahead of the car, in the direction it's
+    // moving, that passes a test on its distance and the stopping distance
+    fn floor_ahead(&self, test: impl Fn(f64, f64) -> bool) -> Option<u8> {
+        let profile = &self.config.motion;
+        let up = self.motor == Motor::Up;
+        let stopping = self.motion.stopping_distance(profile);
+        let floors: Vec<u8> = if up {
+            (LOWEST_FLOOR..=HIGHEST_FLOOR).collect()
+        } else {
+            (LOWEST_FLOOR..=HIGHEST_FLOOR).rev().collect()
+        };
+        floors.into_iter().find(|&floor| {
+            let distance = self.motion.distance_to(profile, floor, up);
+            self.config.served.serves(floor) && distance > EPSILON && test(distance, stopping)
+        })
+    }
+
+    fn physics_tick(&mut self) -> Option<String> {
+        let profile = self.config.motion.clone();
+        let up = self.motor == Motor::Up;
+        let dt = self.config.tick_interval as f64 / 1000.0;
+        let stopped = self.motion.step(&profile, dt, up, self.stop_floor);
+        self.floor = self.motion.floor(&profile, up);
+
+        if self.motion.position > profile.level(HIGHEST_FLOOR) + EPSILON {
+            self.crash("Hit the roof!");
+        } else if self.motion.position < -EPSILON {
+            self.crash("Hit the ground!");
+        } else if stopped {
+            self.set_motor(Motor::Off);
+            self.stopping = false;
+            self.stop_floor = None;
+            self.approached = None;
+            return Some(format!("S{}", self.floor));
+        } else if !self.stopping {
+            // Announce the next floor on the last tick there's still room to
+            // stop for it.  If it's already too late it's passed over quietly.
+            let floor = self.floor_ahead(|_, _| true)?;
+            let mut next = self.motion.clone();
+            next.step(&profile, dt, up, None);
+            let distance = self.motion.distance_to(&profile, floor, up);
+            let stopping = self.motion.stopping_distance(&profile);
+            if Some(floor) != self.approached
+                && next.stopping_distance(&profile) > next.distance_to(&profile, floor, up)
+            {
+                self.approached = Some(floor);
+                if stopping <= distance + EPSILON {
+                    return self.approach(floor);
+                }
+            }
+        }
+        None
+    }
+
+    fn handle_tick(&mut self) -> Option<String> {
+        if self.config.physics && self.motor != Motor::Off {
+            return self.physics_tick();
+        }
+        let approach = self.config.ticks_per_floor - self.config.approach_ticks;
+        self.clock += 1;
+        if self.motor == Motor::Up {
+            if self.floor >= HIGHEST_FLOOR {
+                self.crash("Hit the roof!");
+            } else if self.clock == approach && self.config.served.serves(self.floor + 1) {
+                return self.approach(self.floor + 1);
+            } else if self.clock >= self.config.ticks_per_floor {
+                self.floor += 1;
+                self.clock = 0;
+                if self.stopping {
+                    self.set_motor(Motor::Off);
+                    self.stopping = false;
+                    return Some(format!("S{}", self.floor));
+                }
+            }
+        } else if self.motor == Motor::Down {
+            if self.floor <= LOWEST_FLOOR {
+                self.crash("Hit the ground!");
+            } else if self.clock == approach && self.config.served.serves(self.floor - 1) {
+                return self.approach(self.floor - 1);
+            } else if self.clock >= self.config.ticks_per_floor {
+                self.floor -= 1;
+                self.clock = 0;
+                if self.stopping {
+                    self.set_motor(Motor::Off);
+                    self.stopping = false;
+                    return Some(format!("S{}", self.floor));
+                }
+            }
+        } else if self.stuck_door {
+            // Jammed; the doors stay where they are
+        } else if self.door == Door::Closing {
+            if self.clock > self.config.ticks_for_door {
+                self.set_door(Door::Closed);
+                return Some(format!("C{}", self.floor));
+            }
+        } else if self.door == Door::Opening && self.clock > self.config.ticks_for_door {
+            self.set_door(Door::Open);
+            return Some(format!("O{}", self.floor));
+        }
+        None
+    }
+}
+
+// Lifty's name for a button, e.g. "U3" or "RU3" for the rear up button
+fn button(side: Side, kind: char, floor: u8) -> String {
+    match side {
+        Side::Front => format!("{kind}{floor}"),
+        Side::Rear => format!("R{kind}{floor}"),
+    }
+}
+
+// Progress of one passenger through the simulation
+#[derive(Debug, Clone)]
+struct Trip {
+    passenger: Passenger,
+    boarded: Option<u64>,
+    alighted: Option<u64>,
+}
+
+impl Trip {
+    fn going_up(&self) -> bool {
+        self.passenger.destination > self.passenger.origin
+    }
+
+    fn hall_button(&self) -> String {
+        let kind = if self.going_up() { 'U' } else { 'D' };
+        button(self.passenger.origin_side, kind, self.passenger.origin)
+    }
+
+    fn panel_button(&self) -> String {
+        button(
+            self.passenger.destination_side,
+            'P',
+            self.passenger.destination,
+        )
+    }
+
+    fn waiting(&self, tick: u64) -> bool {
+        self.passenger.arrival <= tick && self.boarded.is_none()
+    }
+}
+
+// Summary of a simulation run.  Times are in ticks.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub passengers: usize,
+    pub delivered: usize,
+    pub total_wait: u64,
+    pub max_wait: u64,
+    pub total_journey: u64,
+    pub max_journey: u64,
+    pub stops: usize,
+    pub distance: usize,
+    pub reversals: usize,
+    pub ticks: u64,
+    pub overloads: usize,
+    pub faults: usize,
+    pub violations: Vec<String>, // Unsafe things the controller did
+    pub crash: Option<String>,
+    pub resets: usize,            // Times the controller had to reset Lifty
+    pub failure: Option<Failure>, // Why the car was taken out of service, if it was
+}
+
+impl Stats {
+    pub fn mean_wait(&self) -> f64 {
+        self.total_wait as f64 / self.delivered.max(1) as f64
+    }
+
+    pub fn mean_journey(&self) -> f64 {
+        self.total_journey as f64 / self.delivered.max(1) as f64
+    }
+}
+
+pub struct Simulation {
+    pub lifty: Lifty,
+    tick: u64,
+    tick_interval: u64,
+    limit: u64,
+    rated_load: u32,
+    trips: Vec<Trip>,
+    boarders: Vec<usize>, // Who got on at the current stop, in order
+    pending: VecDeque<String>,
+    stops: usize,
+    distance: usize,
+    reversals: usize,
+    overloads: usize,
+    last_motor: Motor,
+    faults: FaultPlan,
+    injected: usize,
+    drop_events: usize,
+    duplicate_events: usize,
+    delay_event: Option<u64>,
+    delayed: Vec<(u64, String)>, // Late events and when they turn up
+    drop_commands: usize,
+    duplicate_commands: usize,
+    disconnected: bool,
+    violations: Vec<String>,
+    crash: Option<String>, // The first crash, even if Lifty has been reset since
+}
+
+impl Simulation {
+    // `limit` is the tick at which the simulation gives up on any passengers
+    // that still haven't been delivered.
+    pub fn new(config: &BuildingConfig, passengers: &[Passenger], limit: u64) -> Simulation {
+        Simulation::with_faults(config, passengers, limit, FaultPlan::new())
+    }
+
+    pub fn with_faults(
+        config: &BuildingConfig,
+        passengers: &[Passenger],
+        limit: u64,
+        faults: FaultPlan,
+    ) -> Simulation {
+        Simulation {
+            lifty: Lifty::new(config),
+            tick: 0,
+            tick_interval: config.tick_interval,
+            limit,
+            rated_load: config.rated_load,
+            trips: passengers
+                .iter()
+                .map(|p| Trip {
+                    passenger: p.clone(),
+                    boarded: None,
+                    alighted: None,
+                })
+                .collect(),
+            boarders: Vec::new(),
+            pending: VecDeque::new(),
+            stops: 0,
+            distance: 0,
+            reversals: 0,
+            overloads: 0,
+            last_motor: Motor::Off,
+            faults,
+            injected: 0,
+            drop_events: 0,
+            duplicate_events: 0,
+            delay_event: None,
+            delayed: Vec::new(),
+            drop_commands: 0,
+            duplicate_commands: 0,
+            disconnected: false,
+            violations: Vec::new(),
+            crash: None,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.tick >= self.limit || self.trips.iter().all(|t| t.alighted.is_some())
+    }
+
+    fn press(&mut self, button: &str) {
+        if !self.lifty.button_lit(button) {
+            if let Some(event) = self.lifty.handle_command(button) {
+                self.deliver(event);
+            }
+        }
+    }
+
+    fn riding(&self) -> impl Iterator<Item = &Trip> {
+        self.trips
+            .iter()
+            .filter(|t| t.boarded.is_some() && t.alighted.is_none())
+    }
+
+    // Percent of rated load currently in the car
+    fn load(&self) -> u32 {
+        self.riding().map(|t| t.passenger.weight).sum::<u32>() * 100 / self.rated_load
+    }
+
+    fn update_load_sensor(&mut self) {
+        let load = self.load().min(MAX_LOAD as u32) as u8;
+        if load != self.lifty.load {
+            if let Some(event) = self.lifty.handle_command(&format!("L{load}")) {
+                self.deliver(event);
+            }
+        }
+    }
+
+    // Doors have opened.  Riders for this floor get out and anyone waiting to
+    // go in the direction shown on the indicator gets in, as long as it's
+    // through the door on their side.  People squeeze in
+    // while there's room; the first one who doesn't fit tries anyway and only
+    // steps back out when the overload warning goes off.
+    fn exchange_passengers(&mut self) {
+        let floor = self.lifty.floor;
+        let doors = self.lifty.doors;
+        let direction = if self.lifty.indicator_floor == floor {
+            self.lifty.indicator
+        } else {
+            Indicator::Off
+        };
+        for trip in self.trips.iter_mut() {
+            if trip.boarded.is_some()
+                && trip.alighted.is_none()
+                && trip.passenger.destination == floor
+                && doors.has(trip.passenger.destination_side)
+            {
+                trip.alighted = Some(self.tick);
+            }
+        }
+
+        self.boarders.clear();
+        for n in 0..self.trips.len() {
+            let trip = &self.trips[n];
+            if trip.waiting(self.tick)
+                && trip.passenger.origin == floor
+                && doors.has(trip.passenger.origin_side)
+                && match direction {
+                    Indicator::Up => trip.going_up(),
+                    Indicator::Down => !trip.going_up(),
+                    Indicator::Off => true,
+                }
+            {
+                if self.load() > RATED_LOAD as u32 {
+                    break;
+                }
+                self.trips[n].boarded = Some(self.tick);
+                self.boarders.push(n);
+            }
+        }
+        self.update_load_sensor();
+
+        for n in self.boarders.clone() {
+            let button = self.trips[n].panel_button();
+            self.press(&button);
+        }
+    }
+
+    // The overload warning is sounding.  The last ones in get back out.
+    fn step_off(&mut self) {
+        while self.load() > RATED_LOAD as u32 {
+            match self.boarders.pop() {
+                Some(n) => self.trips[n].boarded = None,
+                None => break,
+            }
+        }
+        self.update_load_sensor();
+    }
+
+    // Lifty stays crashed until it's reset, but the crash is worth knowing
+    // about even once it has been
+    fn note_crash(&mut self) {
+        if self.crash.is_none() {
+            self.crash = self.lifty.crashed.clone();
+        }
+    }
+
+    // Pass an event from Lifty on to the controller, unless the network
+    // loses it, repeats it or holds it up
+    fn deliver(&mut self, event: String) {
+        if self.disconnected {
+            return;
+        }
+        if self.drop_events > 0 {
+            self.drop_events -= 1;
+        } else if let Some(ticks) = self.delay_event.take() {
+            self.delayed.push((self.tick + ticks, event));
+        } else {
+            if self.duplicate_events > 0 {
+                self.duplicate_events -= 1;
+                self.pending.push_back(event.clone());
+            }
+            self.pending.push_back(event);
+        }
+    }
+
+    fn inject(&mut self, fault: Fault) {
+        self.injected += 1;
+        match fault {
+            Fault::DropEvent => self.drop_events += 1,
+            Fault::DuplicateEvent => self.duplicate_events += 1,
+            Fault::DelayEvent(ticks) => self.delay_event = Some(ticks),
+            Fault::DropCommand => self.drop_commands += 1,
+            Fault::DuplicateCommand => self.duplicate_commands += 1,
+            Fault::Disconnect => self.disconnected = true,
+            Fault::Garble(datagram) => self.deliver(datagram),
+            _ => {
+                if let Some(event) = self.lifty.inject(&fault) {
+                    self.deliver(event);
+                }
+            }
+        }
+    }
+
+    // Advance the world by one clock tick
+    fn step(&mut self) {
+        self.tick += 1;
+
+        for fault in self.faults.due(self.tick) {
+            self.inject(fault);
+        }
+        let tick = self.tick;
+        let (arrived, delayed) = self.delayed.drain(..).partition(|(at, _)| *at <= tick);
+        self.delayed = delayed;
+        for (_, event) in arrived {
+            self.pending.push_back(event);
+        }
+
+        let floor = self.lifty.floor;
+        let crashed = self.lifty.crashed.is_some();
+        let event = self.lifty.handle_command("T");
+        if let (false, Some(reason @ ("Hit the roof!" | "Hit the ground!"))) =
+            (crashed, self.lifty.crashed.as_deref())
+        {
+            self.violations.push(format!("{reason} (tick {tick})"));
+        }
+        self.note_crash();
+        self.distance += floor.abs_diff(self.lifty.floor) as usize;
+
+        // Passengers press the hall button when they show up, and again if
+        // the doors close on them without the button being lit.
+        let callers: Vec<usize> = (0..self.trips.len())
+            .filter(|&n| {
+                let trip = &self.trips[n];
+                trip.passenger.arrival == self.tick
+                    || (trip.waiting(self.tick)
+                        && event == Some(format!("C{}", trip.passenger.origin)))
+            })
+            .collect();
+
+        if let Some(event) = event {
+            let opened = event.starts_with('O');
+            self.deliver(event);
+            if opened {
+                self.stops += 1;
+                self.exchange_passengers();
+            }
+        }
+        for n in callers {
+            if self.trips[n].boarded.is_none() {
+                let button = self.trips[n].hall_button();
+                self.press(&button);
+            }
+        }
+
+        // Riders press their button again if it goes out before they get
+        // there (e.g. cancelled by anti-nuisance)
+        let floor = self.lifty.floor;
+        let buttons: Vec<String> = self
+            .riding()
+            .filter(|t| t.passenger.destination != floor)
+            .map(|t| t.panel_button())
+            .collect();
+        for button in buttons {
+            self.press(&button);
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            passengers: self.trips.len(),
+            stops: self.stops,
+            distance: self.distance,
+            reversals: self.reversals,
+            ticks: self.tick,
+            overloads: self.overloads,
+            faults: self.injected,
+            violations: self.violations.clone(),
+            crash: self.crash.clone(),
+            ..Stats::default()
+        };
+        for trip in &self.trips {
+            if let (Some(boarded), Some(alighted)) = (trip.boarded, trip.alighted) {
+                let wait = boarded - trip.passenger.arrival;
+                let journey = alighted - trip.passenger.arrival;
+                stats.delivered += 1;
+                stats.total_wait += wait;
+                stats.max_wait = stats.max_wait.max(wait);
+                stats.total_journey += journey;
+                stats.max_journey = stats.max_journey.max(journey);
+            }
+        }
+        stats
+    }
+}
+
+impl Simulation {
+    // A command that made it across the network
+    fn carry_out(&mut self, message: &str) {
+        if let Some(violation) = self.lifty.unsafe_command(message) {
+            self.violations
+                .push(format!("{violation}: {message} (tick {})", self.tick));
+        }
+        let motor = match message {
+            "MU" => Motor::Up,
+            "MD" => Motor::Down,
+            _ => Motor::Off,
+        };
+        if motor != Motor::Off {
+            if self.last_motor != Motor::Off && self.last_motor != motor {
+                self.reversals += 1;
+            }
+            self.last_motor = motor;
+        }
+        // Lifty reports buttons pressed over the network like any others
+        if let Some(event) = self.lifty.handle_command(message) {
+            self.deliver(event);
+        }
+        self.note_crash();
+        if message == "OW" {
+            self.overloads += 1;
+            self.step_off();
+        }
+    }
+}
+
+impl Transport for Simulation {
+    fn send(&mut self, message: &str) {
+        if self.disconnected {
+            return;
+        }
+        if self.drop_commands > 0 {
+            self.drop_commands -= 1;
+            return;
+        }
+        if self.duplicate_commands > 0 {
+            self.duplicate_commands -= 1;
+            self.carry_out(message);
+        }
+        self.carry_out(message);
+    }
+
+    fn receive(&mut self, timeout: Option<Duration>) -> Option<String> {
+        let deadline =
+            timeout.map(|t| self.tick + (t.as_millis() as u64).div_ceil(self.tick_interval));
+        while self.pending.is_empty() {
+            if self.finished() || deadline.is_some_and(|d| self.tick >= d) {
+                return None;
+            }
+            self.step();
+        }
+        self.pending.pop_front()
+    }
+
+    fn now(&self) -> Duration {
+        Duration::from_millis(self.tick * self.tick_interval)
+    }
+}
+
+// Run the control algorithm against the passengers until everyone has been
+// delivered, the car goes out of service, or the time limit runs out.  If the
+// hardware crashes the controller resets it and carries on.
+pub fn run(
+    algorithm: &mut dyn Algorithm,
+    config: &BuildingConfig,
+    passengers: &[Passenger],
+    limit: u64,
+) -> Stats {
+    run_with_faults(algorithm, config, passengers, limit, FaultPlan::new())
+}
+
+pub fn run_with_faults(
+    algorithm: &mut dyn Algorithm,
+    config: &BuildingConfig,
+    passengers: &[Passenger],
+    limit: u64,
+    faults: FaultPlan,
+) -> Stats {
+    let mut controller =
+        LiftyController::with_transport(Simulation::with_faults(config, passengers, limit, faults));
+    controller.configure(config);
+    let mut failure = None;
+    let mut resets = 0;
+    while let Some(event) = controller.receive() {
+        if event == Event::Reset {
+            resets += 1;
+        }
+        if let Some(command) = algorithm.handle_at(event, controller.now()) {
+            controller.send(command);
+        }
+        // Nothing more is going to happen once the car is out of service
+        if let Event::Fault(reason) = event {
+            failure = Some(reason);
+            break;
+        }
+    }
+    Stats {
+        resets,
+        failure,
+        ..controller.transport().stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::{call, call_button};
+    use crate::diagram::Machine;
+    use crate::logic::{
+        Command, Elevator, Event, HardwareState, IdlePolicy, ServedFloors, Settings, NUM_FLOORS,
+    };
+    use crate::motion::MotionProfile;
+    use crate::snapshot::Snapshot;
+    use crate::states::{ElevatorAfterEvent, IdleElevator};
+    use crate::traffic::{Pattern, TrafficProfile};
+
+    fn check_all_delivered(algorithm: &mut dyn Algorithm, pattern: Pattern) {
+        let config = BuildingConfig::new();
+        let profile = TrafficProfile {
+            passengers: 200,
+            pattern,
+            ..TrafficProfile::new()
+        };
+        let stats = run(algorithm, &config, &profile.generate(&config), 1_000_000);
+        assert_eq!(stats.crash, None);
+        assert_eq!(stats.delivered, stats.passengers);
+    }
+
+    // The two machines, each made for a building's settings
+    const MACHINES: [fn(Settings) -> Box<dyn Algorithm>; 2] = [
+        |settings| Box::new(Elevator::with_settings(settings)),
+        |settings| {
+            Box::new(ElevatorAfterEvent::Idle(IdleElevator::with_settings(
+                settings,
+            )))
+        },
+    ];
+
+    // Runs each machine in turn like `run_with_faults`, with the same
+    // passengers and faults.  `watch` is shown each event, the commands the
+    // machine answered it with and the controller, and keeps what it finds
+    // in its own copy of `found` for each machine.
+    fn run_both<T: Clone>(
+        config: &BuildingConfig,
+        passengers: &[Passenger],
+        faults: impl Fn() -> FaultPlan,
+        found: T,
+        mut watch: impl FnMut(&mut T, Event, &[&Command], &mut LiftyController<Simulation>),
+    ) -> [(Stats, T); 2] {
+        MACHINES.map(|machine| {
+            let mut algorithm = machine(config.settings());
+            let mut found = found.clone();
+            let simulation = Simulation::with_faults(config, passengers, 1_000_000, faults());
+            let mut controller = LiftyController::with_transport(simulation);
+            controller.configure(config);
+            let (mut resets, mut failure) = (0, None);
+            while let Some(event) = controller.receive() {
+                if event == Event::Reset {
+                    resets += 1;
+                }
+                let command = algorithm.handle_at(event, controller.now());
+                let mut sent = Vec::new();
+                let mut pending: Vec<&Command> = command.iter().collect();
+                while let Some(command) = pending.pop() {
+                    match command {
+                        Command::Sequence(commands) => pending.extend(commands.iter().rev()),
+                        command => sent.push(command),
+                    }
+                }
+                watch(&mut found, event, &sent, &mut controller);
+                if let Some(command) = command {
+                    controller.send(command);
+                }
+                if let Event::Fault(reason) = event {
+                    failure = Some(reason);
+                    break;
+                }
+            }
+            let stats = Stats {
+                resets,
+                failure,
+                ..controller.transport().stats()
+            };
+            (stats, found)
+        })
+    }
+
+    #[test]
+    fn logic_delivers_everyone() {
+        check_all_delivered(&mut Elevator::new(), Pattern::Uniform);
+        check_all_delivered(&mut Elevator::new(), Pattern::UpPeak);
+        check_all_delivered(&mut Elevator::new(), Pattern::DownPeak);
+    }
+
+    #[test]
+    fn states_delivers_everyone() {
+        let idle = || ElevatorAfterEvent::Idle(IdleElevator::new());
+        check_all_delivered(&mut idle(), Pattern::Uniform);
+        check_all_delivered(&mut idle(), Pattern::UpPeak);
+        check_all_delivered(&mut idle(), Pattern::DownPeak);
+    }
+
+    // Lifty reports the car arriving and the doors closing when they do,
+    // and they're what the machine expects next.  Feeding the machine its
+    // own predictions as well would have it handle each one twice.
+    fn check_reported_as_expected<M: Machine>() {
+        let config = BuildingConfig::new();
+        let profile = TrafficProfile {
+            passengers: 50,
+            ..TrafficProfile::new()
+        };
+        let passengers = profile.generate(&config);
+        let simulation = Simulation::new(&config, &passengers, 1_000_000);
+        let mut controller = LiftyController::with_transport(simulation);
+        let mut machine = M::with_settings(config.settings());
+        let mut expected = None;
+        let mut reported = 0;
+        while let Some(event) = controller.receive() {
+            if let Event::Arrived(_) | Event::Closed(_) = event {
+                assert_eq!(expected, Some(event));
+                reported += 1;
+            }
+            if let Some(command) = machine.handle_at(event, controller.now()) {
+                controller.send(command);
+            }
+            expected = machine.produce_future_event();
+        }
+        let stats = controller.transport().stats();
+        assert_eq!(stats.crash, None);
+        assert_eq!(stats.delivered, stats.passengers);
+        assert!(reported > 0);
+    }
+
+    #[test]
+    fn hardware_reports_what_the_machine_expects() {
+        check_reported_as_expected::<Elevator>();
+        check_reported_as_expected::<ElevatorAfterEvent>();
+    }
+
+    #[test]
+    fn overloaded_car_waits_for_someone_to_get_out() {
+        let config = BuildingConfig {
+            rated_load: 300,
+            ..BuildingConfig::new()
+        };
+        let profile = TrafficProfile {
+            passengers: 100,
+            duration: 60,
+            pattern: Pattern::DownPeak,
+            ..TrafficProfile::new()
+        };
+        let passengers = profile.generate(&config);
+        // Counting the hall calls a full car passed by
+        let found = (true, 0, 0); // Going up, load, calls bypassed
+        let runs = run_both(
+            &config,
+            &passengers,
+            FaultPlan::new,
+            found,
+            |(going_up, load, bypassed), event, sent, controller| {
+                for command in sent {
+                    match command {
+                        Command::MoveUp => *going_up = true,
+                        Command::MoveDown => *going_up = false,
+                        _ => {}
+                    }
+                }
+                match event {
+                    Event::Load(weighed) => *load = weighed,
+                    Event::Arrived(floor) if sent.is_empty() && *load > config.bypass_load => {
+                        let call = if *going_up { 'U' } else { 'D' };
+                        let lifty = &controller.transport().lifty;
+                        if lifty.button_lit(&format!("{call}{floor}")) {
+                            *bypassed += 1;
+                        }
+                    }
+                    _ => {}
+                }
+            },
+        );
+        for (stats, (_, _, bypassed)) in runs {
+            assert_eq!(stats.crash, None);
+            assert!(stats.overloads > 0);
+            assert!(bypassed > 0);
+            assert_eq!(stats.delivered, stats.passengers);
+        }
+    }
+
+    #[test]
+    fn parked_car_delivers_everyone() {
+        for idle_policy in [IdlePolicy::ReturnToLobby, IdlePolicy::ParkAtBusiestFloor] {
+            let config = BuildingConfig {
+                idle_policy,
+                idle_delay: 2000,
+                ..BuildingConfig::new()
+            };
+            // Few enough that the car is often left idle
+            let profile = TrafficProfile {
+                passengers: 20,
+                ..TrafficProfile::new()
+            };
+            let passengers = profile.generate(&config);
+            // Checking that an idle car heads for the parking floor the policy
+            // picks, and counting the times it sets off to park
+            let found = ([0; NUM_FLOORS as usize], LOWEST_FLOOR, 0); // Demand, floor, parked
+            let runs = run_both(
+                &config,
+                &passengers,
+                FaultPlan::new,
+                found,
+                |(demand, floor, parked), event, sent, _| match event {
+                    Event::Up(call, _) | Event::Down(call, _) => {
+                        demand[(call - LOWEST_FLOOR) as usize] += 1
+                    }
+                    Event::Arrived(at) | Event::Closed(at) => *floor = at,
+                    Event::IdleTimeout => {
+                        // The busiest floor by hall calls, staying put on a tie
+                        let park = match config.idle_policy {
+                            IdlePolicy::ReturnToLobby => config.served.lowest(),
+                            _ => (config.served.floors())
+                                .max_by_key(|&at| {
+                                    (demand[(at - LOWEST_FLOOR) as usize], at == *floor)
+                                })
+                                .unwrap(),
+                        };
+                        match sent {
+                            [Command::MoveUp] => assert!(park > *floor),
+                            [Command::MoveDown] => assert!(park < *floor),
+                            _ => return,
+                        }
+                        *parked += 1;
+                    }
+                    _ => {}
+                },
+            );
+            for (stats, (_, _, parked)) in runs {
+                assert_eq!(stats.crash, None);
+                assert!(parked > 0);
+                assert_eq!(stats.delivered, stats.passengers);
+            }
+        }
+    }
+
+    #[test]
+    fn anti_nuisance_delivers_everyone() {
+        for anti_nuisance in [false, true] {
+            let config = BuildingConfig {
+                anti_nuisance,
+                ..BuildingConfig::new()
+            };
+            let profile = TrafficProfile {
+                passengers: 200,
+                ..TrafficProfile::new()
+            };
+            let passengers = profile.generate(&config);
+            // Pranksters press car buttons, and the car calls cancelled as
+            // the doors close are counted
+            let pranks: Vec<Fault> = (LOWEST_FLOOR..=HIGHEST_FLOOR)
+                .map(|floor| Fault::Press(format!("P{floor}")))
+                .collect();
+            let faults = || FaultPlan::random(7, 0.02, pranks.clone());
+            let runs = run_both(
+                &config,
+                &passengers,
+                faults,
+                0,
+                |cancelled, event, sent, _| {
+                    if let Event::Closed(_) = event {
+                        for command in sent {
+                            if let Command::CancelCarCalls(calls) = command {
+                                *cancelled += calls.len();
+                            }
+                        }
+                    }
+                },
+            );
+            for (stats, cancelled) in runs {
+                assert_eq!(stats.crash, None);
+                assert_eq!(cancelled > 0, anti_nuisance);
+                assert_eq!(stats.delivered, stats.passengers);
+            }
+        }
+    }
+
+    // For `run_both`, noting each floor the machine hears the car arrive at
+    // and each floor and set of doors it opens
+    fn note_stops(
+        (arrivals, openings): &mut (Vec<u8>, Vec<(u8, Doors)>),
+        event: Event,
+        sent: &[&Command],
+        _: &mut LiftyController<Simulation>,
+    ) {
+        if let Event::Arrived(floor) = event {
+            arrivals.push(floor);
+        }
+        for command in sent {
+            if let Command::StopAndOpen { floor, doors, .. }
+            | Command::OpenDoor { floor, doors, .. } = command
+            {
+                openings.push((*floor, *doors));
+            }
+        }
+    }
+
+    #[test]
+    fn express_zone_delivers_everyone() {
+        let config = BuildingConfig {
+            served: ServedFloors::only(&[1, 4, 5]),
+            ..BuildingConfig::new()
+        };
+        for pattern in [Pattern::Uniform, Pattern::UpPeak, Pattern::DownPeak] {
+            let profile = TrafficProfile {
+                passengers: 100,
+                pattern,
+                ..TrafficProfile::new()
+            };
+            let passengers = profile.generate(&config);
+            assert!(passengers
+                .iter()
+                .all(|p| config.served.serves(p.origin) && config.served.serves(p.destination)));
+
+            let runs = run_both(
+                &config,
+                &passengers,
+                FaultPlan::new,
+                Default::default(),
+                note_stops,
+            );
+            for (stats, (arrivals, openings)) in runs {
+                assert_eq!(stats.crash, None);
+                assert_eq!(stats.delivered, stats.passengers);
+                // Lifty's arrivals at 2 and 3 don't get as far as the algorithm
+                assert!(arrivals.iter().all(|&floor| config.served.serves(floor)));
+                assert!(openings
+                    .iter()
+                    .all(|&(floor, _)| config.served.serves(floor)));
+                assert!(openings.iter().any(|&(floor, _)| floor == 4));
+            }
+        }
+    }
+
+    #[test]
+    fn rear_doors_deliver_everyone() {
+        let config = BuildingConfig {
+            served: ServedFloors::with_doors(&[1, 2, 3], &[1, 3, 4, 5]),
+            ..BuildingConfig::new()
+        };
+        for pattern in [Pattern::Uniform, Pattern::UpPeak, Pattern::DownPeak] {
+            let profile = TrafficProfile {
+                passengers: 100,
+                pattern,
+                ..TrafficProfile::new()
+            };
+            let passengers = profile.generate(&config);
+            assert!(passengers.iter().all(|p| {
+                config.served.doors(p.origin).has(p.origin_side)
+                    && config.served.doors(p.destination).has(p.destination_side)
+            }));
+
+            let runs = run_both(
+                &config,
+                &passengers,
+                FaultPlan::new,
+                Default::default(),
+                note_stops,
+            );
+            for (stats, (_, openings)) in runs {
+                assert_eq!(stats.crash, None);
+                assert_eq!(stats.delivered, stats.passengers);
+                // Only doors there are at the floor, and each side on its own
+                // as well as both together at 1 and 3
+                assert!(openings
+                    .iter()
+                    .all(|&(floor, doors)| config.served.doors(floor).contains(doors)));
+                for doors in [Doors::FRONT, Doors::REAR, Doors::BOTH] {
+                    assert!(openings.iter().any(|&(_, opened)| opened == doors));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn physics_delivers_everyone() {
+        let low_rise = BuildingConfig {
+            physics: true,
+            ..BuildingConfig::new()
+        };
+        // Long run through an express zone, fast enough that the car has to
+        // be told to stop long before it gets to the floor
+        let express = BuildingConfig {
+            physics: true,
+            served: ServedFloors::only(&[1, 2, 5]),
+            motion: MotionProfile {
+                rated_speed: 4.0,
+                floor_heights: [4.0, 40.0, 40.0, 4.0],
+                ..MotionProfile::new()
+            },
+            ..BuildingConfig::new()
+        };
+        for config in [low_rise, express] {
+            let profile = TrafficProfile {
+                passengers: 100,
+                ..TrafficProfile::new()
+            };
+            let passengers = profile.generate(&config);
+
+            // The car is standing level with the floor whenever its doors
+            // close, and the stops are all planned in time, so none are missed
+            let runs = run_both(
+                &config,
+                &passengers,
+                FaultPlan::new,
+                0,
+                |overshoots, event, _, controller| match event {
+                    Event::Closed(floor) => {
+                        let lifty = &controller.transport().lifty;
+                        let motion = &lifty.motion;
+                        assert_eq!(lifty.floor, floor);
+                        assert_eq!(motion.speed, 0.0);
+                        assert!((motion.position - config.motion.level(floor)).abs() < EPSILON);
+                    }
+                    Event::Overshot(_) => *overshoots += 1,
+                    _ => {}
+                },
+            );
+            for (stats, overshoots) in runs {
+                assert_eq!(stats.crash, None);
+                assert_eq!(overshoots, 0);
+                assert_eq!(stats.delivered, stats.passengers);
+            }
+        }
+    }
+
+    #[test]
+    fn express_runs_at_rated_speed() {
+        let config = BuildingConfig {
+            physics: true,
+            motion: MotionProfile {
+                rated_speed: 4.0,
+                floor_heights: [4.0, 40.0, 40.0, 4.0],
+                ..MotionProfile::new()
+            },
+            ..BuildingConfig::new()
+        };
+        let mut lifty = Lifty::new(&config);
+        lifty.handle_command("MU");
+        let mut events = Vec::new();
+        let mut top_speed: f64 = 0.0;
+        let mut braking_from = 0.0;
+        while lifty.motor != Motor::Off && lifty.crashed.is_none() {
+            if let Some(event) = lifty.handle_command("T") {
+                if event == "A4" {
+                    braking_from = lifty.motion.distance_to(&config.motion, 4, true);
+                    lifty.handle_command("S");
+                }
+                events.push(event);
+            }
+            top_speed = top_speed.max(lifty.motion.speed);
+        }
+        assert_eq!(lifty.crashed, None);
+        assert_eq!(events, ["A2", "A3", "A4", "S4"]);
+        assert_eq!(top_speed, 4.0);
+        // Approached with just enough room to stop from full speed
+        let stopping = config.motion.stopping_distance(4.0, 0.0);
+        assert!(braking_from >= stopping && braking_from < stopping + 0.4);
+    }
+
+    // For `run_both`, as if the controller were slow to make up its mind,
+    // falling a tick behind on a quarter of the approaches to a middle floor.
+    // Those stops come too late for Lifty, though with a long enough
+    // `window` the controller doesn't know it when it sends them.  Counts
+    // the stops Lifty reports missed.
+    fn sluggish(
+        window: Duration,
+    ) -> impl FnMut(&mut (fastrand::Rng, usize), Event, &[&Command], &mut LiftyController<Simulation>)
+    {
+        move |(rng, overshoots), event, _, controller| {
+            controller.set_stop_window(window);
+            match event {
+                Event::Arrived(floor)
+                    if floor != LOWEST_FLOOR && floor != HIGHEST_FLOOR && rng.f64() < 0.25 =>
+                {
+                    controller.transport_mut().step();
+                }
+                Event::Overshot(_) => *overshoots += 1,
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn late_stops_are_replanned() {
+        let config = BuildingConfig::new();
+        let passengers = TrafficProfile::new().generate(&config);
+        let window = Duration::from_millis(config.tick_interval);
+        let found = (fastrand::Rng::with_seed(1), 0);
+        for (stats, (_, overshoots)) in run_both(
+            &config,
+            &passengers,
+            FaultPlan::new,
+            found,
+            sluggish(window),
+        ) {
+            assert_eq!(stats.crash, None);
+            assert!(overshoots > 0);
+            assert_eq!(stats.delivered, stats.passengers);
+        }
+    }
+
+    #[test]
+    fn stops_that_turn_out_late_are_replanned() {
+        // The controller sends every stop, and finds out it was too late
+        // when the car approaches the next floor instead
+        let window = Duration::from_secs(60);
+        for physics in [false, true] {
+            let config = BuildingConfig {
+                physics,
+                ..BuildingConfig::new()
+            };
+            let passengers = TrafficProfile::new().generate(&config);
+            let found = (fastrand::Rng::with_seed(1), 0);
+            for (stats, (_, overshoots)) in run_both(
+                &config,
+                &passengers,
+                FaultPlan::new,
+                found,
+                sluggish(window),
+            ) {
+                assert_eq!(stats.crash, None);
+                assert!(overshoots > 0);
+                assert_eq!(stats.delivered, stats.passengers);
+            }
+        }
+    }
+
+    #[test]
+    fn hardware_faults() {
+        let config = BuildingConfig::new();
+        let mut lifty = Lifty::new(&config);
+        // The next event within a floor's travel
+        let next_event =
+            |lifty: &mut Lifty| (0..config.ticks_per_floor).find_map(|_| lifty.handle_command("T"));
+
+        lifty.inject(&Fault::MotorWontStart);
+        lifty.handle_command("MU");
+        assert_eq!(lifty.motor, Motor::Off);
+        lifty.handle_command("MU");
+        assert_eq!(lifty.motor, Motor::Up);
+
+        lifty.inject(&Fault::MissApproach);
+        assert_eq!(next_event(&mut lifty), None);
+        assert_eq!(next_event(&mut lifty).as_deref(), Some("A3"));
+        lifty.handle_command("S");
+        assert_eq!(next_event(&mut lifty).as_deref(), Some("S3"));
+
+        lifty.inject(&Fault::StuckDoor);
+        lifty.handle_command("DO");
+        assert_eq!(next_event(&mut lifty), None);
+        assert_eq!(lifty.door, Door::Opening);
+        assert_eq!(lifty.crashed, None);
+
+        assert_eq!(
+            lifty.inject(&Fault::Press("P4".to_string())),
+            Some("P4".to_string())
+        );
+        lifty.inject(&Fault::Reset);
+        assert_eq!(lifty.floor, LOWEST_FLOOR);
+        assert!(!lifty.stuck_door);
+    }
+
+    #[test]
+    fn unsafe_commands_are_caught() {
+        let mut lifty = Lifty::new(&BuildingConfig::new());
+        assert_eq!(lifty.unsafe_command("MU"), None);
+        lifty.handle_command("DO");
+        assert_eq!(
+            lifty.unsafe_command("MU"),
+            Some("car moved with the doors open")
+        );
+        lifty.door = Door::Closed;
+        lifty.handle_command("MU");
+        assert_eq!(
+            lifty.unsafe_command("DO"),
+            Some("doors opened while moving")
+        );
+    }
+
+    #[test]
+    fn missed_approach_is_survived() {
+        let config = BuildingConfig::new();
+        let passengers = [Passenger {
+            arrival: 1,
+            origin: 1,
+            origin_side: Side::Front,
+            destination: 5,
+            destination_side: Side::Front,
+            weight: 75,
+        }];
+        let faults = || FaultPlan::scripted(vec![(1, Fault::MissApproach)]);
+
+        for (stats, _) in run_both(&config, &passengers, faults, (), |_, _, _, _| {}) {
+            assert_eq!((stats.faults, stats.crash, stats.delivered), (1, None, 1));
+            assert!(stats.violations.is_empty());
+        }
+    }
+
+    #[test]
+    fn spurious_presses_are_harmless() {
+        let config = BuildingConfig::new();
+        let passengers = TrafficProfile::new().generate(&config);
+        let faults = || FaultPlan::random(3, 0.01, vec![Fault::Press(String::new())]);
+
+        for (stats, _) in run_both(&config, &passengers, faults, (), |_, _, _, _| {}) {
+            assert!(stats.faults > 0);
+            assert_eq!(stats.crash, None);
+            assert!(stats.violations.is_empty());
+            assert_eq!(stats.delivered, stats.passengers);
+        }
+    }
+
+    #[test]
+    fn garbled_datagrams_are_ignored() {
+        let config = BuildingConfig::new();
+        let passengers = TrafficProfile::new().generate(&config);
+        let faults = || FaultPlan::random(3, 0.01, vec![Fault::Garble(String::new())]);
+        for (stats, _) in run_both(&config, &passengers, faults, (), |_, _, _, _| {}) {
+            assert!(stats.faults > 0);
+            assert_eq!((stats.crash, stats.resets), (None, 0));
+            assert!(stats.violations.is_empty());
+            assert_eq!(stats.delivered, stats.passengers);
+        }
+    }
+
+    #[test]
+    fn unresponsive_hardware_is_reset() {
+        let config = BuildingConfig::new();
+        let passengers = [Passenger {
+            arrival: 1,
+            origin: 3,
+            origin_side: Side::Front,
+            destination: 1,
+            destination_side: Side::Front,
+            weight: 75,
+        }];
+
+        // The motor ignores the first start, and later the doors jam on
+        // the way to the passenger.  Resetting Lifty clears both.
+        for (at, fault) in [(1, Fault::MotorWontStart), (60, Fault::StuckDoor)] {
+            let faults = || FaultPlan::scripted(vec![(at, fault.clone())]);
+            for (stats, _) in run_both(&config, &passengers, faults, (), |_, _, _, _| {}) {
+                assert_eq!((stats.resets, stats.failure), (1, None));
+                assert_eq!((stats.crash, stats.delivered), (None, 1));
+                assert!(stats.violations.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn hardware_that_stays_broken_goes_out_of_service() {
+        let config = BuildingConfig::new();
+        let passengers = [Passenger {
+            arrival: 1,
+            origin: 3,
+            origin_side: Side::Front,
+            destination: 1,
+            destination_side: Side::Front,
+            weight: 75,
+        }];
+        // The doors jam again straight after every reset
+        let faults = || FaultPlan::scripted((1..10_000).map(|t| (t, Fault::StuckDoor)).collect());
+
+        for (stats, _) in run_both(&config, &passengers, faults, (), |_, _, _, _| {}) {
+            assert_eq!((stats.resets, stats.failure), (3, Some(Failure::Doors)));
+            assert_eq!((stats.crash, stats.delivered), (None, 0));
+            assert!(stats.violations.is_empty());
+            assert!(stats.ticks < 10_000);
+        }
+    }
+
+    #[test]
+    fn hardware_that_stops_answering_goes_out_of_service() {
+        let config = BuildingConfig::new();
+        let passengers = [Passenger {
+            arrival: 1,
+            origin: 3,
+            origin_side: Side::Front,
+            destination: 1,
+            destination_side: Side::Front,
+            weight: 75,
+        }];
+        // The car is on its way up when Lifty drops off the network.  None
+        // of the controller's resets are answered, so none of them take.
+        let faults = || FaultPlan::scripted(vec![(5, Fault::Disconnect)]);
+
+        for (stats, _) in run_both(&config, &passengers, faults, (), |_, _, _, _| {}) {
+            assert_eq!((stats.resets, stats.failure), (0, Some(Failure::Motion)));
+            assert_eq!(stats.delivered, 0);
+            assert!(stats.ticks < 10_000);
+        }
+    }
+
+    #[test]
+    fn crashed_hardware_is_reset() {
+        // A stop command that arrives twice crashes Lifty
+        let config = BuildingConfig::new();
+        let passengers = TrafficProfile::new().generate(&config);
+        let faults = || FaultPlan::random(1, 0.001, vec![Fault::DuplicateCommand]);
+
+        for (stats, _) in run_both(&config, &passengers, faults, (), |_, _, _, _| {}) {
+            assert!(stats.crash.is_some());
+            assert!(stats.resets > 0);
+            assert_eq!(stats.failure, None);
+            assert!(stats.violations.is_empty());
+            assert_eq!(stats.delivered, stats.passengers);
+        }
+    }
+
+    #[test]
+    fn controller_closes_the_doors_after_the_dwell() {
+        // The main loop waits for the hardware, so nothing but the
+        // controller's own timer gets the doors shut again
+        let config = BuildingConfig::new();
+        let passengers = [Passenger {
+            arrival: 10_000,
+            origin: 3,
+            origin_side: Side::Front,
+            destination: 1,
+            destination_side: Side::Front,
+            weight: 75,
+        }];
+        let mut controller =
+            LiftyController::with_transport(Simulation::new(&config, &passengers, 20_000));
+        controller.send(Command::OpenDoor {
+            floor: 1,
+            direction: Indicator::Off,
+            doors: Doors::FRONT,
+        });
+        assert_eq!(controller.receive(), Some(Event::Closed(1)));
+        let doors = 2.0 * config.seconds(config.ticks_for_door as f64);
+        let dwell = config.door_dwell as f64 / 1000.0;
+        assert!(controller.now().as_secs_f64() >= doors + dwell);
+    }
+
+    #[test]
+    fn indicator_is_lit_where_the_car_is() {
+        // Lifty lights the indicator at whichever floor it's told to, and
+        // the people who go by it are the ones waiting where the car is.  A
+        // car call made while the doors are open changes the car's floor's.
+        let config = BuildingConfig::new();
+        let passengers = [Passenger {
+            arrival: 10_000,
+            origin: 3,
+            origin_side: Side::Front,
+            destination: 1,
+            destination_side: Side::Front,
+            weight: 75,
+        }];
+        for machine in MACHINES {
+            let mut algorithm = machine(config.settings());
+            let simulation = Simulation::new(&config, &passengers, 20_000);
+            let mut controller = LiftyController::with_transport(simulation);
+            for button in ["P1", "P3"] {
+                controller.transport_mut().press(button);
+                let event = controller.receive().unwrap();
+                assert_eq!(event, call(button).unwrap());
+                if let Some(command) = algorithm.handle_at(event, controller.now()) {
+                    controller.send(command);
+                }
+            }
+            let status = controller.status().unwrap();
+            assert_eq!(
+                (status.indicator, status.indicator_floor),
+                (Indicator::Up, 1)
+            );
+        }
+    }
+
+    // Lifty's reports of what it did just before a reset, which only turn
+    // up once the controller has sent the reset and is waiting to hear how
+    // it went
+    struct LateReports {
+        simulation: Simulation,
+        late: Vec<String>,
+    }
+
+    impl Transport for LateReports {
+        fn send(&mut self, message: &str) {
+            self.simulation.send(message);
+            // Not the reset the controller starts with
+            if message == "R" && self.simulation.tick > 0 {
+                for report in self.late.drain(..) {
+                    self.simulation.deliver(report);
+                }
+            }
+        }
+
+        fn receive(&mut self, timeout: Option<Duration>) -> Option<String> {
+            self.simulation.receive(timeout)
+        }
+
+        fn now(&self) -> Duration {
+            self.simulation.now()
+        }
+    }
+
+    #[test]
+    fn reports_from_before_a_reset_are_dropped() {
+        let config = BuildingConfig::new();
+        let passengers = [Passenger {
+            arrival: 1,
+            origin: 3,
+            origin_side: Side::Front,
+            destination: 1,
+            destination_side: Side::Front,
+            weight: 75,
+        }];
+        for machine in MACHINES {
+            let mut algorithm = machine(config.settings());
+            // The doors jam on the way to the passenger, and the reset
+            // crosses with an approach and a closing from before it, and a
+            // press of the up button at 4
+            let faults = FaultPlan::scripted(vec![(60, Fault::StuckDoor)]);
+            let late = vec!["A2".to_string(), "C3".to_string(), "U4".to_string()];
+            let simulation = Simulation::with_faults(&config, &passengers, 10_000, faults);
+            let mut controller = LiftyController::with_transport(LateReports { simulation, late });
+            controller.configure(&config);
+            let mut events = Vec::new();
+            while let Some(event) = controller.receive() {
+                events.push(event);
+                if let Some(command) = algorithm.handle_at(event, controller.now()) {
+                    controller.send(command);
+                }
+            }
+            // Straight after the reset come the calls, pressed meanwhile or
+            // pressed again, and nothing about the car
+            let reset = events.iter().position(|&event| event == Event::Reset);
+            let after_reset = &events[reset.unwrap() + 1..];
+            assert_eq!(
+                after_reset[..2],
+                [Event::Up(4, Side::Front), Event::Down(3, Side::Front)]
+            );
+            let stats = controller.transport().simulation.stats();
+            assert_eq!(stats.crash, None);
+            assert!(stats.violations.is_empty());
+            assert_eq!(stats.delivered, 1);
+        }
+    }
+
+    #[test]
+    fn status_query() {
+        let config = BuildingConfig::new();
+        let mut lifty = Lifty::new(&config);
+        lifty.handle_command("P5");
+        lifty.handle_command("RU3");
+        lifty.handle_command("IU1");
+        lifty.handle_command("DO");
+        let reply = lifty.handle_command("Q").unwrap();
+        assert_eq!(
+            reply,
+            "Q floor=1 motor=off door=opening doors=front stopping=no indicator=up@1 lit=P5,RU3 crashed=no"
+        );
+        assert_eq!(Status::parse(&reply), Ok(lifty.status()));
+        assert!(Status::parse("Q floor=6").is_err());
+
+        // A crashed Lifty still answers
+        lifty.handle_command("MU");
+        let status = Status::parse(&lifty.handle_command("Q").unwrap()).unwrap();
+        assert!(status.crashed && !status.is_reset());
+        lifty.handle_command("R");
+        assert!(lifty.status().is_reset());
+
+        // Events that come in while the controller waits for the answer
+        // aren't lost
+        let passengers = [Passenger {
+            arrival: 1,
+            origin: 3,
+            origin_side: Side::Front,
+            destination: 1,
+            destination_side: Side::Front,
+            weight: 75,
+        }];
+        let mut controller =
+            LiftyController::with_transport(Simulation::new(&config, &passengers, 1000));
+        controller.transport_mut().step();
+        let status = controller.status().unwrap();
+        assert_eq!((status.floor, status.lit), (1, vec!["D3".to_string()]));
+        assert_eq!(controller.receive(), Some(Event::Down(3, Side::Front)));
+    }
+
+    #[test]
+    fn same_passengers_for_same_seed() {
+        let config = BuildingConfig::new();
+        let profile = TrafficProfile::new();
+        assert_eq!(profile.generate(&config), profile.generate(&config));
+    }
+
+    // Run one controller for a while, then start another that takes the car
+    // over from wherever it is
+    fn check_take_over<A: Algorithm>(
+        mut first: impl Algorithm,
+        events: usize,
+        resume: impl Fn(&HardwareState) -> (Option<Command>, A),
+    ) {
+        let config = BuildingConfig::new();
+        let profile = TrafficProfile {
+            passengers: 50,
+            ..TrafficProfile::new()
+        };
+        let passengers = profile.generate(&config);
+        let mut controller =
+            LiftyController::with_transport(Simulation::new(&config, &passengers, 1_000_000));
+        for _ in 0..events {
+            let event = controller.receive().unwrap();
+            if let Some(command) = first.handle_at(event, controller.now()) {
+                controller.send(command);
+            }
+        }
+
+        let (mut controller, hardware) = LiftyController::take_over(controller.into_transport());
+        let (command, mut second) = resume(&hardware.expect("Lifty didn't say how it was"));
+        if let Some(command) = command {
+            controller.send(command);
+        }
+        while let Some(event) = controller.receive() {
+            if let Some(command) = second.handle_at(event, controller.now()) {
+                controller.send(command);
+            }
+        }
+        let stats = controller.transport().stats();
+        assert_eq!(stats.crash, None);
+        assert_eq!(stats.violations, Vec::<String>::new());
+        assert_eq!(stats.delivered, stats.passengers);
+    }
+
+    #[test]
+    fn take_over_mid_operation() {
+        // Cut over at enough different points to catch the car standing,
+        // moving and with its doors open
+        for events in [1, 25, 50, 75, 100, 150, 200, 250] {
+            check_take_over(Elevator::new(), events, |hardware| {
+                let (elevator, command) = Elevator::resume(Settings::new(), hardware).unwrap();
+                (command, elevator)
+            });
+            check_take_over(
+                ElevatorAfterEvent::Idle(IdleElevator::new()),
+                events,
+                |hardware| ElevatorAfterEvent::resume(Settings::new(), hardware).unwrap(),
+            );
+        }
+    }
+
+    #[test]
+    fn starting_afresh_loses_no_calls() {
+        // As if the building had changed since the last controller, so
+        // that the car is somewhere the new one can't carry on from
+        for events in [1, 25, 50, 100, 150, 200, 250] {
+            start_afresh_after(events);
+        }
+    }
+
+    fn start_afresh_after(events: usize) {
+        let config = BuildingConfig::new();
+        let profile = TrafficProfile {
+            passengers: 50,
+            ..TrafficProfile::new()
+        };
+        let passengers = profile.generate(&config);
+        let mut controller =
+            LiftyController::with_transport(Simulation::new(&config, &passengers, 1_000_000));
+        let mut first = Elevator::new();
+        for _ in 0..events {
+            let event = controller.receive().unwrap();
+            if let Some(command) = first.handle_at(event, controller.now()) {
+                controller.send(command);
+            }
+        }
+
+        let (mut controller, hardware) = LiftyController::take_over(controller.into_transport());
+        assert!(hardware.is_some());
+        controller.start_afresh();
+        let mut second = Elevator::new();
+        while let Some(event) = controller.receive() {
+            if let Some(command) = second.handle_at(event, controller.now()) {
+                controller.send(command);
+            }
+        }
+        let stats = controller.transport().stats();
+        assert_eq!(stats.crash, None);
+        assert_eq!(stats.violations, Vec::<String>::new());
+        assert_eq!(stats.delivered, stats.passengers);
+    }
+
+    #[test]
+    fn calls_survive_a_controller_crash() {
+        // The controller dies, and the one that replaces it can't take over
+        // without a reset, which puts every light out.  The calls come back
+        // from the last snapshot.
+        let config = BuildingConfig::new();
+        let profile = TrafficProfile {
+            passengers: 50,
+            ..TrafficProfile::new()
+        };
+        let passengers = profile.generate(&config);
+        let mut controller =
+            LiftyController::with_transport(Simulation::new(&config, &passengers, 1_000_000));
+        let mut elevator = Elevator::new();
+        for _ in 0..150 {
+            let event = controller.receive().unwrap();
+            if let Some(command) = elevator.handle_at(event, controller.now()) {
+                controller.send(command);
+            }
+        }
+        let snapshot = Snapshot::parse(&elevator.snapshot().text()).unwrap();
+        assert!(!snapshot.calls.is_empty());
+
+        let mut controller = LiftyController::with_transport(controller.into_transport());
+        controller.press(&snapshot.calls);
+        let lit = controller.status().unwrap().lit;
+        for &call in &snapshot.calls {
+            assert!(lit.contains(&call_button(call).unwrap()));
+        }
+        let mut elevator = ElevatorAfterEvent::Idle(IdleElevator::new()).restore(&snapshot);
+        while let Some(event) = controller.receive() {
+            if let Some(command) = elevator.handle_at(event, controller.now()) {
+                controller.send(command);
+            }
+        }
+        let stats = controller.transport().stats();
+        assert_eq!(stats.crash, None);
+        assert_eq!(stats.delivered, stats.passengers);
+    }
+}
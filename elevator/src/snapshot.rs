@@ -0,0 +1,222 @@
+// Snapshots of the elevator algorithm, saved on every transition so that a
+// controller that dies doesn't take everybody's calls with it.  A snapshot
+// is a small `key = value` file, like the configuration files:
+//
+//     # elevator.snapshot
+//     state       = moving-up
+//     floor       = 3
+//     load        = 40
+//     parking     = none
+//     demand      = 4 0 2 1 0
+//     stop_load   = 40
+//     empty_stops = 1
+//     calls       = P5 RP2 U1 D4
+//
+// Calls are named after the buttons that make them, which says which doors
+// each is waiting at as well.  On restart the controller presses the
+// buttons of any that the hardware has lost, and the rest is restored by
+// the elevator algorithm (see `ElevatorData::restore`).  Where the car is
+// and which doors are open come from the hardware, not from here.
+
+use crate::config;
+use crate::controller::{call, call_button};
+use crate::logic::{Event, HIGHEST_FLOOR, LOWEST_FLOOR, NUM_FLOORS};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub state: String, // e.g. "open-up", or "fault-doors" when out of service
+    pub floor: u8,
+    pub load: u8,
+    pub parking: Option<u8>,
+    pub demand: [u32; NUM_FLOORS as usize],
+    pub stop_load: Option<u8>,
+    pub empty_stops: u8,
+    pub calls: Vec<Event>,
+}
+
+// A floor, or "none"
+fn optional(key: &str, value: &str) -> Result<Option<u8>, String> {
+    match value {
+        "none" => Ok(None),
+        value => config::value(key, value).map(Some),
+    }
+}
+
+fn show(value: Option<u8>) -> String {
+    value.map_or("none".to_string(), |value| value.to_string())
+}
+
+impl Snapshot {
+    pub fn parse(text: &str) -> Result<Snapshot, String> {
+        let (mut state, mut floor, mut load, mut calls) = (None, None, None, None);
+        let (mut parking, mut demand, mut stop_load, mut empty_stops) = (None, None, None, None);
+        for (key, value) in config::parse(text)? {
+            match key.as_str() {
+                "state" => state = Some(value),
+                "floor" => floor = Some(config::value::<u8>(&key, &value)?),
+                "load" => load = Some(config::value(&key, &value)?),
+                "parking" => parking = Some(optional(&key, &value)?),
+                "demand" => {
+                    let counts = value
+                        .split_whitespace()
+                        .map(|count| config::value(&key, count))
+                        .collect::<Result<Vec<u32>, _>>()?;
+                    let bad = || format!("bad value for demand ({value})");
+                    demand = Some(counts.try_into().map_err(|_| bad())?);
+                }
+                "stop_load" => stop_load = Some(optional(&key, &value)?),
+                "empty_stops" => empty_stops = Some(config::value(&key, &value)?),
+                "calls" => {
+                    let bad = |button| format!("bad call ({button})");
+                    calls = Some(
+                        value
+                            .split_whitespace()
+                            .map(|button| call(button).ok_or_else(|| bad(button)))
+                            .collect::<Result<_, _>>()?,
+                    )
+                }
+                _ => return Err(format!("unknown setting {key}")),
+            }
+        }
+        let missing = |key| format!("missing setting {key}");
+        let floor = floor.ok_or_else(|| missing("floor"))?;
+        if !(LOWEST_FLOOR..=HIGHEST_FLOOR).contains(&floor) {
+            return Err(format!("bad value for floor ({floor})"));
+        }
+        let parking = parking.ok_or_else(|| missing("parking"))?;
+        if parking.is_some_and(|parking| !(LOWEST_FLOOR..=HIGHEST_FLOOR).contains(&parking)) {
+            return Err(format!("bad value for parking ({})", show(parking)));
+        }
+        Ok(Snapshot {
+            state: state.ok_or_else(|| missing("state"))?,
+            floor,
+            load: load.ok_or_else(|| missing("load"))?,
+            parking,
+            demand: demand.ok_or_else(|| missing("demand"))?,
+            stop_load: stop_load.ok_or_else(|| missing("stop_load"))?,
+            empty_stops: empty_stops.ok_or_else(|| missing("empty_stops"))?,
+            calls: calls.ok_or_else(|| missing("calls"))?,
+        })
+    }
+
+    pub fn text(&self) -> String {
+        let calls: Vec<String> = self.calls.iter().filter_map(|&c| call_button(c)).collect();
+        let demand: Vec<String> = self.demand.iter().map(u32::to_string).collect();
+        format!(
+            "state = {}\nfloor = {}\nload = {}\nparking = {}\ndemand = {}\n\
+             stop_load = {}\nempty_stops = {}\ncalls = {}\n",
+            self.state,
+            self.floor,
+            self.load,
+            show(self.parking),
+            demand.join(" "),
+            show(self.stop_load),
+            self.empty_stops,
+            calls.join(" ")
+        )
+    }
+}
+
+// Where snapshots are kept.  Each one replaces the last in a single rename,
+// so a crash part way through saving leaves the previous snapshot intact.
+pub struct SnapshotStore {
+    path: String,
+}
+
+impl SnapshotStore {
+    pub fn new(path: &str) -> Self {
+        SnapshotStore {
+            path: path.to_string(),
+        }
+    }
+
+    pub fn save(&self, snapshot: &Snapshot) -> Result<(), String> {
+        let error = |e: std::io::Error| format!("{}: {e}", self.path);
+        let temporary = format!("{}.tmp", self.path);
+        let mut file = fs::File::create(&temporary).map_err(error)?;
+        file.write_all(snapshot.text().as_bytes()).map_err(error)?;
+        file.sync_all().map_err(error)?;
+        fs::rename(&temporary, &self.path).map_err(error)?;
+        // The rename itself is only safe on disk once the directory is
+        let directory = match Path::new(&self.path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::File::open(directory)
+            .and_then(|directory| directory.sync_all())
+            .map_err(error)
+    }
+
+    // The last snapshot saved, or None if there isn't one
+    pub fn load(&self) -> Result<Option<Snapshot>, String> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => Snapshot::parse(&text)
+                .map(Some)
+                .map_err(|e| format!("{}: {e}", self.path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {e}", self.path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logic::Side;
+
+    #[test]
+    fn snapshot_text() {
+        let snapshot = Snapshot {
+            state: "moving-up".to_string(),
+            floor: 3,
+            load: 40,
+            parking: None,
+            demand: [4, 0, 2, 1, 0],
+            stop_load: Some(40),
+            empty_stops: 1,
+            calls: vec![
+                Event::Panel(5, Side::Front),
+                Event::Panel(2, Side::Rear),
+                Event::Up(1, Side::Front),
+                Event::Down(4, Side::Front),
+            ],
+        };
+        let text = snapshot.text();
+        assert_eq!(
+            text,
+            "state = moving-up\nfloor = 3\nload = 40\nparking = none\ndemand = 4 0 2 1 0\n\
+             stop_load = 40\nempty_stops = 1\ncalls = P5 RP2 U1 D4\n"
+        );
+        assert_eq!(Snapshot::parse(&text), Ok(snapshot));
+        let rest = "load = 0\nstop_load = none\nempty_stops = 0\n";
+        let parse = |text: &str| Snapshot::parse(&format!("state = idle\nfloor = 1\n{rest}{text}"));
+        assert!(parse("parking = 2\ndemand = 0 0 0 0 0\ncalls =\n").is_ok());
+        assert!(parse("parking = none\ndemand = 0 0 0 0 0\ncalls = P9\n").is_err());
+        assert!(parse("parking = 9\ndemand = 0 0 0 0 0\ncalls =\n").is_err());
+        assert!(parse("parking = none\ndemand = 0 0 0\ncalls =\n").is_err());
+        assert!(parse("parking = none\ncalls =\n").is_err());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("elevator-{}.snapshot", std::process::id()));
+        let store = SnapshotStore::new(path.to_str().unwrap());
+        assert_eq!(store.load(), Ok(None));
+        let snapshot = Snapshot {
+            state: "idle".to_string(),
+            floor: 1,
+            load: 0,
+            parking: None,
+            demand: [0, 1, 0, 0, 0],
+            stop_load: None,
+            empty_stops: 0,
+            calls: vec![Event::Up(2, Side::Front)],
+        };
+        store.save(&snapshot).unwrap();
+        assert_eq!(store.load(), Ok(Some(snapshot)));
+        fs::remove_file(path).unwrap();
+    }
+}
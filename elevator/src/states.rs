@@ -1,111 +1,50 @@
-use crate::logic::{Command, Event, Indicator, HIGHEST_FLOOR, LOWEST_FLOOR, NUM_FLOORS};
-
-#[derive(Debug, Clone)]
-pub struct ElevatorData {
-    pub floor: u8,
-    destinations: [bool; NUM_FLOORS as usize],
-    up_requests: [bool; NUM_FLOORS as usize],
-    down_requests: [bool; NUM_FLOORS as usize],
-}
-
-impl ElevatorData {
-    pub fn new() -> Self {
-        ElevatorData {
-            floor: LOWEST_FLOOR,
-            destinations: [false; NUM_FLOORS as usize],
-            up_requests: [false; NUM_FLOORS as usize],
-            down_requests: [false; NUM_FLOORS as usize],
-        }
-    }
-
-    pub fn set_destination(&mut self, floor: usize, value: bool) {
-        self.destinations[floor] = value;
-    }
-
-    pub fn get_destination(&self, floor: usize) -> bool {
-        self.destinations[floor]
-    }
-
-    pub fn set_up_request(&mut self, floor: usize, value: bool) {
-        self.up_requests[floor] = value;
-    }
-
-    pub fn get_up_request(&self, floor: usize) -> bool {
-        self.up_requests[floor]
-    }
-
-    pub fn set_down_request(&mut self, floor: usize, value: bool) {
-        self.down_requests[floor] = value;
-    }
-
-    pub fn get_down_request(&self, floor: usize) -> bool {
-        self.down_requests[floor]
-    }
-
-    pub fn have_requests(&self) -> bool {
-        self.destinations.iter().any(|&x| x)
-            || self.up_requests.iter().any(|&x| x)
-            || self.down_requests.iter().any(|&x| x)
-    }
-
-    pub fn highest_request(&self) -> u8 {
-        for floor in (LOWEST_FLOOR..=HIGHEST_FLOOR).rev() {
-            if self.get_destination(floor as usize)
-                || self.get_up_request(floor as usize)
-                || self.get_down_request(floor as usize)
-            {
-                return floor;
-            }
-        }
-        self.floor
-    }
-
-    pub fn lowest_request(&self) -> u8 {
-        for floor in LOWEST_FLOOR..=HIGHEST_FLOOR {
-            if self.get_destination(floor as usize)
-                || self.get_up_request(floor as usize)
-                || self.get_down_request(floor as usize)
-            {
-                return floor;
-            }
-        }
-        self.floor
-    }
-}
+use crate::logic::{
+    Algorithm, Command, ElevatorData, ElevatorState, Event, Failure, HardwareState, IdlePolicy,
+    Settings, Stop,
+};
+use crate::rules::State;
+use crate::snapshot::Snapshot;
 
 // State structs
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IdleElevator {
     data: ElevatorData,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MovingUpElevator {
     data: ElevatorData,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MovingDownElevator {
     data: ElevatorData,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OpenElevator {
     data: ElevatorData,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OpenUpElevator {
     data: ElevatorData,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OpenDownElevator {
     data: ElevatorData,
 }
 
+// Out of service until the hardware is seen to
+#[derive(Debug, Clone)]
+pub struct FaultElevator {
+    data: ElevatorData,
+    pub failure: Failure,
+}
+
 // Enums for state transitions (since Rust can't return different types from same method)
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ElevatorAfterEvent {
     Idle(IdleElevator),
     MovingUp(MovingUpElevator),
@@ -113,20 +52,205 @@ pub enum ElevatorAfterEvent {
     Open(OpenElevator),
     OpenUp(OpenUpElevator),
     OpenDown(OpenDownElevator),
+    Fault(FaultElevator),
 }
 
 impl ElevatorAfterEvent {
-    pub fn handle(self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
-        match self {
+    // Pick up where another controller left off, without resetting the
+    // hardware.  The waiting calls are handled as if they had just been
+    // made, which may get the car going.
+    pub fn resume(
+        settings: Settings,
+        hardware: &HardwareState,
+    ) -> Result<(Option<Command>, ElevatorAfterEvent), String> {
+        let (data, state) = ElevatorData::resume(settings, hardware)?;
+        let mut commands = Vec::new();
+        let mut elevator = match state {
+            ElevatorState::Idle => {
+                let (command, elevator) = IdleElevator::enter(data);
+                commands.extend(command);
+                elevator
+            }
+            state => ElevatorAfterEvent::in_state(data, state),
+        };
+        for &call in &hardware.calls {
+            let (command, next) = elevator.handle(call);
+            commands.extend(command);
+            elevator = next;
+        }
+        Ok((Command::sequence(commands), elevator))
+    }
+
+    // The typestate for `state`, holding `data` as it is
+    pub(crate) fn in_state(data: ElevatorData, state: ElevatorState) -> Self {
+        match state {
+            ElevatorState::Idle => ElevatorAfterEvent::Idle(IdleElevator { data }),
+            ElevatorState::Open => ElevatorAfterEvent::Open(OpenElevator { data }),
+            ElevatorState::OpenUp => ElevatorAfterEvent::OpenUp(OpenUpElevator { data }),
+            ElevatorState::OpenDown => ElevatorAfterEvent::OpenDown(OpenDownElevator { data }),
+            ElevatorState::MovingUp => ElevatorAfterEvent::MovingUp(MovingUpElevator { data }),
+            ElevatorState::MovingDown => {
+                ElevatorAfterEvent::MovingDown(MovingDownElevator { data })
+            }
+            ElevatorState::Fault(failure) => {
+                ElevatorAfterEvent::Fault(FaultElevator { data, failure })
+            }
+        }
+    }
+
+    pub fn handle(mut self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
+        // An overshoot is only ever reported straight after the stop
+        let stop = self.data_mut().stop.take();
+        if !self.data_mut().settings.served.valid_call(event) {
+            return (Some(Command::RejectEvent(event)), self);
+        }
+        self.data_mut().record_call(event);
+        let state = self.state();
+        match event {
+            Event::Load(load) => return (self.data_mut().weigh(load, &state), self),
+            Event::PanelCancel(floor, side) => {
+                let command = self.data_mut().cancel_car_call(floor, side, &state);
+                return (Some(command), self);
+            }
+            Event::Overshot(floor) => return self.handle_overshot(floor, stop),
+            // Cards are checked by the security layer (security.rs), if any
+            Event::Credential(_) => return (None, self),
+            Event::Fault(failure) => {
+                return FaultElevator::enter(std::mem::take(self.data_mut()), failure)
+            }
+            // Start again from where the reset left the car.  The controller
+            // presses the buttons of the calls that were waiting again.
+            Event::Reset => {
+                let mut data = std::mem::take(self.data_mut());
+                data.reset();
+                return IdleElevator::enter(data);
+            }
+            _ => {}
+        }
+
+        // Nuisance calls are weeded out as the doors close, before deciding
+        // where to go next.
+        let cancelled = match (&self, event) {
+            (
+                ElevatorAfterEvent::Open(_)
+                | ElevatorAfterEvent::OpenUp(_)
+                | ElevatorAfterEvent::OpenDown(_),
+                Event::Closed(_),
+            ) => self.data_mut().cancel_nuisance_calls(),
+            _ => Vec::new(),
+        };
+        let stopping = match (&self, event) {
+            (ElevatorAfterEvent::MovingUp(elevator), Event::Arrived(floor)) => {
+                Some(elevator.data.stop_at(floor, true))
+            }
+            (ElevatorAfterEvent::MovingDown(elevator), Event::Arrived(floor)) => {
+                Some(elevator.data.stop_at(floor, false))
+            }
+            _ => None,
+        };
+
+        let (command, mut elevator) = match self {
             ElevatorAfterEvent::Idle(elevator) => elevator.handle(event),
             ElevatorAfterEvent::MovingUp(elevator) => elevator.handle(event),
             ElevatorAfterEvent::MovingDown(elevator) => elevator.handle(event),
             ElevatorAfterEvent::Open(elevator) => elevator.handle(event),
             ElevatorAfterEvent::OpenUp(elevator) => elevator.handle(event),
             ElevatorAfterEvent::OpenDown(elevator) => elevator.handle(event),
+            ElevatorAfterEvent::Fault(elevator) => elevator.handle(event),
+        };
+        let data = elevator.data_mut();
+        data.ordered(&command, stopping);
+        data.forget_answered_sides();
+        (Command::cancelling(cancelled, command), elevator)
+    }
+
+    // The stop was ordered too late and the car is carrying on past the
+    // floor.  Its calls are still waiting, so put them back.  If there's
+    // nothing further on, the car stops at the next floor and comes back.
+    fn handle_overshot(
+        self,
+        floor: u8,
+        stop: Option<Stop>,
+    ) -> (Option<Command>, ElevatorAfterEvent) {
+        let (mut data, stop) = match (self, stop) {
+            (
+                ElevatorAfterEvent::Open(OpenElevator { data })
+                | ElevatorAfterEvent::OpenUp(OpenUpElevator { data })
+                | ElevatorAfterEvent::OpenDown(OpenDownElevator { data }),
+                Some(stop),
+            ) if stop.floor == floor => (data, stop),
+            _ => panic!("Unexpected event: {:?}", Event::Overshot(floor)),
+        };
+        let going_up = stop.going_up;
+        data.overshot(stop);
+        match going_up {
+            true => (
+                None,
+                ElevatorAfterEvent::MovingUp(MovingUpElevator { data }),
+            ),
+            false => (
+                None,
+                ElevatorAfterEvent::MovingDown(MovingDownElevator { data }),
+            ),
+        }
+    }
+
+    // What's worth saving in case the controller dies
+    pub fn snapshot(&self) -> Snapshot {
+        self.data().snapshot(&self.state())
+    }
+
+    // Pick up what the last snapshot knew that the hardware doesn't
+    pub fn restore(mut self, snapshot: &Snapshot) -> ElevatorAfterEvent {
+        let state = self.state();
+        self.data_mut().restore(&state, snapshot);
+        self.check_invariants();
+        self
+    }
+
+    // The state logic.rs would be in
+    fn state(&self) -> ElevatorState {
+        match self {
+            ElevatorAfterEvent::Idle(_) => ElevatorState::Idle,
+            ElevatorAfterEvent::MovingUp(_) => ElevatorState::MovingUp,
+            ElevatorAfterEvent::MovingDown(_) => ElevatorState::MovingDown,
+            ElevatorAfterEvent::Open(_) => ElevatorState::Open,
+            ElevatorAfterEvent::OpenUp(_) => ElevatorState::OpenUp,
+            ElevatorAfterEvent::OpenDown(_) => ElevatorState::OpenDown,
+            ElevatorAfterEvent::Fault(elevator) => ElevatorState::Fault(elevator.failure),
+        }
+    }
+
+    fn data_mut(&mut self) -> &mut ElevatorData {
+        match self {
+            ElevatorAfterEvent::Idle(elevator) => &mut elevator.data,
+            ElevatorAfterEvent::MovingUp(elevator) => &mut elevator.data,
+            ElevatorAfterEvent::MovingDown(elevator) => &mut elevator.data,
+            ElevatorAfterEvent::Open(elevator) => &mut elevator.data,
+            ElevatorAfterEvent::OpenUp(elevator) => &mut elevator.data,
+            ElevatorAfterEvent::OpenDown(elevator) => &mut elevator.data,
+            ElevatorAfterEvent::Fault(elevator) => &mut elevator.data,
+        }
+    }
+
+    fn data(&self) -> &ElevatorData {
+        match self {
+            ElevatorAfterEvent::Idle(elevator) => &elevator.data,
+            ElevatorAfterEvent::MovingUp(elevator) => &elevator.data,
+            ElevatorAfterEvent::MovingDown(elevator) => &elevator.data,
+            ElevatorAfterEvent::Open(elevator) => &elevator.data,
+            ElevatorAfterEvent::OpenUp(elevator) => &elevator.data,
+            ElevatorAfterEvent::OpenDown(elevator) => &elevator.data,
+            ElevatorAfterEvent::Fault(elevator) => &elevator.data,
         }
     }
 
+    // The checks logic.rs makes after every event.  The types can't rule out
+    // the ones about the calls, so they're checked here (see proofs.rs).
+    pub fn check_invariants(&self) {
+        self.data().check_invariants(&self.state());
+    }
+
     pub fn produce_future_event(&self) -> Option<Event> {
         match self {
             ElevatorAfterEvent::Idle(elevator) => elevator.produce_future_event(),
@@ -135,10 +259,41 @@ impl ElevatorAfterEvent {
             ElevatorAfterEvent::Open(elevator) => elevator.produce_future_event(),
             ElevatorAfterEvent::OpenUp(elevator) => elevator.produce_future_event(),
             ElevatorAfterEvent::OpenDown(elevator) => elevator.produce_future_event(),
+            ElevatorAfterEvent::Fault(_) => None,
         }
     }
 }
 
+impl Default for IdleElevator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Algorithm for ElevatorAfterEvent {
+    fn handle(&mut self, event: Event) -> Option<Command> {
+        // Each state consumes itself on a transition, so swap in a placeholder
+        // while the real state is being handled.
+        let elevator = std::mem::replace(self, ElevatorAfterEvent::Idle(IdleElevator::new()));
+        let (command, elevator) = elevator.handle(event);
+        *self = elevator;
+        command
+    }
+}
+
+// The typestate for the state the table in rules.rs moves the car to.  The
+// calls opening the doors answers are taken off as it enters an open state.
+fn moved_to(data: ElevatorData, state: State) -> ElevatorAfterEvent {
+    match state {
+        State::Idle => ElevatorAfterEvent::Idle(IdleElevator { data }),
+        State::MovingUp => ElevatorAfterEvent::MovingUp(MovingUpElevator { data }),
+        State::MovingDown => ElevatorAfterEvent::MovingDown(MovingDownElevator { data }),
+        State::Open => ElevatorAfterEvent::Open(OpenElevator::new(data)),
+        State::OpenUp => ElevatorAfterEvent::OpenUp(OpenUpElevator::new(data)),
+        State::OpenDown => ElevatorAfterEvent::OpenDown(OpenDownElevator::new(data)),
+    }
+}
+
 impl IdleElevator {
     pub fn new() -> Self {
         IdleElevator {
@@ -146,325 +301,93 @@ impl IdleElevator {
         }
     }
 
-    pub fn handle(mut self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
-        match event {
-            Event::Panel(floor) => {
-                if floor != self.data.floor {
-                    self.data.set_destination(floor as usize, true);
-                    if floor > self.data.floor {
-                        (
-                            Some(Command::MoveUp),
-                            ElevatorAfterEvent::MovingUp(MovingUpElevator { data: self.data }),
-                        )
-                    } else {
-                        (
-                            Some(Command::MoveDown),
-                            ElevatorAfterEvent::MovingDown(MovingDownElevator { data: self.data }),
-                        )
-                    }
-                } else {
-                    (
-                        Some(Command::OpenDoor {
-                            floor,
-                            direction: Indicator::Off,
-                        }),
-                        ElevatorAfterEvent::Open(OpenElevator { data: self.data }),
-                    )
-                }
-            }
-            Event::Up(floor) => {
-                if floor != self.data.floor {
-                    self.data.set_up_request(floor as usize, true);
-                    if floor > self.data.floor {
-                        (
-                            Some(Command::MoveUp),
-                            ElevatorAfterEvent::MovingUp(MovingUpElevator { data: self.data }),
-                        )
-                    } else {
-                        (
-                            Some(Command::MoveDown),
-                            ElevatorAfterEvent::MovingDown(MovingDownElevator { data: self.data }),
-                        )
-                    }
-                } else {
-                    (
-                        Some(Command::OpenDoor {
-                            floor,
-                            direction: Indicator::Up,
-                        }),
-                        ElevatorAfterEvent::OpenUp(OpenUpElevator { data: self.data }),
-                    )
-                }
-            }
-            Event::Down(floor) => {
-                if floor != self.data.floor {
-                    self.data.set_down_request(floor as usize, true);
-                    if floor > self.data.floor {
-                        (
-                            Some(Command::MoveUp),
-                            ElevatorAfterEvent::MovingUp(MovingUpElevator { data: self.data }),
-                        )
-                    } else {
-                        (
-                            Some(Command::MoveDown),
-                            ElevatorAfterEvent::MovingDown(MovingDownElevator { data: self.data }),
-                        )
-                    }
-                } else {
-                    (
-                        Some(Command::OpenDoor {
-                            floor,
-                            direction: Indicator::Down,
-                        }),
-                        ElevatorAfterEvent::OpenDown(OpenDownElevator { data: self.data }),
-                    )
-                }
-            }
-            _ => panic!("Unexpected event: {:?}", event),
+    pub fn with_settings(settings: Settings) -> Self {
+        IdleElevator {
+            data: ElevatorData::with_settings(settings),
         }
     }
 
+    // Going idle starts the parking timer, unless the car stays where it is
+    fn enter(data: ElevatorData) -> (Option<Command>, ElevatorAfterEvent) {
+        (
+            data.idle_timer(),
+            ElevatorAfterEvent::Idle(IdleElevator { data }),
+        )
+    }
+
+    pub fn handle(mut self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
+        let (command, next) = self.data.follow_rules(State::Idle, event);
+        let elevator = match next {
+            State::Idle => ElevatorAfterEvent::Idle(self),
+            next => moved_to(self.data, next),
+        };
+        (command, elevator)
+    }
+
     pub fn produce_future_event(&self) -> Option<Event> {
-        None
+        match self.data.settings.idle_policy {
+            IdlePolicy::StayPut => None,
+            _ => Some(Event::IdleTimeout),
+        }
     }
 }
 
 impl MovingUpElevator {
+    // A moving car carries on or stops, and only stops by opening the doors
     pub fn handle(mut self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
-        match event {
-            Event::Panel(floor) => {
-                self.data.set_destination(floor as usize, true);
-                (None, ElevatorAfterEvent::MovingUp(self))
-            }
-            Event::Up(floor) => {
-                self.data.set_up_request(floor as usize, true);
-                (None, ElevatorAfterEvent::MovingUp(self))
-            }
-            Event::Down(floor) => {
-                self.data.set_down_request(floor as usize, true);
-                (None, ElevatorAfterEvent::MovingUp(self))
-            }
-            Event::Arrived(floor) => {
-                self.data.floor = floor;
-
-                if self.data.get_destination(floor as usize) {
-                    if self.data.highest_request() > floor
-                        || self.data.get_up_request(floor as usize)
-                    {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Up,
-                            }),
-                            ElevatorAfterEvent::OpenUp(OpenUpElevator { data: self.data }),
-                        )
-                    } else if self.data.have_requests() && self.data.highest_request() <= floor {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Down,
-                            }),
-                            ElevatorAfterEvent::OpenDown(OpenDownElevator { data: self.data }),
-                        )
-                    } else {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Off,
-                            }),
-                            ElevatorAfterEvent::Open(OpenElevator { data: self.data }),
-                        )
-                    }
-                } else if self.data.get_up_request(floor as usize)
-                    && self.data.highest_request() == floor
-                {
-                    (
-                        Some(Command::StopAndOpen {
-                            floor,
-                            direction: Indicator::Down,
-                        }),
-                        ElevatorAfterEvent::OpenDown(OpenDownElevator { data: self.data }),
-                    )
-                } else {
-                    (None, ElevatorAfterEvent::MovingUp(self))
-                }
-            }
-            _ => panic!("Unexpected event: {:?}", event),
-        }
+        let (command, next) = self.data.follow_rules(State::MovingUp, event);
+        let elevator = match next {
+            State::MovingUp => ElevatorAfterEvent::MovingUp(self),
+            State::Open | State::OpenUp | State::OpenDown => moved_to(self.data, next),
+            _ => unreachable!("A moving car can't go straight to {next:?}"),
+        };
+        (command, elevator)
     }
 
     pub fn produce_future_event(&self) -> Option<Event> {
-        Some(Event::Arrived(self.data.floor + 1))
+        self.data
+            .settings
+            .served
+            .above(self.data.floor)
+            .map(Event::Arrived)
     }
 }
 
 impl MovingDownElevator {
     pub fn handle(mut self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
-        // Clear destination when entering moving down state
-        self.data.set_destination(self.data.floor as usize, false);
-
-        match event {
-            Event::Panel(floor) => {
-                self.data.set_destination(floor as usize, true);
-                (None, ElevatorAfterEvent::MovingDown(self))
-            }
-            Event::Up(floor) => {
-                self.data.set_up_request(floor as usize, true);
-                (None, ElevatorAfterEvent::MovingDown(self))
-            }
-            Event::Down(floor) => {
-                self.data.set_down_request(floor as usize, true);
-                (None, ElevatorAfterEvent::MovingDown(self))
-            }
-            Event::Arrived(floor) => {
-                self.data.floor = floor;
-
-                if self.data.get_destination(floor as usize) {
-                    if self.data.lowest_request() < floor
-                        || self.data.get_down_request(floor as usize)
-                    {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Down,
-                            }),
-                            ElevatorAfterEvent::OpenDown(OpenDownElevator { data: self.data }),
-                        )
-                    } else if self.data.have_requests() && self.data.lowest_request() >= floor {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Up,
-                            }),
-                            ElevatorAfterEvent::OpenUp(OpenUpElevator { data: self.data }),
-                        )
-                    } else {
-                        (
-                            Some(Command::StopAndOpen {
-                                floor,
-                                direction: Indicator::Off,
-                            }),
-                            ElevatorAfterEvent::Open(OpenElevator { data: self.data }),
-                        )
-                    }
-                } else if self.data.get_down_request(floor as usize) {
-                    (
-                        Some(Command::StopAndOpen {
-                            floor,
-                            direction: Indicator::Down,
-                        }),
-                        ElevatorAfterEvent::OpenDown(OpenDownElevator { data: self.data }),
-                    )
-                } else if self.data.get_up_request(floor as usize)
-                    && self.data.lowest_request() == floor
-                {
-                    (
-                        Some(Command::StopAndOpen {
-                            floor,
-                            direction: Indicator::Up,
-                        }),
-                        ElevatorAfterEvent::OpenUp(OpenUpElevator { data: self.data }),
-                    )
-                } else {
-                    (None, ElevatorAfterEvent::MovingDown(self))
-                }
-            }
-            _ => panic!("Unexpected event: {:?}", event),
-        }
+        let (command, next) = self.data.follow_rules(State::MovingDown, event);
+        let elevator = match next {
+            State::MovingDown => ElevatorAfterEvent::MovingDown(self),
+            State::Open | State::OpenUp | State::OpenDown => moved_to(self.data, next),
+            _ => unreachable!("A moving car can't go straight to {next:?}"),
+        };
+        (command, elevator)
     }
 
     pub fn produce_future_event(&self) -> Option<Event> {
-        Some(Event::Arrived(self.data.floor - 1))
+        self.data
+            .settings
+            .served
+            .below(self.data.floor)
+            .map(Event::Arrived)
     }
 }
 
 // Open State Implementation
 impl OpenElevator {
-    pub fn handle(mut self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
-        // Clear destination when entering open state
-        self.data.set_destination(self.data.floor as usize, false);
+    // Clear destination when entering open state
+    fn new(mut data: ElevatorData) -> Self {
+        data.set_destination(data.floor as usize, false);
+        OpenElevator { data }
+    }
 
-        match event {
-            Event::Panel(floor) => {
-                if floor != self.data.floor {
-                    self.data.set_destination(floor as usize, true);
-                    if floor > self.data.floor {
-                        (
-                            Some(Command::ChangeIndicator {
-                                floor,
-                                direction: Indicator::Up,
-                            }),
-                            ElevatorAfterEvent::OpenUp(OpenUpElevator { data: self.data }),
-                        )
-                    } else {
-                        (
-                            Some(Command::ChangeIndicator {
-                                floor,
-                                direction: Indicator::Down,
-                            }),
-                            ElevatorAfterEvent::OpenDown(OpenDownElevator { data: self.data }),
-                        )
-                    }
-                } else {
-                    (
-                        Some(Command::RejectEvent(event)),
-                        ElevatorAfterEvent::Open(self),
-                    )
-                }
-            }
-            Event::Up(floor) => {
-                if floor != self.data.floor {
-                    self.data.set_up_request(floor as usize, true);
-                    (None, ElevatorAfterEvent::Open(self))
-                } else {
-                    (
-                        Some(Command::ChangeIndicator {
-                            floor,
-                            direction: Indicator::Up,
-                        }),
-                        ElevatorAfterEvent::OpenUp(OpenUpElevator { data: self.data }),
-                    )
-                }
-            }
-            Event::Down(floor) => {
-                if floor != self.data.floor {
-                    self.data.set_down_request(floor as usize, true);
-                    (None, ElevatorAfterEvent::Open(self))
-                } else {
-                    (
-                        Some(Command::ChangeIndicator {
-                            floor,
-                            direction: Indicator::Down,
-                        }),
-                        ElevatorAfterEvent::OpenDown(OpenDownElevator { data: self.data }),
-                    )
-                }
-            }
-            Event::Closed(_) => {
-                if !self.data.have_requests() {
-                    (
-                        None,
-                        ElevatorAfterEvent::Idle(IdleElevator { data: self.data }),
-                    )
-                } else {
-                    let next = self.data.highest_request();
-                    if next > self.data.floor {
-                        (
-                            Some(Command::MoveUp),
-                            ElevatorAfterEvent::MovingUp(MovingUpElevator { data: self.data }),
-                        )
-                    } else if next < self.data.floor {
-                        (
-                            Some(Command::MoveDown),
-                            ElevatorAfterEvent::MovingDown(MovingDownElevator { data: self.data }),
-                        )
-                    } else {
-                        panic!("I'm supposed to move!")
-                    }
-                }
-            }
-            _ => panic!("Unexpected event: {:?}", event),
-        }
+    pub fn handle(mut self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
+        let (command, next) = self.data.follow_rules(State::Open, event);
+        let elevator = match next {
+            State::Open => ElevatorAfterEvent::Open(self),
+            next => moved_to(self.data, next),
+        };
+        (command, elevator)
     }
 
     pub fn produce_future_event(&self) -> Option<Event> {
@@ -473,66 +396,20 @@ impl OpenElevator {
 }
 
 impl OpenUpElevator {
-    pub fn handle(mut self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
-        // Clear destination and up request when entering open up state
-        self.data.set_destination(self.data.floor as usize, false);
-        self.data.set_up_request(self.data.floor as usize, false);
+    // Clear destination and up request when entering open up state
+    fn new(mut data: ElevatorData) -> Self {
+        data.set_destination(data.floor as usize, false);
+        data.set_up_request(data.floor as usize, false);
+        OpenUpElevator { data }
+    }
 
-        match event {
-            Event::Panel(floor) => {
-                if floor != self.data.floor {
-                    self.data.set_destination(floor as usize, true);
-                    (None, ElevatorAfterEvent::OpenUp(self))
-                } else {
-                    (
-                        Some(Command::RejectEvent(event)),
-                        ElevatorAfterEvent::OpenUp(self),
-                    )
-                }
-            }
-            Event::Up(floor) => {
-                if floor != self.data.floor {
-                    self.data.set_up_request(floor as usize, true);
-                    (None, ElevatorAfterEvent::OpenUp(self))
-                } else {
-                    (
-                        Some(Command::RejectEvent(event)),
-                        ElevatorAfterEvent::OpenUp(self),
-                    )
-                }
-            }
-            Event::Down(floor) => {
-                self.data.set_down_request(floor as usize, true);
-                (None, ElevatorAfterEvent::OpenUp(self))
-            }
-            Event::Closed(floor) => {
-                if !self.data.have_requests() {
-                    (
-                        None,
-                        ElevatorAfterEvent::Idle(IdleElevator { data: self.data }),
-                    )
-                } else if self.data.highest_request() > floor {
-                    (
-                        Some(Command::MoveUp),
-                        ElevatorAfterEvent::MovingUp(MovingUpElevator { data: self.data }),
-                    )
-                } else if self.data.highest_request() < floor {
-                    (
-                        Some(Command::MoveDown),
-                        ElevatorAfterEvent::MovingDown(MovingDownElevator { data: self.data }),
-                    )
-                } else {
-                    (
-                        Some(Command::OpenDoor {
-                            floor,
-                            direction: Indicator::Up,
-                        }),
-                        ElevatorAfterEvent::OpenDown(OpenDownElevator { data: self.data }),
-                    )
-                }
-            }
-            _ => panic!("Unexpected event: {:?}", event),
-        }
+    pub fn handle(mut self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
+        let (command, next) = self.data.follow_rules(State::OpenUp, event);
+        let elevator = match next {
+            State::OpenUp => ElevatorAfterEvent::OpenUp(self),
+            next => moved_to(self.data, next),
+        };
+        (command, elevator)
     }
 
     pub fn produce_future_event(&self) -> Option<Event> {
@@ -541,69 +418,47 @@ impl OpenUpElevator {
 }
 
 impl OpenDownElevator {
-    pub fn handle(mut self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
-        // Clear destination and down request when entering open down state
-        self.data.set_destination(self.data.floor as usize, false);
-        self.data.set_down_request(self.data.floor as usize, false);
+    // Clear destination and down request when entering open down state
+    fn new(mut data: ElevatorData) -> Self {
+        data.set_destination(data.floor as usize, false);
+        data.set_down_request(data.floor as usize, false);
+        OpenDownElevator { data }
+    }
 
-        match event {
-            Event::Panel(floor) => {
-                if floor != self.data.floor {
-                    self.data.set_destination(floor as usize, true);
-                    (None, ElevatorAfterEvent::OpenDown(self))
-                } else {
-                    (
-                        Some(Command::RejectEvent(event)),
-                        ElevatorAfterEvent::OpenDown(self),
-                    )
-                }
-            }
-            Event::Up(floor) => {
-                self.data.set_up_request(floor as usize, true);
-                (None, ElevatorAfterEvent::OpenDown(self))
-            }
-            Event::Down(floor) => {
-                if floor != self.data.floor {
-                    self.data.set_down_request(floor as usize, true);
-                    (None, ElevatorAfterEvent::OpenDown(self))
-                } else {
-                    (
-                        Some(Command::RejectEvent(event)),
-                        ElevatorAfterEvent::OpenDown(self),
-                    )
-                }
-            }
-            Event::Closed(floor) => {
-                if !self.data.have_requests() {
-                    (
-                        None,
-                        ElevatorAfterEvent::Idle(IdleElevator { data: self.data }),
-                    )
-                } else if self.data.lowest_request() < floor {
-                    (
-                        Some(Command::MoveDown),
-                        ElevatorAfterEvent::MovingDown(MovingDownElevator { data: self.data }),
-                    )
-                } else if self.data.lowest_request() > floor {
-                    (
-                        Some(Command::MoveUp),
-                        ElevatorAfterEvent::MovingUp(MovingUpElevator { data: self.data }),
-                    )
-                } else {
-                    (
-                        Some(Command::ChangeIndicator {
-                            floor: self.data.floor,
-                            direction: Indicator::Up,
-                        }),
-                        ElevatorAfterEvent::OpenUp(OpenUpElevator { data: self.data }),
-                    )
-                }
-            }
-            _ => panic!("Unexpected event: {:?}", event),
-        }
+    pub fn handle(mut self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
+        let (command, next) = self.data.follow_rules(State::OpenDown, event);
+        let elevator = match next {
+            State::OpenDown => ElevatorAfterEvent::OpenDown(self),
+            next => moved_to(self.data, next),
+        };
+        (command, elevator)
     }
 
     pub fn produce_future_event(&self) -> Option<Event> {
         Some(Event::Closed(self.data.floor))
     }
 }
+
+impl FaultElevator {
+    // The hardware has stopped answering, so there's no telling what the car
+    // or the doors will do next.  Take the car out of service: drop every
+    // call, turning its light off so that nobody waits for a car that isn't
+    // coming, and stay put until somebody sees to it.
+    fn enter(mut data: ElevatorData, failure: Failure) -> (Option<Command>, ElevatorAfterEvent) {
+        (
+            data.drop_calls(),
+            ElevatorAfterEvent::Fault(FaultElevator { data, failure }),
+        )
+    }
+
+    // Calls are refused and the car doesn't move
+    pub fn handle(self, event: Event) -> (Option<Command>, ElevatorAfterEvent) {
+        match event {
+            Event::Panel(..) | Event::Up(..) | Event::Down(..) => (
+                Some(Command::RejectEvent(event)),
+                ElevatorAfterEvent::Fault(self),
+            ),
+            _ => (None, ElevatorAfterEvent::Fault(self)),
+        }
+    }
+}
@@ -0,0 +1,373 @@
+// The elevator algorithm as a TLA+ specification, to check with TLC (or any
+// other TLA+ model checker) offline.  The transitions come from the table in
+// rules.rs, row for row, so the spec says what the controller does rather
+// than what somebody remembered it doing.  Along with it go the invariants
+// from `check_invariants` in logic.rs and the property that every call is
+// answered in the end:
+//
+//     cargo run --bin spec -- tla > Elevator.tla
+//     cargo run --bin spec -- cfg > Elevator.cfg
+//     java -cp tla2tools.jar tlc2.TLC Elevator
+//
+// Some things are left out to keep the model small: which side of the car
+// calls are made from, the anti-nuisance check, cancelled car calls,
+// overshoots and faults.  The load is only whether the car is overloaded,
+// or too full to pick anyone up.  The parking floor of a car that parks at
+// the busiest floor can be any floor, since the model doesn't count calls.
+
+use crate::logic::{IdlePolicy, Settings, HIGHEST_FLOOR, LOWEST_FLOOR};
+use crate::rules::{Action, Atom, Dir, Rule, To, FIRST, RULES};
+use std::fmt::Write as _;
+
+// Named state predicates, for the config and for counterexamples
+const INVARIANTS: [&str; 7] = [
+    "TypeOK",
+    "IdleInvariant",
+    "MovingUpInvariant",
+    "MovingDownInvariant",
+    "OpenInvariant",
+    "OpenUpInvariant",
+    "OpenDownInvariant",
+];
+
+// An atom of a guard, for the car `c` given the event `e`.  Events are
+// records with a kind and a floor: the call's floor, the floor arrived at or
+// closed at, or for IdleTimeout, the parking floor.
+fn atom(atom: Atom) -> &'static str {
+    match atom {
+        Atom::Here => "e.floor = c.floor",
+        Atom::Above => "e.floor > c.floor",
+        Atom::SideOpen => "TRUE", // Sides aren't modelled
+        Atom::Overloaded => "c.overloaded",
+        Atom::Requests => "RequestFloors(c) # {}",
+        Atom::HighestAbove => "HighestRequest(c) > c.floor",
+        Atom::HighestBelow => "HighestRequest(c) < c.floor",
+        Atom::LowestAbove => "LowestRequest(c) > c.floor",
+        Atom::LowestBelow => "LowestRequest(c) < c.floor",
+        Atom::ParkAbove => "e.floor > c.floor",
+        Atom::ParkBelow => "e.floor < c.floor",
+        Atom::StopGoingUp => {
+            "c.floor \\in c.dest \\/ (c.floor \\in c.up /\\ ~c.bypassing) \\/ HighestRequest(c) <= c.floor"
+        }
+        Atom::StopGoingDown => {
+            "c.floor \\in c.dest \\/ (c.floor \\in c.down /\\ ~c.bypassing) \\/ LowestRequest(c) >= c.floor"
+        }
+        Atom::UpwardCalls => "c.floor \\in c.up \\/ \\E g \\in Calls(c) : g > c.floor",
+        Atom::DownwardCalls => "c.floor \\in c.down \\/ \\E g \\in Calls(c) : g < c.floor",
+        Atom::OtherCalls => "c.up \\cup c.down \\cup (c.dest \\ {c.floor}) # {}",
+    }
+}
+
+// What an action does to the car `c` on the event `e`
+fn action(action: Action) -> &'static str {
+    match action {
+        Action::Register => {
+            "CASE e.kind = \"Panel\" -> [c EXCEPT !.dest = @ \\cup {e.floor}]\n\
+             \x20     [] e.kind = \"Up\" -> [c EXCEPT !.up = @ \\cup {e.floor}]\n\
+             \x20     [] e.kind = \"Down\" -> [c EXCEPT !.down = @ \\cup {e.floor}]"
+        }
+        Action::Arrive => {
+            "[c EXCEPT !.floor = e.floor, !.parking = IF Calls(c) = {} THEN @ ELSE 0]"
+        }
+        Action::Stop => "[c EXCEPT !.dest = @ \\ {c.floor}, !.parking = 0]",
+        Action::Park => "[c EXCEPT !.parking = e.floor]",
+    }
+}
+
+fn direction(dir: Dir) -> &'static str {
+    match dir {
+        Dir::Up => "\"Up\"",
+        Dir::Down => "\"Down\"",
+        Dir::Off => "\"Off\"",
+        Dir::Call => "CallDirection(e)",
+    }
+}
+
+// `actions` applied in turn to the car `car`
+fn apply(actions: &[Action], car: &str) -> String {
+    actions.iter().fold(car.to_string(), |car, action| {
+        format!("{action:?}({car}, e)")
+    })
+}
+
+// The car after a row's actions, in its next state
+fn after(rule: &Rule) -> String {
+    let car = apply(rule.actions, "a");
+    let next = match rule.next {
+        To::Stay => return format!("Answer({car})"),
+        To::Unreachable(reason) => return format!("Assert(FALSE, \"{reason}\")"),
+        To::Idle => "\"Idle\"".to_string(),
+        To::MovingUp => "\"MovingUp\"".to_string(),
+        To::MovingDown => "\"MovingDown\"".to_string(),
+        To::Open(Dir::Up) => "\"OpenUp\"".to_string(),
+        To::Open(Dir::Down) => "\"OpenDown\"".to_string(),
+        To::Open(Dir::Off) => "\"Open\"".to_string(),
+        To::Open(dir) => format!("OpenState({})", direction(dir)),
+    };
+    format!("Answer(Go({car}, {next}))")
+}
+
+fn condition(rule: &Rule) -> String {
+    let kinds: Vec<String> = rule
+        .events
+        .iter()
+        .map(|kind| format!("\"{kind:?}\""))
+        .collect();
+    let mut terms = vec![
+        format!("a.state = \"{:?}\"", rule.state),
+        match kinds.len() {
+            1 => format!("e.kind = {}", kinds[0]),
+            _ => format!("e.kind \\in {{{}}}", kinds.join(", ")),
+        },
+    ];
+    for &(atom, wanted) in rule.guard {
+        let not = if wanted { "" } else { "~" };
+        terms.push(format!("{not}{atom:?}(a, e)"));
+    }
+    terms.join(" /\\ ")
+}
+
+fn set(floors: impl Iterator<Item = u8>) -> String {
+    let floors: Vec<String> = floors.map(|floor| floor.to_string()).collect();
+    format!("{{{}}}", floors.join(", "))
+}
+
+// The spec, as module Elevator, for a building with `settings`
+pub fn module(settings: &Settings) -> String {
+    let served = &settings.served;
+    let parking = match settings.idle_policy {
+        IdlePolicy::StayPut => "{}".to_string(),
+        IdlePolicy::ReturnToLobby => set([served.lowest()].into_iter()),
+        IdlePolicy::ParkAtBusiestFloor => "Floors".to_string(),
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{}",
+        "-".repeat(30) + " MODULE Elevator " + &"-".repeat(30)
+    );
+    out.push_str(
+        "\\* The elevator algorithm, from the table of transitions in rules.rs.\n\
+         \\* Made by `cargo run --bin spec -- tla`; change the rules, not this.\n\
+         EXTENDS Integers, TLC\n\n",
+    );
+    let _ = writeln!(
+        out,
+        "Floors == {}  \\* The floors served",
+        set(served.floors())
+    );
+    let _ = writeln!(
+        out,
+        "ParkingFloors == {parking}  \\* Where the idle timer may send the car"
+    );
+    let _ = writeln!(out, "Lowest == {LOWEST_FLOOR}");
+    let _ = writeln!(out, "Highest == {HIGHEST_FLOOR}");
+    out.push_str(concat!(
+        "States == {\"Idle\", \"MovingUp\", \"MovingDown\", \"Open\", \"OpenUp\", \"OpenDown\"}\n",
+        "\n",
+        "\\* dest, up and down are the floors with car calls and hall calls; parking\n",
+        "\\* is the floor the car is parking at, or 0\n",
+        "VARIABLE car\n",
+        "\n",
+        "Max(S) == CHOOSE x \\in S : \\A y \\in S : y <= x\n",
+        "Min(S) == CHOOSE x \\in S : \\A y \\in S : x <= y\n",
+        "\n",
+        "Calls(c) == c.dest \\cup c.up \\cup c.down\n",
+        "RequestFloors(c) == IF c.parking = 0 THEN Calls(c) ELSE Calls(c) \\cup {c.parking}\n",
+        "HighestRequest(c) == IF RequestFloors(c) = {} THEN Lowest ELSE Max(RequestFloors(c))\n",
+        "LowestRequest(c) == IF RequestFloors(c) = {} THEN Lowest ELSE Min(RequestFloors(c))\n",
+        "\n",
+        "CallDirection(e) == CASE e.kind = \"Up\" -> \"Up\" [] e.kind = \"Down\" -> \"Down\" [] OTHER -> \"Off\"\n",
+        "OpenState(d) == CASE d = \"Up\" -> \"OpenUp\" [] d = \"Down\" -> \"OpenDown\" [] OTHER -> \"Open\"\n",
+        "Go(c, state) == [c EXCEPT !.state = state]\n",
+        "\n",
+        "\\* Opening the doors answers the car call at the floor, and the hall call\n",
+        "\\* in the direction shown\n",
+        "Answer(c) ==\n",
+        "    CASE c.state = \"Open\" -> [c EXCEPT !.dest = @ \\ {c.floor}]\n",
+        "      [] c.state = \"OpenUp\" -> [c EXCEPT !.dest = @ \\ {c.floor}, !.up = @ \\ {c.floor}]\n",
+        "      [] c.state = \"OpenDown\" -> [c EXCEPT !.dest = @ \\ {c.floor}, !.down = @ \\ {c.floor}]\n",
+        "      [] OTHER -> c\n",
+        "\n",
+        "\\* Facts that guards are made of (see Atom in rules.rs)\n",
+    ));
+    let atoms: Vec<Atom> = (RULES.iter())
+        .flat_map(|rule| rule.guard.iter().map(|&(atom, _)| atom))
+        .fold(Vec::new(), |mut atoms, atom| {
+            if !atoms.contains(&atom) {
+                atoms.push(atom);
+            }
+            atoms
+        });
+    for atom_ in atoms {
+        let _ = writeln!(out, "{atom_:?}(c, e) == {}", atom(atom_));
+    }
+    out.push_str("\n\\* What the rows do to the car (see Action in rules.rs)\n");
+    for action_ in [Action::Register, Action::Arrive, Action::Stop, Action::Park] {
+        let _ = writeln!(out, "{action_:?}(c, e) ==\n    {}", action(action_));
+    }
+
+    out.push_str("\n\\* Actions taken before any guard is looked at\nFirst(c, e) ==\n    CASE ");
+    let mut pairs = Vec::new();
+    for &(state, kind, _) in FIRST {
+        if !pairs.contains(&(state, kind)) {
+            pairs.push((state, kind));
+        }
+    }
+    for (n, &(state, kind)) in pairs.iter().enumerate() {
+        if n > 0 {
+            out.push_str("      [] ");
+        }
+        let actions: Vec<Action> = (FIRST.iter())
+            .filter(|&&(s, k, _)| (s, k) == (state, kind))
+            .map(|&(_, _, action)| action)
+            .collect();
+        let _ = writeln!(
+            out,
+            "c.state = \"{state:?}\" /\\ e.kind = \"{kind:?}\" -> {}",
+            apply(&actions, "c")
+        );
+    }
+    out.push_str("      [] OTHER -> c\n");
+
+    out.push_str(
+        "\n\\* The car after an event, one arm per row of the table.  Exactly one\n\
+         \\* arm applies, or the table wouldn't have compiled.\n\
+         After(c, e) ==\n    LET a == First(c, e) IN\n    CASE ",
+    );
+    for (n, rule) in RULES.iter().enumerate() {
+        if n > 0 {
+            out.push_str("      [] ");
+        }
+        let _ = writeln!(out, "{}\n            -> {}", condition(rule), after(rule));
+    }
+
+    out.push_str(concat!(
+        "\n",
+        "Init ==\n",
+        "    car = [state |-> \"Idle\", floor |-> Lowest, dest |-> {}, up |-> {}, down |-> {},\n",
+        "           parking |-> 0, overloaded |-> FALSE, bypassing |-> FALSE]\n",
+        "\n",
+        "CallEvents ==\n",
+        "    {[kind |-> \"Panel\", floor |-> f] : f \\in Floors}\n",
+        "    \\cup {[kind |-> \"Up\", floor |-> f] : f \\in {g \\in Floors : g < Max(Floors)}}\n",
+        "    \\cup {[kind |-> \"Down\", floor |-> f] : f \\in {g \\in Floors : g > Min(Floors)}}\n",
+        "\n",
+        "\\* What the hardware (or the idle timer) reports next, as in\n",
+        "\\* produce_future_event\n",
+        "HardwareEvents(c) ==\n",
+        "    CASE c.state = \"MovingUp\" ->\n",
+        "            {[kind |-> \"Arrived\", floor |-> Min({g \\in Floors : g > c.floor})]}\n",
+        "      [] c.state = \"MovingDown\" ->\n",
+        "            {[kind |-> \"Arrived\", floor |-> Max({g \\in Floors : g < c.floor})]}\n",
+        "      [] c.state \\in {\"Open\", \"OpenUp\", \"OpenDown\"} ->\n",
+        "            {[kind |-> \"Closed\", floor |-> c.floor]}\n",
+        "      [] OTHER -> {[kind |-> \"IdleTimeout\", floor |-> t] : t \\in ParkingFloors}\n",
+        "\n",
+        "Call == \\E e \\in CallEvents : car' = After(car, e)\n",
+        "Hardware == \\E e \\in HardwareEvents(car) : car' = After(car, e)\n",
+        "\n",
+        "\\* Passengers get in and out.  An overloaded car is too full to pick\n",
+        "\\* anyone up, too.\n",
+        "Load == \\E o, b \\in BOOLEAN : (o => b) /\\ car' = [car EXCEPT !.overloaded = o, !.bypassing = b]\n",
+        "\n",
+        "Next == Call \\/ Hardware \\/ Load\n",
+        "\n",
+        "\\* The car and the doors always get there in the end\n",
+        "Spec == Init /\\ [][Next]_car /\\ WF_car(Hardware)\n",
+        "\n",
+        "TypeOK ==\n",
+        "    car \\in [state : States, floor : Lowest..Highest, dest : SUBSET Floors,\n",
+        "             up : SUBSET Floors, down : SUBSET Floors, parking : Floors \\cup {0},\n",
+        "             overloaded : BOOLEAN, bypassing : BOOLEAN]\n",
+        "\n",
+        "\\* From check_invariants in logic.rs\n",
+        "IdleInvariant == car.state = \"Idle\" => RequestFloors(car) = {}\n",
+        "MovingUpInvariant ==\n",
+        "    car.state = \"MovingUp\" =>\n",
+        "        /\\ car.floor < Highest\n",
+        "        /\\ RequestFloors(car) # {}\n",
+        "        /\\ HighestRequest(car) > car.floor\n",
+        "MovingDownInvariant ==\n",
+        "    car.state = \"MovingDown\" =>\n",
+        "        /\\ car.floor > Lowest\n",
+        "        /\\ RequestFloors(car) # {}\n",
+        "        /\\ LowestRequest(car) < car.floor\n",
+        "OpenInvariant ==\n",
+        "    car.state = \"Open\" =>\n",
+        "        /\\ car.parking = 0\n",
+        "        /\\ car.floor \\notin car.up \\cup car.down\n",
+        "        /\\ car.dest = {}\n",
+        "OpenUpInvariant ==\n",
+        "    car.state = \"OpenUp\" =>\n",
+        "        /\\ car.floor < Highest\n",
+        "        /\\ car.parking = 0\n",
+        "        /\\ car.floor \\notin car.up \\cup car.dest\n",
+        "OpenDownInvariant ==\n",
+        "    car.state = \"OpenDown\" =>\n",
+        "        /\\ car.floor > Lowest\n",
+        "        /\\ car.parking = 0\n",
+        "        /\\ car.floor \\notin car.down \\cup car.dest\n",
+        "\n",
+        "\\* Every call is answered in the end, unless the car stays too full to\n",
+        "\\* pick anyone up for good\n",
+        "Answered ==\n",
+        "    \\A f \\in Floors :\n",
+        "        /\\ f \\in car.dest ~> f \\notin car.dest\n",
+        "        /\\ f \\in car.up ~> f \\notin car.up\n",
+        "        /\\ f \\in car.down ~> f \\notin car.down\n",
+        "CallsAnswered == <>[](~car.bypassing) => Answered\n",
+    ));
+    out.push_str(&"=".repeat(77));
+    out.push('\n');
+    out
+}
+
+// A TLC config for the spec
+pub fn config() -> String {
+    format!(
+        "SPECIFICATION Spec\nINVARIANTS {}\nPROPERTY CallsAnswered\n",
+        INVARIANTS.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logic::ServedFloors;
+
+    #[test]
+    fn every_row_is_in_the_spec() {
+        let spec = module(&Settings::new());
+        assert!(spec.starts_with("------------------------------ MODULE Elevator"));
+        assert!(spec.ends_with("====\n"));
+        // One arm of After per row
+        let after = &spec[spec.find("After(c, e) ==").unwrap()..spec.find("Init ==").unwrap()];
+        assert_eq!(after.matches(" -> ").count(), RULES.len());
+        assert!(after.contains(
+            "a.state = \"Idle\" /\\ e.kind \\in {\"Panel\", \"Up\", \"Down\"} /\\ ~Here(a, e) /\\ Above(a, e)\n            -> Answer(Go(Register(a, e), \"MovingUp\"))"
+        ));
+        assert!(after.contains("-> Assert(FALSE, \"I'm supposed to move!\")"));
+        assert!(spec.contains("StopGoingUp(c, e) == "));
+        for invariant in INVARIANTS {
+            assert!(spec.contains(&format!("\n{invariant} ==")), "{invariant}");
+        }
+        assert!(spec.contains("\nCallsAnswered == "));
+        assert!(config().contains("INVARIANTS TypeOK IdleInvariant"));
+    }
+
+    #[test]
+    fn building_settings() {
+        let spec = module(&Settings::new());
+        assert!(spec.contains("Floors == {1, 2, 3, 4, 5}"));
+        assert!(spec.contains("ParkingFloors == {}"));
+        let spec = module(&Settings {
+            served: ServedFloors::only(&[1, 2, 4, 5]),
+            idle_policy: IdlePolicy::ReturnToLobby,
+            ..Settings::new()
+        });
+        assert!(spec.contains("Floors == {1, 2, 4, 5}"));
+        assert!(spec.contains("ParkingFloors == {1}"));
+    }
+}
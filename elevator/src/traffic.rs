@@ -0,0 +1,118 @@
+use crate::building::BuildingConfig;
+use crate::config;
+use crate::logic::Side;
+
+// A person who shows up at `origin` at time `arrival` (in ticks) and wants
+// to ride to `destination`, using the door on the given side at each end.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Passenger {
+    pub arrival: u64,
+    pub origin: u8,
+    pub origin_side: Side,
+    pub destination: u8,
+    pub destination_side: Side,
+    pub weight: u32, // kilograms
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Uniform,  // Trips between random floors
+    UpPeak,   // Everyone leaves from the lobby (morning rush)
+    DownPeak, // Everyone heads to the lobby (evening rush)
+}
+
+// Recipe for a reproducible stream of passengers
+#[derive(Debug, Clone)]
+pub struct TrafficProfile {
+    pub seed: u64,
+    pub passengers: usize,
+    pub duration: u64, // seconds over which passengers arrive
+    pub timeout: u64,  // seconds before giving up on a run
+    pub pattern: Pattern,
+}
+
+impl TrafficProfile {
+    pub fn new() -> Self {
+        TrafficProfile {
+            seed: 1,
+            passengers: 50,
+            duration: 600,
+            timeout: 3600,
+            pattern: Pattern::Uniform,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut profile = TrafficProfile::new();
+        for (key, value) in config::read(path)? {
+            match key.as_str() {
+                "seed" => profile.seed = config::value(&key, &value)?,
+                "passengers" => profile.passengers = config::value(&key, &value)?,
+                "duration" => profile.duration = config::value(&key, &value)?,
+                "timeout" => profile.timeout = config::value(&key, &value)?,
+                "pattern" => {
+                    profile.pattern = match value.as_str() {
+                        "uniform" => Pattern::Uniform,
+                        "up-peak" => Pattern::UpPeak,
+                        "down-peak" => Pattern::DownPeak,
+                        _ => return Err(format!("{path}: unknown pattern {value}")),
+                    }
+                }
+                _ => return Err(format!("{path}: unknown setting {key}")),
+            }
+        }
+        Ok(profile)
+    }
+
+    // Produce the passengers, sorted by arrival time.  The same profile
+    // always produces the same passengers.
+    pub fn generate(&self, building: &BuildingConfig) -> Vec<Passenger> {
+        let mut rng = fastrand::Rng::with_seed(self.seed);
+        let floors: Vec<u8> = building.served.floors().collect();
+        let lobby = floors[0];
+        let last_tick = self.duration * 1000 / building.tick_interval;
+        // Where a landing has both doors, people use either
+        let side = |rng: &mut fastrand::Rng, floor| {
+            let doors = building.served.doors(floor);
+            if doors.front && (!doors.rear || rng.bool()) {
+                Side::Front
+            } else {
+                Side::Rear
+            }
+        };
+        let mut passengers: Vec<Passenger> = (0..self.passengers)
+            .map(|_| {
+                let (origin, destination) = match self.pattern {
+                    Pattern::Uniform => {
+                        let origin = rng.usize(..floors.len());
+                        let mut destination = rng.usize(..floors.len() - 1);
+                        if destination >= origin {
+                            destination += 1;
+                        }
+                        (floors[origin], floors[destination])
+                    }
+                    Pattern::UpPeak => (lobby, floors[rng.usize(1..floors.len())]),
+                    Pattern::DownPeak => (floors[rng.usize(1..floors.len())], lobby),
+                };
+                let arrival = rng.u64(1..=last_tick.max(1));
+                let weight = rng.u32(50..=100);
+                Passenger {
+                    arrival,
+                    origin,
+                    origin_side: side(&mut rng, origin),
+                    destination,
+                    destination_side: side(&mut rng, destination),
+                    weight,
+                }
+            })
+            .collect();
+        passengers.sort_by_key(|p| p.arrival);
+        passengers
+    }
+}
+
+impl Default for TrafficProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
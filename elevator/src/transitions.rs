@@ -0,0 +1,324 @@
+// A log of the state machine's transitions, for people and for ops tools.
+// Every event handled makes one record: the state before and after, the
+// event, the command sent (if any), the floor and the waiting calls.  The
+// records go to any number of sinks, each in its own format:
+//
+//     # logging.conf
+//     level = commands              # changes, commands or all
+//     sink = text -                 # readable, to standard output
+//     sink = json transitions.jsonl # one JSON object per line
+//     sink = csv transitions.csv    # with a header row
+//
+// Calls are kept as bitmaps, with bit 0 for the lowest floor.
+
+use crate::config;
+use crate::logic::{Command, Event, LOWEST_FLOOR, NUM_FLOORS};
+use crate::snapshot::Snapshot;
+use std::fs::File;
+use std::io::Write;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub at: Duration, // The controller's clock
+    pub from: String,
+    pub event: Event,
+    pub command: Option<String>, // As printed with {:?}
+    pub to: String,
+    pub floor: u8,
+    pub destinations: u32,
+    pub up: u32,
+    pub down: u32,
+}
+
+impl Transition {
+    pub fn new(
+        at: Duration,
+        before: &Snapshot,
+        event: Event,
+        command: Option<&Command>,
+        after: &Snapshot,
+    ) -> Self {
+        let (mut destinations, mut up, mut down) = (0, 0, 0);
+        for &call in &after.calls {
+            let (bitmap, floor) = match call {
+                Event::Panel(floor, _) => (&mut destinations, floor),
+                Event::Up(floor, _) => (&mut up, floor),
+                Event::Down(floor, _) => (&mut down, floor),
+                _ => continue,
+            };
+            *bitmap |= 1 << (floor - LOWEST_FLOOR);
+        }
+        Transition {
+            at,
+            from: before.state.clone(),
+            event,
+            command: command.map(|command| format!("{command:?}")),
+            to: after.state.clone(),
+            floor: after.floor,
+            destinations,
+            up,
+            down,
+        }
+    }
+}
+
+// How much gets logged
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Changes,  // Only events that change the state
+    Commands, // Those, and events that send a command
+    All,      // Every event
+}
+
+impl Level {
+    fn logs(self, transition: &Transition) -> bool {
+        match self {
+            Level::Changes => transition.from != transition.to,
+            Level::Commands => transition.from != transition.to || transition.command.is_some(),
+            Level::All => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+impl Format {
+    // The record as a line of output, without the newline
+    pub fn line(self, t: &Transition) -> String {
+        let command = t.command.as_deref().unwrap_or("");
+        match self {
+            Format::Text => {
+                let calls = |kind: char, bitmap: u32| -> String {
+                    let floors = (0..NUM_FLOORS).map(|n| match bitmap & (1 << n) {
+                        0 => '-',
+                        _ => char::from(b'0' + LOWEST_FLOOR + n),
+                    });
+                    std::iter::once(kind).chain([':']).chain(floors).collect()
+                };
+                let line = format!(
+                    "{:>8.3}s {} --{:?}--> {} floor {} {} {} {} {command}",
+                    t.at.as_secs_f64(),
+                    t.from,
+                    t.event,
+                    t.to,
+                    t.floor,
+                    calls('P', t.destinations),
+                    calls('U', t.up),
+                    calls('D', t.down),
+                );
+                line.trim_end().to_string()
+            }
+            Format::Json => {
+                let command = match &t.command {
+                    Some(command) => json_string(command),
+                    None => "null".to_string(),
+                };
+                format!(
+                    "{{\"at_ms\":{},\"from\":{},\"event\":{},\"command\":{command},\"to\":{},\"floor\":{},\"destinations\":{},\"up\":{},\"down\":{}}}",
+                    t.at.as_millis(),
+                    json_string(&t.from),
+                    json_string(&format!("{:?}", t.event)),
+                    json_string(&t.to),
+                    t.floor,
+                    t.destinations,
+                    t.up,
+                    t.down
+                )
+            }
+            Format::Csv => format!(
+                "{},{},{},{},{},{},{},{},{}",
+                t.at.as_millis(),
+                csv_field(&t.from),
+                csv_field(&format!("{:?}", t.event)),
+                csv_field(command),
+                csv_field(&t.to),
+                t.floor,
+                t.destinations,
+                t.up,
+                t.down
+            ),
+        }
+    }
+
+    fn header(self) -> Option<&'static str> {
+        match self {
+            Format::Csv => Some("at_ms,from,event,command,to,floor,destinations,up,down"),
+            _ => None,
+        }
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+pub struct TransitionLog {
+    level: Level,
+    sinks: Vec<(Format, Box<dyn Write>)>,
+}
+
+impl TransitionLog {
+    // Nothing is logged until a sink is added
+    pub fn new(level: Level) -> Self {
+        TransitionLog {
+            level,
+            sinks: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut log = TransitionLog::new(Level::All);
+        for (key, value) in config::read(path)? {
+            let bad = || format!("{path}: bad value for {key} ({value})");
+            match key.as_str() {
+                "level" => {
+                    log.level = match value.as_str() {
+                        "changes" => Level::Changes,
+                        "commands" => Level::Commands,
+                        "all" => Level::All,
+                        _ => return Err(bad()),
+                    }
+                }
+                "sink" => {
+                    let (format, output) = value.split_once(' ').ok_or_else(bad)?;
+                    let format = match format {
+                        "text" => Format::Text,
+                        "json" => Format::Json,
+                        "csv" => Format::Csv,
+                        _ => return Err(bad()),
+                    };
+                    let output: Box<dyn Write> = match output.trim() {
+                        "-" => Box::new(std::io::stdout()),
+                        file => Box::new(File::create(file).map_err(|e| format!("{file}: {e}"))?),
+                    };
+                    log.add_sink(format, output);
+                }
+                _ => return Err(format!("{path}: unknown setting {key}")),
+            }
+        }
+        Ok(log)
+    }
+
+    pub fn add_sink(&mut self, format: Format, mut output: Box<dyn Write>) {
+        if let Some(header) = format.header() {
+            let _ = writeln!(output, "{header}");
+        }
+        self.sinks.push((format, output));
+    }
+
+    pub fn log(&mut self, transition: &Transition) {
+        if !self.level.logs(transition) {
+            return;
+        }
+        for (format, output) in self.sinks.iter_mut() {
+            // Losing the log mustn't stop the car
+            if let Err(e) = writeln!(output, "{}", format.line(transition)) {
+                eprintln!("transition log: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logic::{Elevator, Side};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn moving_up() -> Transition {
+        let mut elevator = Elevator::new();
+        let before = elevator.snapshot();
+        let event = Event::Panel(3, Side::Front);
+        let command = elevator.handle(event);
+        Transition::new(
+            Duration::from_millis(1520),
+            &before,
+            event,
+            command.as_ref(),
+            &elevator.snapshot(),
+        )
+    }
+
+    #[test]
+    fn formats() {
+        let transition = moving_up();
+        assert_eq!(transition.destinations, 0b100);
+        assert_eq!(
+            Format::Text.line(&transition),
+            "   1.520s idle --Panel(3, Front)--> moving-up floor 1 P:--3-- U:----- D:----- MoveUp"
+        );
+        assert_eq!(
+            Format::Json.line(&transition),
+            r#"{"at_ms":1520,"from":"idle","event":"Panel(3, Front)","command":"MoveUp","to":"moving-up","floor":1,"destinations":4,"up":0,"down":0}"#
+        );
+        assert_eq!(
+            Format::Csv.line(&transition),
+            r#"1520,idle,"Panel(3, Front)",MoveUp,moving-up,1,4,0,0"#
+        );
+        assert_eq!(json_string("a \"b\"\n"), r#""a \"b\"\u000a""#);
+    }
+
+    // Output that can be looked at after it's been handed over
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn levels() {
+        let transition = moving_up();
+        let same = Transition {
+            from: "idle".to_string(),
+            to: "idle".to_string(),
+            ..transition.clone()
+        };
+        let quiet = Transition {
+            command: None,
+            ..same.clone()
+        };
+        let output = Shared::default();
+        let mut log = TransitionLog::new(Level::Commands);
+        log.add_sink(Format::Csv, Box::new(output.clone()));
+        for t in [&transition, &same, &quiet] {
+            log.log(t);
+        }
+        let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(text.lines().count(), 3); // The header and two records
+
+        assert!(Level::Changes.logs(&transition) && !Level::Changes.logs(&same));
+        assert!(Level::All.logs(&quiet));
+    }
+}
@@ -0,0 +1,358 @@
+// A dashboard for watching the controller in a terminal, for demos and
+// debugging.  It draws the shaft with the car in it, the lit buttons, the
+// state the algorithm is in and the last few transitions:
+//
+//     state moving-up   floor 2   load 40
+//
+//       5 |      | P5
+//       4 |      | D4
+//       3 |      |
+//       2 | [||]^|
+//       1 |      | U1
+//
+// Keys press Lifty's buttons, just as somebody in the building would, so the
+// calls come back to the controller the usual way:
+//
+//     1-5   car panel          Shift-1-5 (!@#$%)  rear car panel
+//     qwert up on floor 1-5    QWERT              rear up
+//     asdfg down on floor 1-5  ASDFG              rear down
+//     Ctrl-C quits
+//
+// There's no curses here, just ANSI escapes, with `stty` to put the terminal
+// into raw mode.
+
+use crate::controller::{call_button, Transport};
+use crate::logic::{Event, Side, HIGHEST_FLOOR, LOWEST_FLOOR, NUM_FLOORS};
+use crate::snapshot::Snapshot;
+use crate::transitions::{Format, Transition};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+// Keys for each floor, lowest first
+const PANEL_KEYS: [&[u8]; 2] = [b"12345", b"!@#$%"];
+const UP_KEYS: [&[u8]; 2] = [b"qwert", b"QWERT"];
+const DOWN_KEYS: [&[u8]; 2] = [b"asdfg", b"ASDFG"];
+const QUIT_KEY: u8 = 3; // Ctrl-C, which raw mode passes through
+
+const LOG_LINES: usize = 10;
+
+// How often to look for keys while waiting on the hardware
+const POLL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Call(Event),
+    Quit,
+}
+
+pub fn hotkey(key: u8) -> Option<Key> {
+    if key == QUIT_KEY {
+        return Some(Key::Quit);
+    }
+    let calls = [
+        (PANEL_KEYS, Event::Panel as fn(u8, Side) -> Event),
+        (UP_KEYS, Event::Up),
+        (DOWN_KEYS, Event::Down),
+    ];
+    for (keys, call) in calls {
+        for (side, keys) in [Side::Front, Side::Rear].into_iter().zip(keys) {
+            if let Some(n) = keys.iter().position(|&k| k == key) {
+                if n >= NUM_FLOORS as usize {
+                    return None;
+                }
+                return match call(LOWEST_FLOOR + n as u8, side) {
+                    // There's no such button
+                    Event::Up(HIGHEST_FLOOR, _) | Event::Down(LOWEST_FLOOR, _) => None,
+                    event => Some(Key::Call(event)),
+                };
+            }
+        }
+    }
+    None
+}
+
+// The dashboard as lines of text, `width` characters at most
+pub fn screen(snapshot: &Snapshot, log: &VecDeque<String>, width: usize) -> Vec<String> {
+    let mut lines = vec![
+        format!(
+            "state {}   floor {}   load {}",
+            snapshot.state, snapshot.floor, snapshot.load
+        ),
+        String::new(),
+    ];
+    let state = snapshot.state.as_str();
+    let car = match state {
+        "open" | "open-up" | "open-down" => "[  ]",
+        "fault-doors" | "fault-motion" => "[XX]",
+        _ => "[||]",
+    };
+    let indicator = match state {
+        "moving-up" | "open-up" => '^',
+        "moving-down" | "open-down" => 'v',
+        _ => ' ',
+    };
+    for floor in (LOWEST_FLOOR..=HIGHEST_FLOOR).rev() {
+        let shaft = match floor == snapshot.floor {
+            true => format!("{car}{indicator}"),
+            false => "     ".to_string(),
+        };
+        let buttons: Vec<String> = snapshot
+            .calls
+            .iter()
+            .filter(|&&call| match call {
+                Event::Panel(at, _) | Event::Up(at, _) | Event::Down(at, _) => at == floor,
+                _ => false,
+            })
+            .filter_map(|&call| call_button(call))
+            .collect();
+        let line = format!("  {floor} | {shaft}| {}", buttons.join(" "));
+        lines.push(line.trim_end().to_string());
+    }
+    lines.push(String::new());
+    lines.extend(log.iter().cloned());
+    lines.extend((log.len()..LOG_LINES).map(|_| String::new()));
+    lines.push(String::new());
+    lines.push("1-5 car  qwert up  asdfg down  (shifted for rear)  Ctrl-C quits".to_string());
+    lines
+        .into_iter()
+        .map(|line| line.chars().take(width).collect())
+        .collect()
+}
+
+// The terminal in raw mode, put back the way it was when dropped
+struct Terminal {
+    saved: String, // Settings as given by `stty -g`
+    width: usize,
+}
+
+fn stty(args: &[&str]) -> Result<String, String> {
+    // stty works on the terminal it's given as standard input
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("stty: {e}"))?;
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        false => Err(format!(
+            "stty: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+impl Terminal {
+    fn raw() -> Result<Self, String> {
+        let saved = stty(&["-g"])?;
+        let width = stty(&["size"])?
+            .split_whitespace()
+            .nth(1)
+            .and_then(|columns| columns.parse().ok())
+            .filter(|&columns| columns > 0) // Not known, e.g. under `script`
+            .unwrap_or(80);
+        stty(&["raw", "-echo"])?;
+        print!("\x1b[2J\x1b[?25l"); // Clear the screen and hide the cursor
+        Ok(Terminal { saved, width })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+        println!("\x1b[?25h");
+        let _ = std::io::stdout().flush();
+    }
+}
+
+pub struct Dashboard {
+    terminal: Terminal,
+    log: VecDeque<String>,
+}
+
+impl Dashboard {
+    // Take over the terminal.  Keys pressed are passed on to `keys` (see
+    // `Hotkeys`).
+    pub fn start(keys: Sender<u8>) -> Result<Dashboard, String> {
+        let terminal = Terminal::raw()?;
+        thread::spawn(move || {
+            for key in std::io::stdin().lock().bytes() {
+                match key {
+                    Ok(key) if keys.send(key).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        Ok(Dashboard {
+            terminal,
+            log: VecDeque::new(),
+        })
+    }
+
+    pub fn show(&mut self, transition: &Transition, snapshot: &Snapshot) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(Format::Text.line(transition));
+        self.draw(snapshot);
+    }
+
+    pub fn draw(&self, snapshot: &Snapshot) {
+        // Raw mode doesn't turn \n into \r\n; each line is cleared to the end
+        // so that a redraw needs no flicker of clearing the screen first
+        let mut out = String::from("\x1b[H");
+        for line in screen(snapshot, &self.log, self.terminal.width) {
+            out.push_str(&line);
+            out.push_str("\x1b[K\r\n");
+        }
+        out.push_str("\x1b[J");
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(out.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+// A transport that presses Lifty's buttons for the keys pressed while the
+// controller waits.  Put it under any `Recording`, so that a capture shows
+// the presses coming back from Lifty rather than being sent by the
+// controller.
+pub struct Hotkeys<T: Transport> {
+    transport: T,
+    keys: Receiver<u8>,
+    quit: bool,
+}
+
+impl<T: Transport> Hotkeys<T> {
+    pub fn new(transport: T, keys: Receiver<u8>) -> Self {
+        Hotkeys {
+            transport,
+            keys,
+            quit: false,
+        }
+    }
+
+    // Press the buttons for any keys pressed so far
+    fn press(&mut self) {
+        loop {
+            match self.keys.try_recv() {
+                Ok(key) => match hotkey(key) {
+                    Some(Key::Call(call)) => {
+                        if let Some(button) = call_button(call) {
+                            self.transport.send(&button);
+                        }
+                    }
+                    Some(Key::Quit) => self.quit = true,
+                    None => {}
+                },
+                Err(TryRecvError::Empty) => return,
+                // No more keys to come; the dashboard is gone
+                Err(TryRecvError::Disconnected) => {
+                    self.quit = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<T: Transport> Transport for Hotkeys<T> {
+    fn send(&mut self, message: &str) {
+        self.transport.send(message);
+    }
+
+    // Returns None for good once Ctrl-C has been pressed, which ends the
+    // controller's loop
+    fn receive(&mut self, timeout: Option<Duration>) -> Option<String> {
+        let deadline = timeout.map(|timeout| self.now() + timeout);
+        loop {
+            self.press();
+            if self.quit {
+                return None;
+            }
+            let wait = match deadline {
+                Some(deadline) if deadline <= self.now() => return None,
+                Some(deadline) => (deadline - self.now()).min(POLL),
+                None => POLL,
+            };
+            if let Some(message) = self.transport.receive(Some(wait)) {
+                return Some(message);
+            }
+        }
+    }
+
+    fn now(&self) -> Duration {
+        self.transport.now()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::building::BuildingConfig;
+    use crate::controller::LiftyController;
+    use crate::sim::Simulation;
+    use std::sync::mpsc;
+
+    #[test]
+    fn keys() {
+        assert_eq!(hotkey(b'3'), Some(Key::Call(Event::Panel(3, Side::Front))));
+        assert_eq!(hotkey(b'@'), Some(Key::Call(Event::Panel(2, Side::Rear))));
+        assert_eq!(hotkey(b'q'), Some(Key::Call(Event::Up(1, Side::Front))));
+        assert_eq!(hotkey(b'G'), Some(Key::Call(Event::Down(5, Side::Rear))));
+        assert_eq!(hotkey(b't'), None); // No up button on the top floor
+        assert_eq!(hotkey(b'a'), None);
+        assert_eq!(hotkey(b'?'), None);
+        assert_eq!(hotkey(3), Some(Key::Quit));
+    }
+
+    #[test]
+    fn shaft() {
+        let snapshot = Snapshot {
+            state: "open-up".to_string(),
+            floor: 2,
+            load: 40,
+            parking: None,
+            demand: [0; NUM_FLOORS as usize],
+            stop_load: None,
+            empty_stops: 0,
+            calls: vec![
+                Event::Panel(5, Side::Front),
+                Event::Up(2, Side::Rear),
+                Event::Down(4, Side::Front),
+            ],
+        };
+        let log = VecDeque::from(["a transition".to_string()]);
+        let lines = screen(&snapshot, &log, 80);
+        assert_eq!(lines[0], "state open-up   floor 2   load 40");
+        assert_eq!(
+            lines[2..7],
+            [
+                "  5 |      | P5",
+                "  4 |      | D4",
+                "  3 |      |",
+                "  2 | [  ]^| RU2",
+                "  1 |      |",
+            ]
+        );
+        assert_eq!(lines[8], "a transition");
+        assert_eq!(lines.len(), 8 + LOG_LINES + 2);
+        assert!(screen(&snapshot, &log, 10)
+            .iter()
+            .all(|line| line.len() <= 10));
+    }
+
+    #[test]
+    fn keys_press_buttons() {
+        let config = BuildingConfig::new();
+        let (keys, received) = mpsc::channel();
+        let simulation = Simulation::new(&config, &[], 1_000_000);
+        let mut controller = LiftyController::with_transport(Hotkeys::new(simulation, received));
+        keys.send(b'4').unwrap();
+        assert_eq!(controller.receive(), Some(Event::Panel(4, Side::Front)));
+        keys.send(QUIT_KEY).unwrap();
+        assert_eq!(controller.receive(), None);
+    }
+}
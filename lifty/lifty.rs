@@ -17,11 +17,14 @@ system with a single elevator car.  I have the following hardware
 features:
 
   - A motor that makes the car go up and down.
-  - A door that can open and close.
-  - A panel of 5 buttons inside the elevator car.
-  - Up request buttons on floors 1-4.
-  - Down request buttons on floors 2-5.
+  - Front and rear doors that can open and close.
+  - A panel of 5 buttons by each door inside the elevator car.
+  - Up request buttons on floors 1-4, at the front and rear landings.
+  - Down request buttons on floors 2-5, at the front and rear landings.
   - A direction indicator light on each floor.
+  - A load sensor that weighs the car (percent of rated load).
+  - An overload warning buzzer in the car.
+  - A card reader in the car.
 
 Residents of the building interact with me by pressing buttons.
 This is done by typing the following commands at the keyboard:
@@ -29,6 +32,9 @@ This is done by typing the following commands at the keyboard:
   Pn - Press button for floor n in the elevator car
   Un - Press up button on floor n
   Dn - Press down button on floor n
+  RPn, RUn, RDn - The same buttons on the rear side
+  Ln - Set the load in the car to n percent of rated load
+  Kn - Present card number n at the card reader
 
 Sadly, I don't have any brains of my own to know what to do
 when a button is pressed.  However, I can interact with a
@@ -42,24 +48,52 @@ I will send the following event messages to the controller:
   Pn - Panel button for floor n was pressed
   Un - Up button on floor n was pressed
   Dn - Down button floor n was pressed
+  RPn, RUn, RDn - The same for the buttons on the rear side
   An - Approaching floor n (still in motion)
   Sn - Stopped at floor n (safe to open door)
   On - Door open on floor n (doors have fully opened)
   Cn - Door closed on floor n (now safe to move)
+  Ln - Load in the car changed to n percent of rated load
+  Kn - Card number n was presented at the card reader
+  Q ... - Answer to a status query, such as
+
+    Q floor=3 motor=off door=open doors=front stopping=no indicator=up@3 lit=P5,RU3 crashed=no
+
+For testing how a controller copes when things go wrong, faults
+can be typed in too (or piped in from a script):
+
+  !DROP      - Lose the next event on its way to the controller
+  !DUP       - Send the next event twice
+  !DELAYn    - Hold the next event back for n ticks
+  !LOSE      - Lose the next command from the controller
+  !REPEAT    - Carry out the next command from the controller twice
+  !STUCK     - Jam the doors (no more On or Cn events until a reset)
+  !NOSTART   - Ignore the next command to start the motor
+  !NOAPPROACH - Skip the next approach event
+  !RESET     - Reset spontaneously
+
+Set FAULT_RATE below to have faults (and stray button presses)
+happen at random.
 
 I understand the following commands from the controller
 
   MU  - Start moving up
   MD  - Start moving down
   S   - Stop at the next floor (generates Sn event when stopped)
-  DO  - Open door (will generate On event when done)
-  DC  - Close the door (will generate Cn event when done)
+  DO  - Open the front door (will generate On event when done)
+  DOR - Open the rear door
+  DOB - Open both doors
+  DC  - Close the door(s) (will generate Cn event when done)
   CPn - Clear panel button n
   CUn - Clear up button n
   CDn - Clear down button n
+  CRPn, CRUn, CRDn - Clear the rear panel, up and down buttons
   IUn - Set indicator light on floor n to "up"
   IDn - Set indicator light on floor n to "down"
   CIn - Clear the indicator light on floor n
+  OW  - Sound the overload warning
+  CW  - Clear the overload warning
+  Q   - Report my status (even when crashed)
   R   - Reset
 
 Although I don't have any brains, I am programmed with some
@@ -88,6 +122,26 @@ const TICKS_FOR_DOOR: usize = 20;
 const APPROACH_TICKS: usize = 10;
 const TICK_INTERVAL: u64 = 100;
 
+// Turn this on to move the car like a real one.  It speeds up and slows
+// down smoothly (within the acceleration and jerk limits), runs at the
+// rated speed when there's room, and the approach event comes at the last
+// moment it could still stop for the floor instead of APPROACH_TICKS out.
+const PHYSICS: bool = false;
+const RATED_SPEED: f64 = 1.0; // metres per second
+const ACCELERATION: f64 = 0.8; // metres per second squared
+const JERK: f64 = 1.0; // metres per second cubed
+const FLOOR_HEIGHTS: [f64; 4] = [3.5, 3.5, 3.5, 3.5]; // metres between floors
+const LEVELLING_SPEED: f64 = 0.1; // creeping the last bit into a floor
+const EPSILON: f64 = 1e-6;
+
+// Chance per clock tick of a random fault.  Zero for a well behaved
+// elevator.
+const FAULT_RATE: f64 = 0.0;
+const FAULT_SEED: u64 = 1;
+
+// Heaviest load that can be reported (percent of rated load)
+const MAX_LOAD: u8 = 200;
+
 // Turn this on if you want Lifty to be super picky or
 // if you're looking for ways to deduct grading points.
 const PEDANTIC: bool = false;
@@ -123,12 +177,27 @@ struct Elevator {
     pub panel_buttons: [bool; 5], // Buttons in the car
     pub up_buttons: [bool; 5],    // Up buttons in the building
     pub down_buttons: [bool; 5],  // Down buttons in the building
+    pub rear_panel_buttons: [bool; 5], // The same by the rear door
+    pub rear_up_buttons: [bool; 5],
+    pub rear_down_buttons: [bool; 5],
     pub indicator: Indicator,     // Indicator light status
     pub indicator_floor: usize,
     pub clock: usize,
     pub motor: Motor,
     pub door: Door,
+    pub front_door: bool, // Which doors are opening, open or closing
+    pub rear_door: bool,
     pub stopping: bool,
+    pub position: f64, // Metres above floor 1 (PHYSICS only)
+    pub speed: f64,
+    pub acceleration: f64,
+    pub stop_floor: Option<usize>, // Floor the car is braking for
+    pub approached: Option<usize>, // Last floor announced (or too late to)
+    pub stuck_door: bool,          // Faults
+    pub motor_wont_start: bool,
+    pub miss_approach: bool,
+    pub load: u8,                  // Percent of rated load
+    pub overload_warning: bool,
     pub crashed: bool,
 }
 
@@ -139,12 +208,27 @@ impl Elevator {
             panel_buttons: [false, false, false, false, false],
             up_buttons: [false, false, false, false, false],
             down_buttons: [false, false, false, false, false],
+            rear_panel_buttons: [false, false, false, false, false],
+            rear_up_buttons: [false, false, false, false, false],
+            rear_down_buttons: [false, false, false, false, false],
             indicator: Indicator::Off,
             indicator_floor: 1,
             clock: 0,
             motor: Motor::Off,
             door: Door::Closed,
+            front_door: true,
+            rear_door: false,
             stopping: false,
+            position: 0.0,
+            speed: 0.0,
+            acceleration: 0.0,
+            stop_floor: None,
+            approached: None,
+            stuck_door: false,
+            motor_wont_start: false,
+            miss_approach: false,
+            load: 0,
+            overload_warning: false,
             crashed: false,
         }
     }
@@ -154,12 +238,27 @@ impl Elevator {
         self.panel_buttons = [false, false, false, false, false];
         self.up_buttons = [false, false, false, false, false];
         self.down_buttons = [false, false, false, false, false];
+        self.rear_panel_buttons = [false, false, false, false, false];
+        self.rear_up_buttons = [false, false, false, false, false];
+        self.rear_down_buttons = [false, false, false, false, false];
         self.indicator = Indicator::Off;
         self.indicator_floor = 1;
         self.clock = 0;
         self.motor = Motor::Off;
         self.door = Door::Closed;
+        self.front_door = true;
+        self.rear_door = false;
         self.stopping = false;
+        self.position = 0.0;
+        self.speed = 0.0;
+        self.acceleration = 0.0;
+        self.stop_floor = None;
+        self.approached = None;
+        self.stuck_door = false;
+        self.motor_wont_start = false;
+        self.miss_approach = false;
+        self.load = 0;
+        self.overload_warning = false;
         self.crashed = false;
     }
 
@@ -168,31 +267,61 @@ impl Elevator {
         self.crashed = true;
     }
 
-    fn as_string(&self) -> String {
-        let mut ps = String::from("P:");
-        for (n, floor) in self.panel_buttons.iter().enumerate() {
-            if *floor {
-                ps.push(char::from_u32(49 + n as u32).unwrap());
-            } else {
-                ps.push('-');
-            }
-        }
-        let mut us = String::from("U:");
-        for (n, floor) in self.up_buttons.iter().enumerate() {
-            if *floor {
-                us.push(char::from_u32(49 + n as u32).unwrap());
-            } else {
-                us.push('-');
-            }
-        }
-        let mut ds = String::from("D:");
-        for (n, floor) in self.down_buttons.iter().enumerate() {
-            if *floor {
-                ds.push(char::from_u32(49 + n as u32).unwrap());
-            } else {
-                ds.push('-');
+    // Answer to a status query: one line of settings, with the lit buttons
+    // named the way they're pressed
+    fn status(&self) -> String {
+        let rows = [
+            ("P", &self.panel_buttons),
+            ("U", &self.up_buttons),
+            ("D", &self.down_buttons),
+            ("RP", &self.rear_panel_buttons),
+            ("RU", &self.rear_up_buttons),
+            ("RD", &self.rear_down_buttons),
+        ];
+        let mut lit = Vec::new();
+        for (name, buttons) in rows {
+            for (n, &on) in buttons.iter().enumerate() {
+                if on {
+                    lit.push(format!("{name}{}", n + 1));
+                }
             }
         }
+        let motor = match self.motor {
+            Motor::Up => "up",
+            Motor::Down => "down",
+            Motor::Off => "off",
+        };
+        let door = match self.door {
+            Door::Opening => "opening",
+            Door::Open => "open",
+            Door::Closing => "closing",
+            Door::Closed => "closed",
+        };
+        let indicator = match self.indicator {
+            Indicator::Up => "up",
+            Indicator::Down => "down",
+            Indicator::Off => "off",
+        };
+        let doors = match (self.front_door, self.rear_door) {
+            (true, true) => "both",
+            (false, true) => "rear",
+            _ => "front",
+        };
+        let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+        format!(
+            "Q floor={} motor={motor} door={door} doors={doors} stopping={} indicator={indicator}@{} lit={} crashed={}",
+            self.floor,
+            yes_no(self.stopping),
+            self.indicator_floor,
+            lit.join(","),
+            yes_no(self.crashed)
+        )
+    }
+
+    fn as_string(&self) -> String {
+        let ps = buttons_string("P:", &self.panel_buttons, &self.rear_panel_buttons);
+        let us = buttons_string("U:", &self.up_buttons, &self.rear_up_buttons);
+        let ds = buttons_string("D:", &self.down_buttons, &self.rear_down_buttons);
         let indicator = if self.indicator_floor == self.floor {
             match self.indicator {
                 Indicator::Up => "^^",
@@ -204,7 +333,7 @@ impl Elevator {
         };
         let status = if self.crashed {
             "CRASH"
-        } else if self.stopping && self.clock >= (TICKS_PER_FLOOR - APPROACH_TICKS) {
+        } else if self.stopping && (PHYSICS || self.clock >= (TICKS_PER_FLOOR - APPROACH_TICKS)) {
             "STOPPING"
         } else if self.motor == Motor::Up {
             "UP"
@@ -221,9 +350,16 @@ impl Elevator {
         } else {
             panic!("Can't determine status")
         };
+        // Door activity at the rear is marked after the status
+        let status = match (self.door != Door::Closed && self.rear_door, self.front_door) {
+            (true, true) => format!("{status} FR"),
+            (true, false) => format!("{status} R"),
+            (false, _) => status.to_string(),
+        };
+        let warning = if self.overload_warning { "!!" } else { "  " };
         format!(
-            "[ FLOOR {} | {status:8} {indicator} | {ps} | {us} | {ds} ]",
-            self.floor
+            "[ FLOOR {} | {status:8} {indicator} | {ps} | {us} | {ds} | LOAD {:3}%{warning} ]",
+            self.floor, self.load
         )
     }
 
@@ -263,6 +399,42 @@ impl Elevator {
         }
     }
 
+    fn set_rear_panel_button(&mut self, floor: usize) {
+        self.rear_panel_buttons[floor - 1] = true;
+    }
+
+    fn clear_rear_panel_button(&mut self, floor: usize) {
+        if PEDANTIC && !self.rear_panel_buttons[floor - 1] {
+            self.crash("rear panel button not previously set");
+        } else {
+            self.rear_panel_buttons[floor - 1] = false;
+        }
+    }
+
+    fn set_rear_up_button(&mut self, floor: usize) {
+        self.rear_up_buttons[floor - 1] = true;
+    }
+
+    fn clear_rear_up_button(&mut self, floor: usize) {
+        if PEDANTIC && !self.rear_up_buttons[floor - 1] {
+            self.crash("rear up button not previously set");
+        } else {
+            self.rear_up_buttons[floor - 1] = false;
+        }
+    }
+
+    fn set_rear_down_button(&mut self, floor: usize) {
+        self.rear_down_buttons[floor - 1] = true;
+    }
+
+    fn clear_rear_down_button(&mut self, floor: usize) {
+        if PEDANTIC && !self.rear_down_buttons[floor - 1] {
+            self.crash("rear down button not previously set");
+        } else {
+            self.rear_down_buttons[floor - 1] = false;
+        }
+    }
+
     fn set_indicator(&mut self, floor: usize, status: Indicator) {
         if self.indicator != Indicator::Off && status != Indicator::Off {
             self.crash("direction indicator already illuminated");
@@ -274,11 +446,24 @@ impl Elevator {
         }
     }
 
+    fn set_load(&mut self, load: u8) -> Option<String> {
+        if load > MAX_LOAD {
+            self.crash("load beyond sensor range");
+            return None;
+        }
+        self.load = load;
+        Some(format!("L{load}"))
+    }
+
     fn set_motor(&mut self, status: Motor) {
         if self.door != Door::Closed {
             self.crash("motor command received while doors open");
             return;
         }
+        if status != Motor::Off && self.load > 100 {
+            self.crash("motor command received while overloaded");
+            return;
+        }
         if self.motor == Motor::Up && status == Motor::Down {
             self.crash("violent direction switch (up->down)");
             return;
@@ -287,7 +472,9 @@ impl Elevator {
             self.crash("violent direction switch (down->up)");
             return;
         }
-        if self.motor != status {
+        if self.motor != status && status != Motor::Off && self.motor_wont_start {
+            self.motor_wont_start = false;
+        } else if self.motor != status {
             self.motor = status;
             self.clock = 0;
         } else if status == Motor::Up {
@@ -322,11 +509,20 @@ impl Elevator {
         self.clock = 0;
     }
 
+    fn open_doors(&mut self, front: bool, rear: bool) {
+        self.set_door(Door::Opening);
+        self.front_door = front;
+        self.rear_door = rear;
+    }
+
     fn handle_command(&mut self, cmd: &str) -> Option<String> {
         if cmd == "R" {
             self.reset();
             return None;
         }
+        if cmd == "Q" {
+            return Some(self.status());
+        }
         if self.crashed {
             return None;
         }
@@ -340,7 +536,19 @@ impl Elevator {
                 self.set_up_button(cmd[1..].parse().unwrap());
                 Some(cmd.to_string())
             }
-            "U5" | "CU5" => {
+            "RP1" | "RP2" | "RP3" | "RP4" | "RP5" => {
+                self.set_rear_panel_button(cmd[2..].parse().unwrap());
+                Some(cmd.to_string())
+            }
+            "RU1" | "RU2" | "RU3" | "RU4" => {
+                self.set_rear_up_button(cmd[2..].parse().unwrap());
+                Some(cmd.to_string())
+            }
+            "RD2" | "RD3" | "RD4" | "RD5" => {
+                self.set_rear_down_button(cmd[2..].parse().unwrap());
+                Some(cmd.to_string())
+            }
+            "U5" | "CU5" | "RU5" | "CRU5" => {
                 self.crash("No up button on top floor");
                 None
             }
@@ -348,7 +556,7 @@ impl Elevator {
                 self.set_down_button(cmd[1..].parse().unwrap());
                 Some(cmd.to_string())
             }
-            "D1" | "CD1" => {
+            "D1" | "CD1" | "RD1" | "CRD1" => {
                 self.crash("No down button on bottom floor");
                 None
             }
@@ -365,6 +573,18 @@ impl Elevator {
                 self.clear_down_button(cmd[2..].parse().unwrap());
                 None
             }
+            "CRP1" | "CRP2" | "CRP3" | "CRP4" | "CRP5" => {
+                self.clear_rear_panel_button(cmd[3..].parse().unwrap());
+                None
+            }
+            "CRU1" | "CRU2" | "CRU3" | "CRU4" => {
+                self.clear_rear_up_button(cmd[3..].parse().unwrap());
+                None
+            }
+            "CRD2" | "CRD3" | "CRD4" | "CRD5" => {
+                self.clear_rear_down_button(cmd[3..].parse().unwrap());
+                None
+            }
             // Direction indicator lights
             "IU1" | "IU2" | "IU3" | "IU4" => {
                 self.set_indicator(cmd[2..].parse().unwrap(), Indicator::Up);
@@ -398,6 +618,17 @@ impl Elevator {
             "S" => {
                 if self.stopping {
                     self.crash("Already made a request to stop");
+                } else if self.motor != Motor::Off && PHYSICS {
+                    // Stop at the floor just announced, if there's still room
+                    // to.  Otherwise the car carries on, as it does without
+                    // physics.
+                    let stopping = stopping_distance(self.speed, self.acceleration);
+                    self.stop_floor = self
+                        .floors_ahead()
+                        .into_iter()
+                        .find(|&floor| self.distance_to(floor) + EPSILON >= stopping)
+                        .filter(|&floor| Some(floor) == self.approached);
+                    self.stopping = self.stop_floor.is_some();
                 } else if self.motor != Motor::Off {
                     // If we can safely stop we will.
                     if self.clock <= TICKS_PER_FLOOR - APPROACH_TICKS {
@@ -410,13 +641,35 @@ impl Elevator {
             }
             // Door commands (from control)
             "DO" => {
-                self.set_door(Door::Opening);
+                self.open_doors(true, false);
+                None
+            }
+            "DOR" => {
+                self.open_doors(false, true);
+                None
+            }
+            "DOB" => {
+                self.open_doors(true, true);
                 None
             }
             "DC" => {
                 self.set_door(Door::Closing);
                 None
             }
+            // Load sensor and overload warning
+            _ if cmd.starts_with('L') && cmd[1..].parse::<u8>().is_ok() => {
+                self.set_load(cmd[1..].parse().unwrap())
+            }
+            // Card reader
+            _ if cmd.starts_with('K') && cmd[1..].parse::<u32>().is_ok() => Some(cmd.to_string()),
+            "OW" => {
+                self.overload_warning = true;
+                None
+            }
+            "CW" => {
+                self.overload_warning = false;
+                None
+            }
             // Clock
             "T" => self.handle_tick(),
             _ => {
@@ -426,13 +679,123 @@ impl Elevator {
         }
     }
 
+    fn approach(&mut self, floor: usize) -> Option<String> {
+        if self.miss_approach {
+            self.miss_approach = false;
+            None
+        } else {
+            Some(format!("A{floor}"))
+        }
+    }
+
+    // Distance still to go to a floor in the direction of travel.  Negative
+    // once it's been passed.
+    fn distance_to(&self, floor: usize) -> f64 {
+        let distance = level(floor) - self.position;
+        if self.motor == Motor::Down {
+            -distance
+        } else {
+            distance
+        }
+    }
+
+    // Floors still ahead of the car, nearest first
+    fn floors_ahead(&self) -> Vec<usize> {
+        let floors: Vec<usize> = if self.motor == Motor::Down {
+            (1..=5).rev().collect()
+        } else {
+            (1..=5).collect()
+        };
+        floors
+            .into_iter()
+            .filter(|&floor| self.distance_to(floor) > EPSILON)
+            .collect()
+    }
+
+    // Move the car for one tick
+    fn move_car(&mut self, dt: f64) -> bool {
+        let remaining = self.stop_floor.map(|floor| self.distance_to(floor).max(0.0));
+        let wanted = match remaining {
+            Some(distance) => braking_speed(distance).clamp(LEVELLING_SPEED, RATED_SPEED),
+            None => RATED_SPEED,
+        };
+        let gap = wanted - self.speed;
+        let wanted_acceleration = gap.signum() * ACCELERATION.min((2.0 * JERK * gap.abs()).sqrt());
+        let change = JERK * dt;
+        self.acceleration += (wanted_acceleration - self.acceleration).clamp(-change, change);
+        self.speed = (self.speed + self.acceleration * dt).max(0.0);
+        if self.speed > RATED_SPEED {
+            self.speed = RATED_SPEED;
+            self.acceleration = 0.0;
+        }
+
+        let travel = self.speed * dt;
+        if let (Some(floor), Some(distance)) = (self.stop_floor, remaining) {
+            if travel >= distance {
+                self.position = level(floor);
+                self.speed = 0.0;
+                self.acceleration = 0.0;
+                return true;
+            }
+        }
+        self.position += if self.motor == Motor::Down { -travel } else { travel };
+        false
+    }
+
+    fn physics_tick(&mut self) -> Option<String> {
+        let dt = TICK_INTERVAL as f64 / 1000.0;
+        let stopped = self.move_car(dt);
+        // The floor the car is at or has most recently passed
+        let mut passed = (1..=5).filter(|&floor| self.distance_to(floor) <= EPSILON);
+        let floor = if self.motor == Motor::Down {
+            passed.next()
+        } else {
+            passed.next_back()
+        };
+        self.floor = floor.unwrap_or(self.floor);
+        if self.position > level(5) + EPSILON {
+            self.crash("Hit the roof!");
+        } else if self.position < -EPSILON {
+            self.crash("Hit the ground!");
+        } else if stopped {
+            self.set_motor(Motor::Off);
+            self.stopping = false;
+            self.stop_floor = None;
+            self.approached = None;
+            return Some(format!("S{}", self.floor));
+        } else if !self.stopping {
+            // Announce the next floor on the last tick there's still room to
+            // stop for it.  If it's already too late it's passed over quietly.
+            let floor = *self.floors_ahead().first()?;
+            if Some(floor) == self.approached {
+                return None;
+            }
+            let distance = self.distance_to(floor);
+            let stopping = stopping_distance(self.speed, self.acceleration);
+            let (position, speed, acceleration) = (self.position, self.speed, self.acceleration);
+            self.move_car(dt);
+            let late = stopping_distance(self.speed, self.acceleration) > self.distance_to(floor);
+            (self.position, self.speed, self.acceleration) = (position, speed, acceleration);
+            if late {
+                self.approached = Some(floor);
+                if stopping <= distance + EPSILON {
+                    return self.approach(floor);
+                }
+            }
+        }
+        None
+    }
+
     fn handle_tick(&mut self) -> Option<String> {
         self.clock += 1;
+        if PHYSICS && self.motor != Motor::Off {
+            return self.physics_tick();
+        }
         if self.motor == Motor::Up {
             if self.floor >= 5 {
                 self.crash("Hit the roof!");
             } else if self.clock == (TICKS_PER_FLOOR - APPROACH_TICKS) {
-                return Some(format!("A{}", self.floor + 1));
+                return self.approach(self.floor + 1);
             } else if self.clock >= TICKS_PER_FLOOR {
                 self.floor += 1;
                 self.clock = 0;
@@ -446,7 +809,7 @@ impl Elevator {
             if self.floor <= 1 {
                 self.crash("Hit the ground!");
             } else if self.clock == (TICKS_PER_FLOOR - APPROACH_TICKS) {
-                return Some(format!("A{}", self.floor - 1));
+                return self.approach(self.floor - 1);
             } else if self.clock >= TICKS_PER_FLOOR {
                 self.floor -= 1;
                 self.clock = 0;
@@ -456,6 +819,8 @@ impl Elevator {
                     return Some(format!("S{}", self.floor));
                 }
             }
+        } else if self.stuck_door {
+            // Jammed; the doors stay where they are
         } else if self.door == Door::Closing {
             if self.clock > TICKS_FOR_DOOR {
                 self.set_door(Door::Closed);
@@ -471,6 +836,154 @@ impl Elevator {
     }
 }
 
+// Height of a floor above floor 1
+fn level(floor: usize) -> f64 {
+    FLOOR_HEIGHTS[..floor - 1].iter().sum()
+}
+
+// Distance the car needs to come to a smooth stop.  Any acceleration has to
+// ease off before the brakes can be applied.
+fn stopping_distance(speed: f64, acceleration: f64) -> f64 {
+    let (a, j) = (ACCELERATION, JERK);
+    let (mut distance, mut speed) = (0.0, speed);
+    if acceleration > 0.0 {
+        let t = acceleration / j;
+        distance = speed * t + acceleration * t * t / 2.0 - j * t * t * t / 6.0;
+        speed += acceleration * acceleration / (2.0 * j);
+    }
+    if speed >= a * a / j {
+        distance + speed * speed / (2.0 * a) + speed * a / (2.0 * j)
+    } else {
+        distance + speed * (speed / j).sqrt()
+    }
+}
+
+// The fastest the car can go and still stop within a distance
+fn braking_speed(distance: f64) -> f64 {
+    let (a, j) = (ACCELERATION, JERK);
+    let speed = (distance * distance * j).cbrt();
+    if speed < a * a / j {
+        speed
+    } else {
+        (-a * a / j + (a.powi(4) / (j * j) + 8.0 * a * distance).sqrt()) / 2.0
+    }
+}
+
+// Faults on the network between me and the control program
+struct Network {
+    drop_events: usize,
+    duplicate_events: usize,
+    delay_event: Option<usize>,
+    delayed: Vec<(usize, String)>, // Ticks to go, event
+    drop_commands: usize,
+    repeat_commands: usize,
+    rng: u64,
+}
+
+impl Network {
+    fn new() -> Network {
+        Network {
+            drop_events: 0,
+            duplicate_events: 0,
+            delay_event: None,
+            delayed: Vec::new(),
+            drop_commands: 0,
+            repeat_commands: 0,
+            rng: FAULT_SEED,
+        }
+    }
+
+    // Events that go out now in place of `event`
+    fn deliver(&mut self, event: String) -> Vec<String> {
+        if self.drop_events > 0 {
+            self.drop_events -= 1;
+            Vec::new()
+        } else if let Some(ticks) = self.delay_event.take() {
+            self.delayed.push((ticks, event));
+            Vec::new()
+        } else if self.duplicate_events > 0 {
+            self.duplicate_events -= 1;
+            vec![event.clone(), event]
+        } else {
+            vec![event]
+        }
+    }
+
+    // Held back events that are due out on this tick
+    fn tick(&mut self) -> Vec<String> {
+        let mut due = Vec::new();
+        self.delayed.retain_mut(|(ticks, event)| {
+            *ticks = ticks.saturating_sub(1);
+            if *ticks == 0 {
+                due.push(event.clone());
+            }
+            *ticks > 0
+        });
+        due
+    }
+
+    // How many times the next command from the controller is carried out
+    fn copies(&mut self) -> usize {
+        if self.drop_commands > 0 {
+            self.drop_commands -= 1;
+            0
+        } else if self.repeat_commands > 0 {
+            self.repeat_commands -= 1;
+            2
+        } else {
+            1
+        }
+    }
+
+    // Random number in 0..n (xorshift)
+    fn random(&mut self, n: u64) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng % n
+    }
+
+    // A fault picked at random, or a stray button press
+    fn random_fault(&mut self) -> String {
+        let floor = 1 + self.random(5);
+        match self.random(12) {
+            0 => String::from("!DROP"),
+            1 => String::from("!DUP"),
+            2 => format!("!DELAY{}", 1 + self.random(20)),
+            3 => String::from("!LOSE"),
+            4 => String::from("!REPEAT"),
+            5 => String::from("!STUCK"),
+            6 => String::from("!NOSTART"),
+            7 => String::from("!NOAPPROACH"),
+            8 => String::from("!RESET"),
+            9 => format!("P{floor}"),
+            10 => format!("U{}", floor.min(4)),
+            _ => format!("D{}", floor.max(2)),
+        }
+    }
+}
+
+// Carry out a fault command (without the '!').  Returns false if there's
+// no such fault.
+fn inject_fault(elev: &mut Elevator, network: &mut Network, fault: &str) -> bool {
+    match fault {
+        "DROP" => network.drop_events += 1,
+        "DUP" => network.duplicate_events += 1,
+        "LOSE" => network.drop_commands += 1,
+        "REPEAT" => network.repeat_commands += 1,
+        "STUCK" => elev.stuck_door = true,
+        "NOSTART" => elev.motor_wont_start = true,
+        "NOAPPROACH" => elev.miss_approach = true,
+        "RESET" => elev.reset(),
+        _ => match fault.strip_prefix("DELAY").map(|n| n.parse()) {
+            Some(Ok(ticks)) if ticks > 0 => network.delay_event = Some(ticks),
+            _ => return false,
+        },
+    }
+    println!("\nFAULT! : {fault}");
+    true
+}
+
 // Runtime environment for the simulator
 
 use std::io;
@@ -485,6 +998,21 @@ enum Command {
     Internal(String),
 }
 
+// A row of buttons for the status line.  A lit button shows its floor
+// number, or 'r' if only the rear one is lit and '*' for both.
+fn buttons_string(label: &str, front: &[bool; 5], rear: &[bool; 5]) -> String {
+    let mut s = String::from(label);
+    for (n, (&front, &rear)) in front.iter().zip(rear).enumerate() {
+        s.push(match (front, rear) {
+            (true, true) => '*',
+            (true, false) => char::from_u32(49 + n as u32).unwrap(),
+            (false, true) => 'r',
+            (false, false) => '-',
+        });
+    }
+    s
+}
+
 fn read_stdin(tx: Sender<Command>) -> ! {
     loop {
         let mut buffer = String::new();
@@ -529,6 +1057,7 @@ fn spawn_threads() -> Receiver<Command> {
 
 fn main() {
     let mut elev = Elevator::new();
+    let mut network = Network::new();
     let command_channel = spawn_threads();
     let mut last = String::new();
     let out_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
@@ -537,7 +1066,10 @@ fn main() {
     println!("I'm just hardware, but you can press my buttons\n(type below and hit return):\n");
     println!("    Pn  - Floor n button on panel inside car");
     println!("    Un  - Up button on floor n");
-    println!("    Dn  - Down button on floor n\n");
+    println!("    Dn  - Down button on floor n");
+    println!("    RPn, RUn, RDn - The same buttons on the rear side");
+    println!("    Ln  - Load in the car is n percent of rated load");
+    println!("    Kn  - Card number n presented at the card reader\n");
     println!("If something goes wrong, I'll crash and you'll have to call");
     println!("maintenance to restart the elevator control program.\n");
 
@@ -554,6 +1086,7 @@ fn main() {
         }
         match command_channel.recv() {
             Ok(recvcmd) => {
+                let is_user_input = matches!(recvcmd, Command::UserInput(_));
                 let cmd = match recvcmd {
                     Command::UserInput(cmd) => {
                         print_newline = false;
@@ -571,13 +1104,35 @@ fn main() {
                         cmd
                     }
                 };
-                if cmd.len() > 0 {
-                    if let Some(outcmd) = elev.handle_command(&cmd) {
-                        out_socket
-                            .send_to(outcmd.as_bytes(), CONTROL_ADDRESS)
-                            .expect("couldn't send data");
+                let mut cmds = vec![cmd.clone()];
+                if cmd == "T" {
+                    // Now and then something goes wrong by itself
+                    if FAULT_RATE > 0.0 && network.random(1_000_000) < (FAULT_RATE * 1e6) as u64 {
+                        cmds.push(network.random_fault());
+                    }
+                } else if !is_user_input {
+                    cmds = vec![cmd.clone(); network.copies()];
+                }
+                let mut events = Vec::new();
+                for cmd in cmds {
+                    if let Some(fault) = cmd.strip_prefix('!') {
+                        if !inject_fault(&mut elev, &mut network, fault) {
+                            elev.crash("Unrecognized fault");
+                        }
+                    } else if cmd.len() > 0 {
+                        if let Some(event) = elev.handle_command(&cmd) {
+                            events.extend(network.deliver(event));
+                        }
                     }
                 }
+                if cmd == "T" {
+                    events.extend(network.tick());
+                }
+                for outcmd in events {
+                    out_socket
+                        .send_to(outcmd.as_bytes(), CONTROL_ADDRESS)
+                        .expect("couldn't send data");
+                }
             }
             Err(e) => {
                 println!("{:?}", e);